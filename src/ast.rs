@@ -1,222 +1,775 @@
+use crate::builtins;
+use crate::diagnostics::Diagnostic;
+use crate::environment::Environment;
+use crate::error::EvalError;
+use crate::ir::{CodeBuilder, Instr, Op, Operand};
+use crate::span::Span;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 
 #[derive(Debug, Clone)]
 pub enum ASTNode {
-    Number(f64),
-    Identifier(String, usize),
+    Number(f64, Span),
+    Identifier(String, usize, Span),
     BinaryOp {
         op: char,
         left: Box<ASTNode>,
         right: Box<ASTNode>,
+        span: Span,
     },
     UnaryOp {
         op: String,
         operand: Box<ASTNode>,
+        span: Span,
     },
+    Call {
+        name: String,
+        args: Vec<ASTNode>,
+        span: Span,
+    },
+    /// `f(x, y) = body`; only valid as a top-level statement.
+    FunctionDef {
+        name: String,
+        params: Vec<(String, usize)>,
+        body: Box<ASTNode>,
+        span: Span,
+    },
+    /// Placeholder for input that failed to parse; later phases skip it.
+    Error(Span),
+}
+
+/// Root of the syntax tree: statements separated by `;` or newlines.
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub statements: Vec<ASTNode>,
+}
+
+/// User-defined functions by name: parameters and body.
+type FunctionTable<'a> = HashMap<&'a str, (&'a [(String, usize)], &'a ASTNode)>;
+
+/// Names visible while checking one statement.
+struct Scope<'a> {
+    /// Arity of every user-defined function.
+    functions: &'a HashMap<&'a str, usize>,
+    /// Name and parameters of the function whose body is being checked.
+    function: Option<(&'a str, &'a [(String, usize)])>,
 }
 
 impl fmt::Display for ASTNode {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            ASTNode::Number(n) => write!(f, "{}", n),
-            ASTNode::Identifier(_name, idx) => write!(f, "id{}", idx),
-            ASTNode::BinaryOp { op, left, right } => {
+            ASTNode::Number(n, _) => write!(f, "{}", n),
+            ASTNode::Identifier(_name, idx, _) => write!(f, "id{}", idx),
+            ASTNode::BinaryOp {
+                op, left, right, ..
+            } => {
                 write!(f, "({} {} {})", left, op, right)
             }
-            ASTNode::UnaryOp { op, operand } => {
-                write!(f, "{}({})", op, operand)
+            ASTNode::UnaryOp { op, operand, .. } => match op.as_str() {
+                "-" | "+" => write!(f, "({}{})", op, operand),
+                _ => write!(f, "{}({})", op, operand),
+            },
+            ASTNode::Call { name, args, .. } => {
+                let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+                write!(f, "{}({})", name, args.join(", "))
             }
+            ASTNode::FunctionDef {
+                name, params, body, ..
+            } => {
+                let params: Vec<String> =
+                    params.iter().map(|(_, idx)| format!("id{}", idx)).collect();
+                write!(f, "fn {}({}) = {}", name, params.join(", "), body)
+            }
+            ASTNode::Error(_) => write!(f, "<error>"),
         }
     }
 }
 
 impl ASTNode {
-    pub fn has_variables(&self) -> bool {
+    /// Source range this node was parsed from.
+    pub fn span(&self) -> Span {
         match self {
-            ASTNode::Identifier(_, _) => true,
-            ASTNode::Number(_) => false,
-            ASTNode::BinaryOp { left, right, .. } => left.has_variables() || right.has_variables(),
-            ASTNode::UnaryOp { operand, .. } => operand.has_variables(),
+            ASTNode::Number(_, span)
+            | ASTNode::Identifier(_, _, span)
+            | ASTNode::BinaryOp { span, .. }
+            | ASTNode::UnaryOp { span, .. }
+            | ASTNode::Call { span, .. }
+            | ASTNode::FunctionDef { span, .. }
+            | ASTNode::Error(span) => *span,
         }
     }
 
-    pub fn evaluate(&self) -> f64 {
+    fn span_mut(&mut self) -> &mut Span {
         match self {
-            ASTNode::Number(n) => *n,
-            ASTNode::Identifier(_, _) => 0.0,
-            ASTNode::BinaryOp { op, left, right } => {
-                let l = left.evaluate();
-                let r = right.evaluate();
-                match op {
+            ASTNode::Number(_, span)
+            | ASTNode::Identifier(_, _, span)
+            | ASTNode::BinaryOp { span, .. }
+            | ASTNode::UnaryOp { span, .. }
+            | ASTNode::Call { span, .. }
+            | ASTNode::FunctionDef { span, .. }
+            | ASTNode::Error(span) => span,
+        }
+    }
+
+    /// The node with its span widened to `span`, as for the parentheses
+    /// around an expression.
+    pub fn with_span(mut self, span: Span) -> ASTNode {
+        *self.span_mut() = span;
+        self
+    }
+
+    /// Value of a subtree that doesn't depend on any variable.
+    pub fn constant_value(&self) -> Option<f64> {
+        self.evaluate_in(&FunctionTable::new(), &mut Environment::new())
+            .ok()
+    }
+
+    /// Evaluates with user functions available. Assignments update `env`;
+    /// inside a function body `env` holds only the bound parameters.
+    fn evaluate_in(
+        &self,
+        functions: &FunctionTable,
+        env: &mut Environment,
+    ) -> Result<f64, EvalError> {
+        match self {
+            ASTNode::Number(n, _) => Ok(*n),
+            ASTNode::Identifier(name, _, span) => env
+                .get(name)
+                .ok_or_else(|| EvalError::UnboundVariable(name.clone(), *span)),
+            ASTNode::Error(span) | ASTNode::FunctionDef { span, .. } => {
+                Err(EvalError::InvalidExpression(*span))
+            }
+            ASTNode::BinaryOp {
+                op: '=',
+                left,
+                right,
+                span,
+            } => {
+                let value = right.evaluate_in(functions, env)?;
+                match &**left {
+                    ASTNode::Identifier(name, _, _) => {
+                        env.set(name, value);
+                        Ok(value)
+                    }
+                    _ => Err(EvalError::InvalidExpression(*span)),
+                }
+            }
+            ASTNode::BinaryOp {
+                op, left, right, ..
+            } => {
+                let l = left.evaluate_in(functions, env)?;
+                let r = right.evaluate_in(functions, env)?;
+                Ok(match op {
                     '+' => l + r,
                     '-' => l - r,
                     '*' => l * r,
                     '/' => l / r,
                     '^' => l.powf(r),
-                    '=' => r,
                     _ => 0.0,
-                }
+                })
             }
-            ASTNode::UnaryOp { op, operand } => {
-                let val = operand.evaluate();
-                match op.as_str() {
-                    "sqrt" => val.powf(0.5),
+            ASTNode::UnaryOp { op, operand, .. } => {
+                let val = operand.evaluate_in(functions, env)?;
+                Ok(match op.as_str() {
+                    "-" => -val,
+                    "sqrt" => val.sqrt(),
                     _ => val,
+                })
+            }
+            ASTNode::Call { name, args, .. } if is_conditional(name, args) => {
+                let branch = if args[0].evaluate_in(functions, env)? != 0.0 {
+                    &args[1]
+                } else {
+                    &args[2]
+                };
+                branch.evaluate_in(functions, env)
+            }
+            ASTNode::Call { name, args, span } => {
+                let values = args
+                    .iter()
+                    .map(|arg| arg.evaluate_in(functions, env))
+                    .collect::<Result<Vec<f64>, EvalError>>()?;
+
+                if let Some((params, body)) = functions.get(name.as_str()) {
+                    if params.len() != values.len() {
+                        return Err(EvalError::WrongArity(name.clone(), *span));
+                    }
+                    let mut locals = Environment::new();
+                    for ((param, _), value) in params.iter().zip(values) {
+                        locals.set(param, value);
+                    }
+                    return body.evaluate_in(functions, &mut locals);
+                }
+
+                match builtins::lookup(name) {
+                    Some(builtin) if builtin.arity == values.len() => Ok((builtin.eval)(&values)),
+                    Some(_) => Err(EvalError::WrongArity(name.clone(), *span)),
+                    None => Err(EvalError::UnknownFunction(name.clone(), *span)),
                 }
             }
         }
     }
 
-    pub fn to_three_address_code(&self, temp_counter: &mut usize) -> (Vec<String>, String) {
+    /// Appends the instructions computing this node to `builder` and
+    /// returns the operand holding its value.
+    pub fn to_three_address_code(&self, builder: &mut CodeBuilder) -> Operand {
         match self {
-            ASTNode::Number(n) => (vec![], format!("{}", n)),
-            ASTNode::Identifier(_name, idx) => (vec![], format!("id{}", idx)),
-            ASTNode::BinaryOp { op, left, right } => {
-                let (mut left_code, left_result) = left.to_three_address_code(temp_counter);
-                let (mut right_code, right_result) = right.to_three_address_code(temp_counter);
-
-                let temp = format!("t{}", temp_counter);
-                *temp_counter += 1;
-
-                let mut code = vec![];
-                code.append(&mut left_code);
-                code.append(&mut right_code);
-
-                let op_str = match op {
-                    '=' => "=",
-                    '+' => "+",
-                    '-' => "-",
-                    '*' => "*",
-                    '/' => "/",
-                    '^' => "^",
-                    _ => "?",
-                };
+            ASTNode::Number(n, _) => Operand::Const(*n),
+            ASTNode::Identifier(_name, idx, _) => Operand::Var(*idx),
+            // Code is only generated for valid programs
+            ASTNode::Error(_) => Operand::Const(f64::NAN),
+            ASTNode::BinaryOp {
+                op, left, right, ..
+            } => {
+                let left_result = left.to_three_address_code(builder);
+                let right_result = right.to_three_address_code(builder);
 
-                if *op == '=' {
-                    code.push(format!("{} = {}", left_result, right_result));
-                    (code, left_result)
-                } else {
-                    code.push(format!(
-                        "{} = {} {} {}",
-                        temp, left_result, op_str, right_result
-                    ));
-                    (code, temp)
+                match Op::from_binary(*op) {
+                    Some(op) => {
+                        let temp = builder.new_temp();
+                        builder.push(Instr::binary(temp, op, left_result, right_result));
+                        temp
+                    }
+                    None => {
+                        builder.push(Instr::copy(left_result, right_result));
+                        left_result
+                    }
                 }
             }
-            ASTNode::UnaryOp { op, operand } => {
-                let (mut operand_code, operand_result) =
-                    operand.to_three_address_code(temp_counter);
-                let temp = format!("t{}", temp_counter);
-                *temp_counter += 1;
+            ASTNode::UnaryOp { op, operand, .. } => {
+                let operand_result = operand.to_three_address_code(builder);
+
+                // Unary plus is a no-op and needs no instruction
+                let op = match op.as_str() {
+                    "-" => Op::Neg,
+                    "sqrt" => Op::Sqrt,
+                    _ => return operand_result,
+                };
+                let temp = builder.new_temp();
+                builder.push(Instr::unary(temp, op, operand_result));
+                temp
+            }
+            ASTNode::Call { name, args, .. } if is_conditional(name, args) => {
+                // Only the selected branch is evaluated:
+                //     ifFalse cond goto Lelse; t = then; goto Lend
+                //     Lelse: t = else
+                //     Lend:
+                let cond = args[0].to_three_address_code(builder);
+                let temp = builder.new_temp();
+                let else_label = builder.new_label();
+                let end_label = builder.new_label();
+
+                builder.push(Instr::if_false(cond, else_label));
+                let then_result = args[1].to_three_address_code(builder);
+                builder.push(Instr::copy(temp, then_result));
+                builder.push(Instr::goto(end_label));
+                builder.push(Instr::label(else_label));
+                let else_result = args[2].to_three_address_code(builder);
+                builder.push(Instr::copy(temp, else_result));
+                builder.push(Instr::label(end_label));
+                temp
+            }
+            ASTNode::Call { name, args, .. } => {
+                // Evaluate every argument first, then pass them in order
+                let results: Vec<Operand> = args
+                    .iter()
+                    .map(|arg| arg.to_three_address_code(builder))
+                    .collect();
+                for result in results {
+                    builder.push(Instr::param(result));
+                }
 
-                operand_code.push(format!("{} = {}({})", temp, op, operand_result));
-                (operand_code, temp)
+                let temp = builder.new_temp();
+                builder.push(Instr::call(temp, name, args.len()));
+                temp
+            }
+            ASTNode::FunctionDef { body, .. } => {
+                let result = body.to_three_address_code(builder);
+                builder.push(Instr::ret(result));
+                result
             }
         }
     }
 
-    pub fn semantic_check(&self) -> Vec<String> {
-        let mut warnings = vec![];
-        self.semantic_check_recursive(&mut warnings);
-        warnings
+    /// Warns when a constant operation on finite operands has a value that
+    /// is not, e.g. an overflow or `0 * (1 / 0)`. Folding keeps that value.
+    fn check_non_finite(&self, operands: &[&ASTNode], warnings: &mut Vec<Diagnostic>) {
+        let Some(value) = self.constant_value().filter(|value| !value.is_finite()) else {
+            return;
+        };
+        let finite_operands = operands.iter().all(|operand| {
+            operand
+                .constant_value()
+                .is_some_and(|value| value.is_finite())
+        });
+        if !finite_operands {
+            return;
+        }
+
+        let message = if value.is_nan() {
+            "constant expression is not a number"
+        } else {
+            "constant expression overflows"
+        };
+        warnings.push(
+            Diagnostic::warning("W0004", message, self.span())
+                .with_label(format!("evaluates to {}", value))
+                .with_help("constant folding keeps this value in the optimized code"),
+        );
     }
 
-    fn semantic_check_recursive(&self, warnings: &mut Vec<String>) {
+    fn semantic_check_recursive(&self, scope: &Scope, warnings: &mut Vec<Diagnostic>) {
         match self {
-            ASTNode::BinaryOp { op, left, right } => {
+            ASTNode::Identifier(name, _, span) => {
+                if let Some((function, params)) = scope.function
+                    && !params.iter().any(|(param, _)| param == name)
+                {
+                    warnings.push(
+                        Diagnostic::error(
+                            "E0104",
+                            format!("`{}` is not a parameter of `{}`", name, function),
+                            *span,
+                        )
+                        .with_label("not in scope")
+                        .with_help("function bodies may only use their own parameters"),
+                    );
+                }
+            }
+            ASTNode::BinaryOp {
+                op,
+                left,
+                right,
+                span,
+            } => {
+                // Only variables can be assigned to
+                if *op == '=' && !matches!(**left, ASTNode::Identifier(..)) {
+                    warnings.push(
+                        Diagnostic::error("E0103", "invalid assignment target", left.span())
+                            .with_label("cannot assign to this expression")
+                            .with_help(
+                                "function definitions like `f(x) = ...` must be whole statements \
+                                 with identifiers as parameters",
+                            ),
+                    );
+                }
+
+                let reported = warnings.len();
+
                 // Check division by zero
-                if *op == '/' {
-                    if let ASTNode::Number(n) = **right {
-                        if n == 0.0 {
-                            warnings.push("Warning: Division by zero detected".to_string());
-                        }
-                    }
+                if *op == '/'
+                    && let ASTNode::Number(n, _) = **right
+                    && n == 0.0
+                {
+                    warnings.push(
+                        Diagnostic::warning("W0001", "division by zero", right.span())
+                            .with_label("divisor is zero")
+                            .with_help("the result will be infinite or NaN"),
+                    );
                 }
 
                 // Check power with negative base and fractional exponent
-                if *op == '^' {
-                    if let (ASTNode::Number(base), ASTNode::Number(exp)) = (&**left, &**right) {
-                        if *base < 0.0 && exp.fract() != 0.0 {
-                            warnings.push("Warning: Negative base with fractional exponent may produce complex numbers".to_string());
-                        }
-                    }
+                if *op == '^'
+                    && let (Some(base), Some(exp)) = (left.constant_value(), right.constant_value())
+                    && base < 0.0
+                    && exp.fract() != 0.0
+                {
+                    warnings.push(
+                        Diagnostic::warning(
+                            "W0002",
+                            "negative base with fractional exponent",
+                            *span,
+                        )
+                        .with_label("result is not a real number")
+                        .with_help("the result would be complex; it evaluates to NaN"),
+                    );
                 }
 
-                left.semantic_check_recursive(warnings);
-                right.semantic_check_recursive(warnings);
+                if *op != '=' && warnings.len() == reported {
+                    self.check_non_finite(&[left, right], warnings);
+                }
+
+                left.semantic_check_recursive(scope, warnings);
+                right.semantic_check_recursive(scope, warnings);
+            }
+            ASTNode::UnaryOp { op, operand, span } => {
+                if op == "sqrt" && operand.constant_value().is_some_and(|value| value < 0.0) {
+                    warnings.push(
+                        Diagnostic::warning("W0003", "square root of a negative number", *span)
+                            .with_label("result is not a real number")
+                            .with_help("the result would be complex; it evaluates to NaN"),
+                    );
+                }
+                operand.semantic_check_recursive(scope, warnings);
+            }
+            ASTNode::Call { name, args, span } => {
+                let arity = match scope.functions.get(name.as_str()) {
+                    Some(arity) => Some(*arity),
+                    None => builtins::lookup(name).map(|builtin| builtin.arity),
+                };
+                match arity {
+                    None => warnings.push(
+                        Diagnostic::error("E0101", format!("unknown function `{}`", name), *span)
+                            .with_label("not a built-in or defined function"),
+                    ),
+                    Some(arity) if arity != args.len() => warnings.push(
+                        Diagnostic::error(
+                            "E0102",
+                            format!(
+                                "`{}` takes {} argument(s) but {} {} supplied",
+                                name,
+                                arity,
+                                args.len(),
+                                if args.len() == 1 { "was" } else { "were" }
+                            ),
+                            *span,
+                        )
+                        .with_label("wrong number of arguments"),
+                    ),
+                    Some(_) => {}
+                }
+                for arg in args {
+                    arg.semantic_check_recursive(scope, warnings);
+                }
+            }
+            ASTNode::FunctionDef {
+                name, params, body, ..
+            } => {
+                let body_scope = Scope {
+                    functions: scope.functions,
+                    function: Some((name, params)),
+                };
+                body.semantic_check_recursive(&body_scope, warnings);
+            }
+            ASTNode::Number(_, _) | ASTNode::Error(_) => {}
+        }
+    }
+
+    /// Names of the functions called anywhere in this tree.
+    fn called_functions<'a>(&'a self, calls: &mut Vec<&'a str>) {
+        match self {
+            ASTNode::Number(_, _) | ASTNode::Identifier(_, _, _) | ASTNode::Error(_) => {}
+            ASTNode::BinaryOp { left, right, .. } => {
+                left.called_functions(calls);
+                right.called_functions(calls);
             }
-            ASTNode::UnaryOp { operand, .. } => {
-                operand.semantic_check_recursive(warnings);
+            ASTNode::UnaryOp { operand, .. } => operand.called_functions(calls),
+            ASTNode::Call { name, args, .. } => {
+                calls.push(name);
+                for arg in args {
+                    arg.called_functions(calls);
+                }
             }
-            _ => {}
+            ASTNode::FunctionDef { body, .. } => body.called_functions(calls),
         }
     }
 
-    pub fn optimize(&self) -> ASTNode {
+    /// Replaces calls to user functions with their bodies, parameters
+    /// substituted by the argument expressions.
+    fn inline_calls(&self, functions: &FunctionTable) -> ASTNode {
         match self {
-            ASTNode::Number(_) | ASTNode::Identifier(_, _) => self.clone(),
-            ASTNode::BinaryOp { op, left, right } => {
-                let left_opt = left.optimize();
-                let right_opt = right.optimize();
-
-                // Constant folding
-                if let (ASTNode::Number(l), ASTNode::Number(r)) = (&left_opt, &right_opt) {
-                    let result = match op {
-                        '+' => l + r,
-                        '-' => l - r,
-                        '*' => l * r,
-                        '/' if *r != 0.0 => l / r,
-                        '^' => l.powf(*r),
-                        _ => {
-                            return ASTNode::BinaryOp {
-                                op: *op,
-                                left: Box::new(left_opt),
-                                right: Box::new(right_opt),
-                            };
-                        }
-                    };
-                    return ASTNode::Number(result);
-                }
-
-                // Algebraic simplification
-                match (*op, &left_opt, &right_opt) {
-                    // x + 0 = x
-                    ('+', _, ASTNode::Number(0.0)) => left_opt,
-                    ('+', ASTNode::Number(0.0), _) => right_opt,
-                    // x - 0 = x
-                    ('-', _, ASTNode::Number(0.0)) => left_opt,
-                    // x * 0 = 0
-                    ('*', _, ASTNode::Number(0.0)) | ('*', ASTNode::Number(0.0), _) => {
-                        ASTNode::Number(0.0)
+            ASTNode::Number(_, _) | ASTNode::Identifier(_, _, _) | ASTNode::Error(_) => {
+                self.clone()
+            }
+            ASTNode::BinaryOp {
+                op,
+                left,
+                right,
+                span,
+            } => ASTNode::BinaryOp {
+                op: *op,
+                left: Box::new(left.inline_calls(functions)),
+                right: Box::new(right.inline_calls(functions)),
+                span: *span,
+            },
+            ASTNode::UnaryOp { op, operand, span } => ASTNode::UnaryOp {
+                op: op.clone(),
+                operand: Box::new(operand.inline_calls(functions)),
+                span: *span,
+            },
+            ASTNode::Call { name, args, span } => {
+                let args: Vec<ASTNode> =
+                    args.iter().map(|arg| arg.inline_calls(functions)).collect();
+                match functions.get(name.as_str()) {
+                    Some((params, body)) => {
+                        let bindings = params
+                            .iter()
+                            .map(|(param, _)| param.as_str())
+                            .zip(args.iter())
+                            .collect();
+                        // The body may itself call other functions
+                        body.substitute(&bindings).inline_calls(functions)
                     }
-                    // x * 1 = x
-                    ('*', _, ASTNode::Number(1.0)) => left_opt,
-                    ('*', ASTNode::Number(1.0), _) => right_opt,
-                    // x / 1 = x
-                    ('/', _, ASTNode::Number(1.0)) => left_opt,
-                    // x ^ 0 = 1
-                    ('^', _, ASTNode::Number(0.0)) => ASTNode::Number(1.0),
-                    // x ^ 1 = x
-                    ('^', _, ASTNode::Number(1.0)) => left_opt,
-                    _ => ASTNode::BinaryOp {
-                        op: *op,
-                        left: Box::new(left_opt),
-                        right: Box::new(right_opt),
+                    None => ASTNode::Call {
+                        name: name.clone(),
+                        args,
+                        span: *span,
                     },
                 }
             }
-            ASTNode::UnaryOp { op, operand } => {
-                let operand_opt = operand.optimize();
-                if let ASTNode::Number(n) = operand_opt {
-                    if op == "sqrt" {
-                        return ASTNode::Number(n.powf(0.5));
-                    }
+            ASTNode::FunctionDef {
+                name,
+                params,
+                body,
+                span,
+            } => ASTNode::FunctionDef {
+                name: name.clone(),
+                params: params.clone(),
+                body: Box::new(body.inline_calls(functions)),
+                span: *span,
+            },
+        }
+    }
+
+    /// Replaces identifiers named in `bindings` with the bound expressions.
+    fn substitute(&self, bindings: &HashMap<&str, &ASTNode>) -> ASTNode {
+        match self {
+            ASTNode::Identifier(name, _, _) => match bindings.get(name.as_str()) {
+                Some(value) => (*value).clone(),
+                None => self.clone(),
+            },
+            ASTNode::Number(_, _) | ASTNode::Error(_) | ASTNode::FunctionDef { .. } => self.clone(),
+            ASTNode::BinaryOp {
+                op,
+                left,
+                right,
+                span,
+            } => ASTNode::BinaryOp {
+                op: *op,
+                left: Box::new(left.substitute(bindings)),
+                right: Box::new(right.substitute(bindings)),
+                span: *span,
+            },
+            ASTNode::UnaryOp { op, operand, span } => ASTNode::UnaryOp {
+                op: op.clone(),
+                operand: Box::new(operand.substitute(bindings)),
+                span: *span,
+            },
+            ASTNode::Call { name, args, span } => ASTNode::Call {
+                name: name.clone(),
+                args: args.iter().map(|arg| arg.substitute(bindings)).collect(),
+                span: *span,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, statement) in self.statements.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}", statement)?;
+        }
+        Ok(())
+    }
+}
+
+impl Program {
+    fn function_table(&self) -> FunctionTable<'_> {
+        self.statements
+            .iter()
+            .filter_map(|statement| match statement {
+                ASTNode::FunctionDef {
+                    name, params, body, ..
+                } => Some((name.as_str(), (params.as_slice(), &**body))),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Runs every statement in order against `env`, which receives the
+    /// assigned values. Returns the value of the last statement that isn't
+    /// a function definition, if any.
+    pub fn evaluate(&self, env: &mut Environment) -> Result<Option<f64>, EvalError> {
+        let functions = self.function_table();
+        let mut last = None;
+        for statement in &self.statements {
+            if !matches!(statement, ASTNode::FunctionDef { .. }) {
+                last = Some(statement.evaluate_in(&functions, env)?);
+            }
+        }
+        Ok(last)
+    }
+
+    /// Three-address code for every top-level statement, with temporaries
+    /// numbered across the whole program. Function bodies are listed
+    /// separately by `function_three_address_code`. A trailing expression
+    /// statement always leaves its value in a temporary, the program's
+    /// result, even when it is a plain name or constant.
+    pub fn to_three_address_code(&self) -> Vec<Instr> {
+        let mut builder = CodeBuilder::default();
+        let mut last = None;
+        for statement in &self.statements {
+            if !matches!(statement, ASTNode::FunctionDef { .. }) {
+                last = Some((statement, statement.to_three_address_code(&mut builder)));
+            }
+        }
+        if let Some((statement, value)) = last
+            && !matches!(statement, ASTNode::BinaryOp { op: '=', .. })
+            && !matches!(value, Operand::Temp(_))
+        {
+            let temp = builder.new_temp();
+            builder.push(Instr::copy(temp, value));
+        }
+        builder.code
+    }
+
+    /// One listing per user function, headed by its signature. Temporaries
+    /// are local to each function.
+    pub fn function_three_address_code(&self) -> Vec<(String, Vec<Instr>)> {
+        self.statements
+            .iter()
+            .filter_map(|statement| match statement {
+                ASTNode::FunctionDef { name, params, .. } => {
+                    let params: Vec<String> =
+                        params.iter().map(|(_, idx)| format!("id{}", idx)).collect();
+                    let mut builder = CodeBuilder::default();
+                    statement.to_three_address_code(&mut builder);
+                    Some((format!("{}({})", name, params.join(", ")), builder.code))
                 }
-                ASTNode::UnaryOp {
-                    op: op.clone(),
-                    operand: Box::new(operand_opt),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn semantic_check(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = vec![];
+        let mut functions: HashMap<&str, usize> = HashMap::new();
+
+        for statement in &self.statements {
+            let ASTNode::FunctionDef {
+                name, params, span, ..
+            } = statement
+            else {
+                continue;
+            };
+
+            if builtins::lookup(name).is_some() {
+                diagnostics.push(
+                    Diagnostic::error("E0106", format!("`{}` is a built-in function", name), *span)
+                        .with_label("cannot be redefined"),
+                );
+            }
+            if functions.insert(name, params.len()).is_some() {
+                diagnostics.push(
+                    Diagnostic::error(
+                        "E0105",
+                        format!("function `{}` is defined more than once", name),
+                        *span,
+                    )
+                    .with_label("redefined here"),
+                );
+            }
+
+            let mut seen = HashSet::new();
+            for (param, _) in params {
+                if !seen.insert(param) {
+                    diagnostics.push(
+                        Diagnostic::error(
+                            "E0108",
+                            format!("parameter `{}` is listed more than once", param),
+                            *span,
+                        )
+                        .with_label(format!("in the definition of `{}`", name)),
+                    );
                 }
             }
         }
+
+        let scope = Scope {
+            functions: &functions,
+            function: None,
+        };
+        for statement in &self.statements {
+            statement.semantic_check_recursive(&scope, &mut diagnostics);
+        }
+
+        self.check_recursion(&mut diagnostics);
+        diagnostics
     }
+
+    /// Reports every user function that can reach itself in the call graph.
+    /// Calls are compiled by inlining the body, which would never end for a
+    /// recursive function, even one guarded by `if`.
+    fn check_recursion(&self, diagnostics: &mut Vec<Diagnostic>) {
+        let mut call_graph: HashMap<&str, Vec<&str>> = HashMap::new();
+        let mut spans = vec![];
+        for statement in &self.statements {
+            if let ASTNode::FunctionDef {
+                name, body, span, ..
+            } = statement
+            {
+                let mut calls = vec![];
+                body.called_functions(&mut calls);
+                call_graph.insert(name, calls);
+                spans.push((name.as_str(), *span));
+            }
+        }
+
+        for (name, span) in spans {
+            if let Some(cycle) = find_cycle(&call_graph, name) {
+                diagnostics.push(
+                    Diagnostic::error("E0107", format!("function `{}` is recursive", name), span)
+                        .with_label(format!("call chain: {}", cycle.join(" → ")))
+                        .with_help(
+                            "calls are inlined, so recursion is not supported; rewrite it without the cycle",
+                        ),
+                );
+            }
+        }
+    }
+
+    /// Expands every call to a user function in place and drops the
+    /// definitions, leaving only built-in calls. Requires a program that
+    /// passed semantic analysis, so that no function is recursive.
+    pub fn inline_functions(&self) -> Program {
+        let functions = self.function_table();
+        Program {
+            statements: self
+                .statements
+                .iter()
+                .filter(|statement| !matches!(statement, ASTNode::FunctionDef { .. }))
+                .map(|statement| statement.inline_calls(&functions))
+                .collect(),
+        }
+    }
+}
+
+/// `if(cond, then, else)`, which picks a branch instead of calling.
+pub fn is_conditional(name: &str, args: &[ASTNode]) -> bool {
+    name.eq_ignore_ascii_case("if") && args.len() == 3
+}
+
+/// Depth-first search for a path from `start` back to itself.
+fn find_cycle<'a>(
+    call_graph: &HashMap<&'a str, Vec<&'a str>>,
+    start: &'a str,
+) -> Option<Vec<&'a str>> {
+    fn visit<'a>(
+        call_graph: &HashMap<&'a str, Vec<&'a str>>,
+        start: &'a str,
+        path: &mut Vec<&'a str>,
+        visited: &mut HashSet<&'a str>,
+    ) -> bool {
+        let current = *path.last().unwrap();
+        for &callee in call_graph.get(current).into_iter().flatten() {
+            if callee == start {
+                path.push(callee);
+                return true;
+            }
+            if call_graph.contains_key(callee) && visited.insert(callee) {
+                path.push(callee);
+                if visit(call_graph, start, path, visited) {
+                    return true;
+                }
+                path.pop();
+            }
+        }
+        false
+    }
+
+    let mut path = vec![start];
+    let mut visited = HashSet::new();
+    visit(call_graph, start, &mut path, &mut visited).then_some(path)
 }
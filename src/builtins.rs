@@ -0,0 +1,102 @@
+/// A function the language provides without a definition.
+pub struct Builtin {
+    pub name: &'static str,
+    pub arity: usize,
+    pub eval: fn(&[f64]) -> f64,
+}
+
+pub const BUILTINS: &[Builtin] = &[
+    Builtin {
+        name: "sqrt",
+        arity: 1,
+        eval: |a| a[0].sqrt(),
+    },
+    Builtin {
+        name: "sin",
+        arity: 1,
+        eval: |a| a[0].sin(),
+    },
+    Builtin {
+        name: "cos",
+        arity: 1,
+        eval: |a| a[0].cos(),
+    },
+    Builtin {
+        name: "tan",
+        arity: 1,
+        eval: |a| a[0].tan(),
+    },
+    Builtin {
+        name: "exp",
+        arity: 1,
+        eval: |a| a[0].exp(),
+    },
+    Builtin {
+        name: "ln",
+        arity: 1,
+        eval: |a| a[0].ln(),
+    },
+    Builtin {
+        name: "log",
+        arity: 1,
+        eval: |a| a[0].log10(),
+    },
+    Builtin {
+        name: "abs",
+        arity: 1,
+        eval: |a| a[0].abs(),
+    },
+    Builtin {
+        name: "floor",
+        arity: 1,
+        eval: |a| a[0].floor(),
+    },
+    Builtin {
+        name: "ceil",
+        arity: 1,
+        eval: |a| a[0].ceil(),
+    },
+    Builtin {
+        name: "round",
+        arity: 1,
+        eval: |a| a[0].round(),
+    },
+    Builtin {
+        name: "min",
+        arity: 2,
+        eval: |a| a[0].min(a[1]),
+    },
+    Builtin {
+        name: "max",
+        arity: 2,
+        eval: |a| a[0].max(a[1]),
+    },
+    Builtin {
+        name: "pow",
+        arity: 2,
+        eval: |a| a[0].powf(a[1]),
+    },
+    Builtin {
+        name: "hypot",
+        arity: 2,
+        eval: |a| a[0].hypot(a[1]),
+    },
+    Builtin {
+        name: "atan2",
+        arity: 2,
+        eval: |a| a[0].atan2(a[1]),
+    },
+    // `if(cond, then, else)`: code generation turns it into jumps so that
+    // only the chosen branch runs
+    Builtin {
+        name: "if",
+        arity: 3,
+        eval: |a| if a[0] != 0.0 { a[1] } else { a[2] },
+    },
+];
+
+/// Finds a built-in by name, ignoring case like the `sqrt` keyword always has.
+pub fn lookup(name: &str) -> Option<&'static Builtin> {
+    let name = name.to_lowercase();
+    BUILTINS.iter().find(|builtin| builtin.name == name)
+}
@@ -0,0 +1,317 @@
+use crate::ast::{self, ASTNode, Program};
+use crate::builtins::{self, BUILTINS};
+use crate::span::Span;
+use std::collections::HashMap;
+use std::fmt;
+
+/// One stack-machine instruction. Encoded as an opcode byte followed by
+/// its operand: a `u32` index or byte offset, or a `u8` built-in index.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Instruction {
+    /// Pushes constant `n` of the chunk.
+    PushConst(u32),
+    /// Pushes the value of variable `n`.
+    Load(u32),
+    /// Assigns the top of the stack to variable `n`, leaving it there.
+    Store(u32),
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+    Neg,
+    Sqrt,
+    /// Replaces the built-in's arguments on top of the stack by its result.
+    CallBuiltin(u8),
+    /// Continues at a byte offset.
+    Jump(u32),
+    /// Pops a value and jumps to a byte offset when it is zero.
+    JumpIfFalse(u32),
+    Pop,
+}
+
+impl Instruction {
+    fn opcode(self) -> u8 {
+        match self {
+            Instruction::PushConst(_) => 0,
+            Instruction::Load(_) => 1,
+            Instruction::Store(_) => 2,
+            Instruction::Add => 3,
+            Instruction::Sub => 4,
+            Instruction::Mul => 5,
+            Instruction::Div => 6,
+            Instruction::Pow => 7,
+            Instruction::Neg => 8,
+            Instruction::Sqrt => 9,
+            Instruction::CallBuiltin(_) => 10,
+            Instruction::Jump(_) => 11,
+            Instruction::JumpIfFalse(_) => 12,
+            Instruction::Pop => 13,
+        }
+    }
+
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            Instruction::PushConst(_) => "PUSH_CONST",
+            Instruction::Load(_) => "LOAD",
+            Instruction::Store(_) => "STORE",
+            Instruction::Add => "ADD",
+            Instruction::Sub => "SUB",
+            Instruction::Mul => "MUL",
+            Instruction::Div => "DIV",
+            Instruction::Pow => "POW",
+            Instruction::Neg => "NEG",
+            Instruction::Sqrt => "SQRT",
+            Instruction::CallBuiltin(_) => "CALL_BUILTIN",
+            Instruction::Jump(_) => "JUMP",
+            Instruction::JumpIfFalse(_) => "JUMP_IF_FALSE",
+            Instruction::Pop => "POP",
+        }
+    }
+
+    fn encode(self, out: &mut Vec<u8>) {
+        out.push(self.opcode());
+        match self {
+            Instruction::PushConst(n)
+            | Instruction::Load(n)
+            | Instruction::Store(n)
+            | Instruction::Jump(n)
+            | Instruction::JumpIfFalse(n) => out.extend(n.to_le_bytes()),
+            Instruction::CallBuiltin(n) => out.push(n),
+            _ => {}
+        }
+    }
+
+    /// The instruction at `offset` and the offset of the next one.
+    pub fn decode(code: &[u8], offset: usize) -> (Instruction, usize) {
+        let wide = || {
+            let bytes = &code[offset + 1..offset + 5];
+            u32::from_le_bytes(bytes.try_into().unwrap())
+        };
+        let instruction = match code[offset] {
+            0 => Instruction::PushConst(wide()),
+            1 => Instruction::Load(wide()),
+            2 => Instruction::Store(wide()),
+            3 => Instruction::Add,
+            4 => Instruction::Sub,
+            5 => Instruction::Mul,
+            6 => Instruction::Div,
+            7 => Instruction::Pow,
+            8 => Instruction::Neg,
+            9 => Instruction::Sqrt,
+            10 => Instruction::CallBuiltin(code[offset + 1]),
+            11 => Instruction::Jump(wide()),
+            12 => Instruction::JumpIfFalse(wide()),
+            13 => Instruction::Pop,
+            byte => unreachable!("invalid opcode {}", byte),
+        };
+        (instruction, offset + instruction.len())
+    }
+
+    /// Encoded size in bytes.
+    fn len(self) -> usize {
+        match self {
+            Instruction::PushConst(_)
+            | Instruction::Load(_)
+            | Instruction::Store(_)
+            | Instruction::Jump(_)
+            | Instruction::JumpIfFalse(_) => 5,
+            Instruction::CallBuiltin(_) => 2,
+            _ => 1,
+        }
+    }
+}
+
+/// Compiled program: the bytecode with its constant and variable tables.
+/// Every statement leaves its value on the stack and all but the last are
+/// popped, so the program's result is on top when it ends.
+#[derive(Debug, Clone, Default)]
+pub struct Chunk {
+    pub code: Vec<u8>,
+    pub constants: Vec<f64>,
+    /// Variable names, by `LOAD`/`STORE` operand.
+    pub names: Vec<String>,
+    /// Source of every `LOAD`, by offset, for unbound variable errors.
+    pub spans: HashMap<usize, Span>,
+}
+
+impl Chunk {
+    /// Compiles a program whose user functions have been inlined, as
+    /// `Program::inline_functions` leaves it.
+    pub fn compile(program: &Program) -> Chunk {
+        let mut chunk = Chunk::default();
+        for (i, statement) in program.statements.iter().enumerate() {
+            if i > 0 {
+                chunk.emit(Instruction::Pop);
+            }
+            chunk.expression(statement);
+        }
+        chunk
+    }
+
+    fn emit(&mut self, instruction: Instruction) -> usize {
+        let offset = self.code.len();
+        instruction.encode(&mut self.code);
+        offset
+    }
+
+    /// Points the jump at `offset` to the end of the code.
+    fn patch(&mut self, offset: usize) {
+        let target = self.index(self.code.len());
+        self.code[offset + 1..offset + 5].copy_from_slice(&target.to_le_bytes());
+    }
+
+    /// Operands are 32-bit, enough for any program that fits in memory.
+    fn index(&self, n: usize) -> u32 {
+        u32::try_from(n).expect("program too large for 32-bit bytecode operands")
+    }
+
+    fn constant(&mut self, value: f64) -> u32 {
+        let n = match self
+            .constants
+            .iter()
+            .position(|c| c.to_bits() == value.to_bits())
+        {
+            Some(n) => n,
+            None => {
+                self.constants.push(value);
+                self.constants.len() - 1
+            }
+        };
+        self.index(n)
+    }
+
+    fn name(&mut self, name: &str) -> u32 {
+        let n = match self.names.iter().position(|n| n == name) {
+            Some(n) => n,
+            None => {
+                self.names.push(name.to_string());
+                self.names.len() - 1
+            }
+        };
+        self.index(n)
+    }
+
+    fn expression(&mut self, node: &ASTNode) {
+        match node {
+            ASTNode::Number(n, _) => {
+                let n = self.constant(*n);
+                self.emit(Instruction::PushConst(n));
+            }
+            ASTNode::Identifier(name, _, span) => {
+                let n = self.name(name);
+                let offset = self.emit(Instruction::Load(n));
+                self.spans.insert(offset, *span);
+            }
+            ASTNode::BinaryOp {
+                op: '=',
+                left,
+                right,
+                ..
+            } => {
+                self.expression(right);
+                if let ASTNode::Identifier(name, _, _) = &**left {
+                    let n = self.name(name);
+                    self.emit(Instruction::Store(n));
+                }
+            }
+            ASTNode::BinaryOp {
+                op, left, right, ..
+            } => {
+                self.expression(left);
+                self.expression(right);
+                self.emit(match op {
+                    '+' => Instruction::Add,
+                    '-' => Instruction::Sub,
+                    '*' => Instruction::Mul,
+                    '/' => Instruction::Div,
+                    _ => Instruction::Pow,
+                });
+            }
+            ASTNode::UnaryOp { op, operand, .. } => {
+                self.expression(operand);
+                match op.as_str() {
+                    "-" => {
+                        self.emit(Instruction::Neg);
+                    }
+                    "sqrt" => {
+                        self.emit(Instruction::Sqrt);
+                    }
+                    _ => {}
+                }
+            }
+            // `if(cond, then, else)` runs only the chosen branch:
+            //     cond; JUMP_IF_FALSE else; then; JUMP end; else: else; end:
+            ASTNode::Call { name, args, .. } if ast::is_conditional(name, args) => {
+                self.expression(&args[0]);
+                let to_else = self.emit(Instruction::JumpIfFalse(0));
+                self.expression(&args[1]);
+                let to_end = self.emit(Instruction::Jump(0));
+                self.patch(to_else);
+                self.expression(&args[2]);
+                self.patch(to_end);
+            }
+            ASTNode::Call { name, args, .. } => {
+                for arg in args {
+                    self.expression(arg);
+                }
+                let builtin = builtins::lookup(name)
+                    .and_then(|builtin| BUILTINS.iter().position(|b| b.name == builtin.name));
+                match builtin {
+                    Some(n) => {
+                        self.emit(Instruction::CallBuiltin(n as u8));
+                    }
+                    // Only inlined, valid programs are compiled
+                    None => {
+                        let n = self.constant(f64::NAN);
+                        self.emit(Instruction::PushConst(n));
+                    }
+                }
+            }
+            ASTNode::FunctionDef { .. } | ASTNode::Error(_) => {
+                let n = self.constant(f64::NAN);
+                self.emit(Instruction::PushConst(n));
+            }
+        }
+    }
+
+    /// Every instruction with its offset, for listing the chunk.
+    pub fn instructions(&self) -> Vec<(usize, Instruction)> {
+        let mut instructions = vec![];
+        let mut offset = 0;
+        while offset < self.code.len() {
+            let (instruction, next) = Instruction::decode(&self.code, offset);
+            instructions.push((offset, instruction));
+            offset = next;
+        }
+        instructions
+    }
+
+    /// `offset  MNEMONIC operand (meaning)` for one instruction.
+    pub fn disassemble(&self, offset: usize, instruction: Instruction) -> String {
+        let operand = match instruction {
+            Instruction::PushConst(n) => format!(" {} ({})", n, self.constants[n as usize]),
+            Instruction::Load(n) | Instruction::Store(n) => {
+                format!(" {} ({})", n, self.names[n as usize])
+            }
+            Instruction::CallBuiltin(n) => {
+                let builtin = &BUILTINS[n as usize];
+                format!(" {} ({}/{})", n, builtin.name, builtin.arity)
+            }
+            Instruction::Jump(target) | Instruction::JumpIfFalse(target) => {
+                format!(" {:04}", target)
+            }
+            _ => String::new(),
+        };
+        format!("{:04}  {}{}", offset, instruction.mnemonic(), operand)
+    }
+}
+
+impl fmt::Display for Chunk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (offset, instruction) in self.instructions() {
+            writeln!(f, "{}", self.disassemble(offset, instruction))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,246 @@
+use crate::ir::{self, Instr, Op, Operand};
+use crate::liveness;
+use crate::x86;
+use std::collections::BTreeSet;
+use std::fmt;
+
+/// A self-contained C translation unit. Every variable the code uses is a
+/// file-scope `double`, temporaries are locals of `main`, and `^` and the built-ins
+/// call `math.h`. `main` takes the variables the code reads before
+/// assigning them as arguments, in symbol table order, and prints every
+/// variable, then the program's result when it ends in an expression.
+#[derive(Debug, Clone, Default)]
+pub struct CSource {
+    pub lines: Vec<String>,
+}
+
+impl CSource {
+    /// Translates optimized three-address code.
+    pub fn generate(code: &[Instr], identifier_table: &[(String, usize)]) -> CSource {
+        let mut vars = BTreeSet::new();
+        let mut temps = BTreeSet::new();
+        let mut read = BTreeSet::new();
+        let mut slots = BTreeSet::new();
+        for instr in code {
+            for operand in instr.dst.into_iter().chain(instr.uses()) {
+                match operand {
+                    Operand::Var(n) => {
+                        vars.insert(n);
+                    }
+                    Operand::Temp(n) => {
+                        temps.insert(n);
+                    }
+                    Operand::Const(_) => {}
+                }
+            }
+            read.extend(instr.uses().filter_map(|operand| match operand {
+                Operand::Temp(n) => Some(n),
+                _ => None,
+            }));
+            if let Op::Spill(slot) | Op::Reload(slot) = instr.op {
+                slots.insert(slot);
+            }
+        }
+        // Results of expression statements before the last are never read,
+        // and `-Wall` warns about variables that are only set
+        let result = ir::result_temp(code);
+        temps.retain(|n| read.contains(n) || Some(*n) == result);
+        let inputs = liveness::inputs(code);
+        let name_of = |n: usize| {
+            identifier_table
+                .iter()
+                .find(|(_, idx)| *idx == n)
+                .map_or_else(|| format!("id{}", n), |(name, _)| name.clone())
+        };
+
+        let mut lines = vec![
+            "#include <math.h>".to_string(),
+            "#include <stdio.h>".to_string(),
+            "#include <stdlib.h>".to_string(),
+            String::new(),
+        ];
+        for n in &vars {
+            lines.push(format!("double id{} = 0; /* {} */", n, name_of(*n)));
+        }
+        if !vars.is_empty() {
+            lines.push(String::new());
+        }
+        if !inputs.is_empty() {
+            lines.extend(INPUT.lines().map(str::to_string));
+            lines.push(String::new());
+        }
+        lines.push("int main(int argc, char **argv) {".to_string());
+        for (kind, set) in [("t", &temps), ("s", &slots)] {
+            if !set.is_empty() {
+                let names: Vec<String> = set.iter().map(|n| format!("{}{}", kind, n)).collect();
+                lines.push(format!("    double {};", names.join(", ")));
+            }
+        }
+
+        let usage: String = inputs.iter().map(|n| format!(" {}", name_of(*n))).collect();
+        lines.push(format!("    if (argc != {}) {{", inputs.len() + 1));
+        lines.push(format!(
+            "        fprintf(stderr, \"usage: %s{}\\n\", argv[0]);",
+            usage
+        ));
+        lines.push("        return 2;".to_string());
+        lines.push("    }".to_string());
+        for (i, n) in inputs.iter().enumerate() {
+            lines.push(format!("    id{} = input(argv[{}]);", n, i + 1));
+        }
+
+        let mut emitter = Emitter {
+            temps: &temps,
+            lines: vec![],
+            params: vec![],
+            returns: false,
+        };
+        for instr in code {
+            emitter.instruction(instr);
+        }
+        lines.extend(emitter.lines);
+        if emitter.returns {
+            lines.push("end:".to_string());
+        }
+
+        for n in &vars {
+            lines.push(format!(
+                "    printf(\"{} = %.17g\\n\", id{});",
+                name_of(*n),
+                n
+            ));
+        }
+        if let Some(n) = result {
+            lines.push(format!("    printf(\"result = %.17g\\n\", t{});", n));
+        }
+        lines.push("    return 0;".to_string());
+        lines.push("}".to_string());
+
+        CSource { lines }
+    }
+}
+
+/// Parses an argument, rejecting anything `strtod` does not consume whole.
+const INPUT: &str = r#"static double input(const char *arg) {
+    char *end;
+    double value = strtod(arg, &end);
+    if (end == arg || *end != '\0') {
+        fprintf(stderr, "not a number: %s\n", arg);
+        exit(2);
+    }
+    return value;
+}"#;
+
+impl fmt::Display for CSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in &self.lines {
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+/// C expression for an operand. Negative constants are parenthesized so
+/// that `minus` and `-` never run into them.
+fn operand(operand: Operand) -> String {
+    match operand {
+        Operand::Temp(n) => format!("t{}", n),
+        Operand::Var(n) => format!("id{}", n),
+        Operand::Const(c) if c.is_nan() => "NAN".to_string(),
+        Operand::Const(c) if c == f64::INFINITY => "INFINITY".to_string(),
+        Operand::Const(c) if c == f64::NEG_INFINITY => "(-INFINITY)".to_string(),
+        Operand::Const(c) if c.is_sign_negative() => format!("({:?})", c),
+        Operand::Const(c) => format!("{:?}", c),
+    }
+}
+
+struct Emitter<'a> {
+    /// Temporaries that are declared; assignments to others are dropped.
+    temps: &'a BTreeSet<usize>,
+    lines: Vec<String>,
+    /// Arguments of the next call.
+    params: Vec<String>,
+    /// Whether a `return` jumps to the `end` label.
+    returns: bool,
+}
+
+impl Emitter<'_> {
+    fn emit(&mut self, text: String) {
+        self.lines.push(format!("    {}", text));
+    }
+
+    fn instruction(&mut self, instr: &Instr) {
+        let a = instr.arg1.map(operand).unwrap_or_default();
+        let b = instr.arg2.map(operand).unwrap_or_default();
+        let dst = instr.dst.map(operand).unwrap_or_default();
+        if let Some(Operand::Temp(n)) = instr.dst
+            && !self.temps.contains(&n)
+        {
+            self.params.clear();
+            return;
+        }
+
+        match &instr.op {
+            Op::Copy => self.emit(format!("{} = {};", dst, a)),
+            Op::Add | Op::Sub | Op::Mul | Op::Div => {
+                self.emit(format!("{} = {} {} {};", dst, a, instr.op.symbol(), b))
+            }
+            Op::Pow => self.emit(format!("{} = pow({}, {});", dst, a, b)),
+            Op::Neg => self.emit(format!("{} = -{};", dst, a)),
+            Op::Sqrt => self.emit(format!("{} = sqrt({});", dst, a)),
+            Op::Param => self.params.push(a),
+            Op::Call { name, .. } => {
+                let args = std::mem::take(&mut self.params).join(", ");
+                self.emit(format!("{} = {}({});", dst, x86::libm_name(name), args));
+            }
+            Op::Return => {
+                self.returns = true;
+                self.emit("goto end;".to_string());
+            }
+            // A label needs a statement after it, even at the end
+            Op::Label(label) => self.lines.push(format!("L{}:;", label)),
+            Op::Goto(label) => self.emit(format!("goto L{};", label)),
+            // NaN is not zero, so it falls through like any true condition
+            Op::IfFalse(label) => self.emit(format!("if ({} == 0) goto L{};", a, label)),
+            Op::Spill(slot) => self.emit(format!("s{} = {};", slot, a)),
+            Op::Reload(slot) => self.emit(format!("{} = s{};", dst, slot)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::options::CompileOptions;
+    use crate::parser::ParseResult;
+    use crate::test_support::{self, Reported, Run};
+
+    fn run(input: &str, inputs: &[(&str, f64)]) -> Run {
+        let result = ParseResult::from_input(input, &CompileOptions::default());
+        assert!(!result.has_errors(), "{}", input);
+        let args = test_support::native_args(&result, inputs);
+        test_support::run_native("program.c", &result.c_source.to_string(), &args)
+    }
+
+    #[test]
+    fn matches_the_evaluator() {
+        if test_support::has_cc() {
+            test_support::check_corpus(Reported::TrailingExpression, run);
+        }
+    }
+
+    #[test]
+    fn results_that_reduce_to_a_name() {
+        if !test_support::has_cc() {
+            return;
+        }
+        for (input, inputs) in test_support::NAME_RESULTS {
+            test_support::check(input, inputs, Reported::TrailingExpression, run);
+        }
+    }
+
+    #[test]
+    fn function_parameters_are_not_variables() {
+        let result = ParseResult::from_input("f(n) = n * 2; f(3)", &CompileOptions::default());
+        assert!(!result.c_source.to_string().contains("/* n */"));
+    }
+}
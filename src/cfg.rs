@@ -0,0 +1,279 @@
+use crate::ir::{Instr, Op};
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// Splits `code` into basic blocks. A leader is the first instruction, a
+/// label, or the instruction after a jump; each block runs from one leader
+/// up to the next.
+pub fn block_ranges(code: &[Instr]) -> Vec<Range<usize>> {
+    let mut leaders: Vec<usize> = vec![];
+    for (i, instr) in code.iter().enumerate() {
+        let after_jump = i > 0 && code[i - 1].ends_block();
+        if i == 0 || after_jump || matches!(instr.op, Op::Label(_)) {
+            leaders.push(i);
+        }
+    }
+    leaders.dedup();
+
+    leaders
+        .iter()
+        .enumerate()
+        .map(|(i, start)| *start..leaders.get(i + 1).copied().unwrap_or(code.len()))
+        .collect()
+}
+
+/// A maximal run of instructions entered only at the top and left only
+/// at the bottom.
+#[derive(Debug, Clone)]
+pub struct BasicBlock {
+    /// Position of the first instruction in the listing.
+    pub start: usize,
+    pub code: Vec<Instr>,
+}
+
+/// Basic blocks with control-flow edges; block 0 is the entry.
+#[derive(Debug, Clone, Default)]
+pub struct ControlFlowGraph {
+    pub blocks: Vec<BasicBlock>,
+    pub successors: Vec<Vec<usize>>,
+    pub predecessors: Vec<Vec<usize>>,
+}
+
+impl ControlFlowGraph {
+    pub fn build(code: &[Instr]) -> Self {
+        let blocks: Vec<BasicBlock> = block_ranges(code)
+            .into_iter()
+            .map(|range| BasicBlock {
+                start: range.start,
+                code: code[range].to_vec(),
+            })
+            .collect();
+
+        let label_blocks: HashMap<usize, usize> = blocks
+            .iter()
+            .enumerate()
+            .filter_map(|(i, block)| match block.code.first()?.op {
+                Op::Label(label) => Some((label, i)),
+                _ => None,
+            })
+            .collect();
+
+        let mut successors = vec![vec![]; blocks.len()];
+        for (i, block) in blocks.iter().enumerate() {
+            let Some(last) = block.code.last() else {
+                continue;
+            };
+            let falls_through = !matches!(last.op, Op::Goto(_) | Op::Return);
+            if falls_through && i + 1 < blocks.len() {
+                successors[i].push(i + 1);
+            }
+            if let Some(target) = last.op.jump_target().and_then(|l| label_blocks.get(&l))
+                && !successors[i].contains(target)
+            {
+                successors[i].push(*target);
+            }
+        }
+
+        let mut predecessors = vec![vec![]; blocks.len()];
+        for (i, succs) in successors.iter().enumerate() {
+            for succ in succs {
+                predecessors[*succ].push(i);
+            }
+        }
+
+        Self {
+            blocks,
+            successors,
+            predecessors,
+        }
+    }
+
+    /// Blocks reachable from the entry, in reverse postorder.
+    pub fn reverse_postorder(&self) -> Vec<usize> {
+        fn visit(
+            cfg: &ControlFlowGraph,
+            block: usize,
+            seen: &mut Vec<bool>,
+            order: &mut Vec<usize>,
+        ) {
+            seen[block] = true;
+            for succ in &cfg.successors[block] {
+                if !seen[*succ] {
+                    visit(cfg, *succ, seen, order);
+                }
+            }
+            order.push(block);
+        }
+
+        let mut order = vec![];
+        if !self.blocks.is_empty() {
+            visit(self, 0, &mut vec![false; self.blocks.len()], &mut order);
+        }
+        order.reverse();
+        order
+    }
+
+    /// Immediate dominator of every block, using the iterative algorithm of
+    /// Cooper, Harvey and Kennedy. The entry and unreachable blocks have
+    /// none.
+    pub fn immediate_dominators(&self) -> Vec<Option<usize>> {
+        let order = self.reverse_postorder();
+        let mut rank = vec![usize::MAX; self.blocks.len()];
+        for (i, block) in order.iter().enumerate() {
+            rank[*block] = i;
+        }
+
+        let mut idom: Vec<Option<usize>> = vec![None; self.blocks.len()];
+        if let Some(entry) = order.first() {
+            idom[*entry] = Some(*entry);
+        }
+
+        let intersect = |idom: &[Option<usize>], mut a: usize, mut b: usize| {
+            while a != b {
+                while rank[a] > rank[b] {
+                    a = idom[a].unwrap();
+                }
+                while rank[b] > rank[a] {
+                    b = idom[b].unwrap();
+                }
+            }
+            a
+        };
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for block in order.iter().skip(1) {
+                let new_idom = self.predecessors[*block]
+                    .iter()
+                    .filter(|pred| idom[**pred].is_some())
+                    .copied()
+                    .reduce(|a, b| intersect(&idom, a, b));
+                if new_idom.is_some() && idom[*block] != new_idom {
+                    idom[*block] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+
+        if let Some(entry) = order.first() {
+            idom[*entry] = None;
+        }
+        idom
+    }
+
+    /// Children of every block in the dominator tree.
+    pub fn dominator_tree(&self) -> Vec<Vec<usize>> {
+        let mut children = vec![vec![]; self.blocks.len()];
+        for (block, idom) in self.immediate_dominators().into_iter().enumerate() {
+            if let Some(idom) = idom {
+                children[idom].push(block);
+            }
+        }
+        children
+    }
+
+    /// Dominance frontier of every block: the blocks where its dominance
+    /// ends, i.e. where definitions from it meet other paths.
+    pub fn dominance_frontiers(&self) -> Vec<Vec<usize>> {
+        let idoms = self.immediate_dominators();
+        let mut frontiers: Vec<Vec<usize>> = vec![vec![]; self.blocks.len()];
+        for (block, preds) in self.predecessors.iter().enumerate() {
+            if preds.len() < 2 {
+                continue;
+            }
+            for pred in preds {
+                let mut runner = Some(*pred);
+                while let Some(current) = runner
+                    && Some(current) != idoms[block]
+                {
+                    if !frontiers[current].contains(&block) {
+                        frontiers[current].push(block);
+                    }
+                    runner = idoms[current];
+                }
+            }
+        }
+        frontiers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{code, listing};
+
+    /// Blocks 0 entry, 1 then, 2 else and 3 join.
+    fn diamond() -> ControlFlowGraph {
+        ControlFlowGraph::build(&code("a = if(c, 1, 2); a + 1"))
+    }
+
+    #[test]
+    fn straight_line_code_is_one_block() {
+        let cfg = ControlFlowGraph::build(&code("a = 1; b = a * 2"));
+        assert_eq!(cfg.blocks.len(), 1);
+        assert_eq!(cfg.successors, [Vec::<usize>::new()]);
+    }
+
+    #[test]
+    fn blocks_split_at_labels_and_jumps() {
+        let cfg = diamond();
+        let blocks: Vec<(usize, Vec<String>)> = cfg
+            .blocks
+            .iter()
+            .map(|block| (block.start, listing(&block.code)))
+            .collect();
+        assert_eq!(
+            blocks,
+            [
+                (0, vec!["ifFalse id2 goto L1".to_string()]),
+                (1, vec!["t1 = 1".to_string(), "goto L2".to_string()]),
+                (3, vec!["L1:".to_string(), "t1 = 2".to_string()]),
+                (
+                    5,
+                    vec![
+                        "L2:".to_string(),
+                        "id1 = t1".to_string(),
+                        "t2 = id1 + 1".to_string()
+                    ]
+                ),
+            ]
+        );
+        assert_eq!(cfg.successors, [vec![1, 2], vec![3], vec![3], vec![]]);
+        assert_eq!(cfg.predecessors, [vec![], vec![0], vec![0], vec![1, 2]]);
+    }
+
+    #[test]
+    fn dominators_of_an_if() {
+        let cfg = diamond();
+        assert_eq!(cfg.reverse_postorder()[0], 0);
+        assert_eq!(
+            cfg.immediate_dominators(),
+            [None, Some(0), Some(0), Some(0)]
+        );
+        assert_eq!(
+            cfg.dominator_tree(),
+            [vec![1, 2, 3], vec![], vec![], vec![]]
+        );
+        assert_eq!(
+            cfg.dominance_frontiers(),
+            [vec![], vec![3], vec![3], vec![]]
+        );
+    }
+
+    #[test]
+    fn dominators_of_nested_ifs() {
+        let program = code("a = x; b = if(c, if(d, 1, 2), 3); b + a");
+        let cfg = ControlFlowGraph::build(&program);
+        assert_eq!(block_ranges(&program).len(), 7);
+        assert_eq!(cfg.successors[4], [6]);
+        assert_eq!(
+            cfg.immediate_dominators(),
+            [None, Some(0), Some(1), Some(1), Some(1), Some(0), Some(0)]
+        );
+        assert_eq!(
+            cfg.dominance_frontiers(),
+            [vec![], vec![6], vec![4], vec![4], vec![6], vec![6], vec![]]
+        );
+    }
+}
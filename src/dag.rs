@@ -0,0 +1,285 @@
+use crate::ir::{self, Instr, Op, Operand};
+use std::collections::{HashMap, HashSet};
+
+/// One value in the DAG: a leaf for a constant or the initial value of a
+/// variable, or an operation over other nodes.
+#[derive(Debug, Clone)]
+pub struct DagNode {
+    pub label: String,
+    pub children: Vec<usize>,
+    /// Temporaries and variables that hold this value, in order.
+    pub names: Vec<String>,
+    pub is_leaf: bool,
+}
+
+/// Directed acyclic graph of straight-line code in which identical
+/// subexpressions share a node. Children always precede their parents.
+#[derive(Debug, Clone, Default)]
+pub struct Dag {
+    pub nodes: Vec<DagNode>,
+}
+
+impl Dag {
+    /// One DAG per basic block, all in the same node list.
+    pub fn build(code: &[Instr]) -> Dag {
+        ValueNumbering::new(code).run(code).1
+    }
+}
+
+/// Rewrites `code` so that every value is computed once per basic block:
+/// a repeated subexpression reuses the operand that already holds it.
+pub fn eliminate_common_subexpressions(code: &[Instr]) -> Vec<Instr> {
+    ValueNumbering::new(code).run(code).0
+}
+
+/// What identifies a node: leaves by their operand, operations by their
+/// operator and operand nodes.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Const(u64),
+    Var(usize),
+    /// A temporary assigned in another block.
+    Temp(usize),
+    Op(String, Vec<usize>),
+}
+
+#[derive(Default)]
+struct ValueNumbering {
+    dag: Dag,
+    /// Temporaries that must keep their assignments: those that live
+    /// across blocks and those holding a statement's result.
+    kept: HashSet<usize>,
+    nodes: HashMap<Key, usize>,
+    /// Node currently bound to each variable.
+    vars: HashMap<usize, usize>,
+    /// Node computed by each temporary.
+    temps: HashMap<usize, usize>,
+    /// Operand that holds each node's value in the rewritten code.
+    holders: HashMap<usize, Operand>,
+    /// Temporaries replaced by an operand computed earlier.
+    aliases: HashMap<usize, Operand>,
+}
+
+impl ValueNumbering {
+    fn new(code: &[Instr]) -> Self {
+        Self {
+            kept: ir::kept_temps(code),
+            ..Self::default()
+        }
+    }
+
+    /// Forgets every value at a block boundary; the nodes built so far
+    /// stay in the graph.
+    fn start_block(&mut self) {
+        self.nodes.clear();
+        self.vars.clear();
+        self.temps.clear();
+        self.holders.clear();
+    }
+
+    fn run(mut self, code: &[Instr]) -> (Vec<Instr>, Dag) {
+        let mut out = vec![];
+        let mut params: Vec<Instr> = vec![];
+
+        for instr in code {
+            if matches!(instr.op, Op::Label(_)) {
+                self.start_block();
+            }
+            let mut instr = instr.clone();
+            instr.arg1 = instr.arg1.map(|arg| self.resolve(arg));
+            instr.arg2 = instr.arg2.map(|arg| self.resolve(arg));
+
+            // Params belong to the call that follows them
+            if instr.op == Op::Param {
+                params.push(instr);
+                continue;
+            }
+
+            let args: Vec<Operand> = match instr.op {
+                Op::Call { .. } => params.iter().filter_map(|p| p.arg1).collect(),
+                _ => instr.uses().collect(),
+            };
+            let mut children: Vec<usize> = args.iter().map(|arg| self.node_of(*arg)).collect();
+
+            let node = match instr.op {
+                Op::Copy => children[0],
+                Op::Return | Op::Label(_) | Op::Goto(_) | Op::IfFalse(_) => {
+                    let ends_block = instr.ends_block();
+                    out.append(&mut params);
+                    out.push(instr);
+                    if ends_block {
+                        self.start_block();
+                    }
+                    continue;
+                }
+                ref op => {
+                    // a + b and b + a are the same value
+                    if matches!(op, Op::Add | Op::Mul) {
+                        children.sort_unstable();
+                    }
+                    let label = match op {
+                        Op::Call { name, .. } => format!("{}()", name),
+                        op => op.symbol().to_string(),
+                    };
+                    self.intern(Key::Op(label.clone(), children.clone()), label, children)
+                }
+            };
+
+            let Some(dst) = instr.dst else {
+                continue;
+            };
+            let held = self.holders.get(&node).copied();
+            let call_params = std::mem::take(&mut params);
+
+            match dst {
+                Operand::Temp(n) if !self.kept.contains(&n) => {
+                    self.temps.insert(n, node);
+                    if let Some(holder) = held {
+                        self.aliases.insert(n, holder);
+                    } else {
+                        out.extend(call_params);
+                        out.push(instr);
+                        self.holders.insert(node, dst);
+                    }
+                }
+                Operand::Var(_) | Operand::Temp(_) => {
+                    // Whatever the variable held is gone after this
+                    self.holders.retain(|_, holder| *holder != dst);
+                    match held {
+                        Some(holder) if instr.op != Op::Copy => out.push(Instr::copy(dst, holder)),
+                        _ => {
+                            out.extend(call_params);
+                            out.push(instr);
+                        }
+                    }
+                    if let Operand::Var(v) | Operand::Temp(v) = dst {
+                        let bindings = if matches!(dst, Operand::Var(_)) {
+                            &mut self.vars
+                        } else {
+                            &mut self.temps
+                        };
+                        bindings.insert(v, node);
+                    }
+                    self.holders.entry(node).or_insert(dst);
+                }
+                Operand::Const(_) => {}
+            }
+            self.dag.nodes[node].names.push(dst.to_string());
+        }
+        out.append(&mut params);
+
+        (out, self.dag)
+    }
+
+    fn resolve(&self, operand: Operand) -> Operand {
+        match operand {
+            Operand::Temp(n) => self.aliases.get(&n).copied().unwrap_or(operand),
+            _ => operand,
+        }
+    }
+
+    fn node_of(&mut self, operand: Operand) -> usize {
+        match operand {
+            Operand::Const(c) => self.intern(Key::Const(c.to_bits()), c.to_string(), vec![]),
+            Operand::Var(v) => match self.vars.get(&v) {
+                Some(node) => *node,
+                None => {
+                    let node = self.intern(Key::Var(v), format!("id{}₀", v), vec![]);
+                    self.vars.insert(v, node);
+                    self.holders.entry(node).or_insert(operand);
+                    node
+                }
+            },
+            Operand::Temp(n) => match self.temps.get(&n) {
+                Some(node) => *node,
+                None => {
+                    let node = self.intern(Key::Temp(n), operand.to_string(), vec![]);
+                    self.temps.insert(n, node);
+                    self.holders.entry(node).or_insert(operand);
+                    node
+                }
+            },
+        }
+    }
+
+    fn intern(&mut self, key: Key, label: String, children: Vec<usize>) -> usize {
+        if let Some(node) = self.nodes.get(&key) {
+            return *node;
+        }
+        let node = self.dag.nodes.len();
+        self.dag.nodes.push(DagNode {
+            label,
+            is_leaf: children.is_empty(),
+            children,
+            names: vec![],
+        });
+        self.nodes.insert(key, node);
+        node
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::FloatMode;
+    use crate::test_support::{code, listing};
+
+    fn cse(input: &str) -> Vec<String> {
+        listing(&eliminate_common_subexpressions(&code(input)))
+    }
+
+    #[test]
+    fn repeated_subexpressions_are_computed_once() {
+        assert_eq!(
+            cse("y = a * b + b * a"),
+            ["t1 = id2 * id3", "t3 = t1 + t1", "id1 = t3"]
+        );
+    }
+
+    #[test]
+    fn assignments_invalidate_values() {
+        let input = "y = a * b; a = 1; z = a * b";
+        assert_eq!(cse(input), listing(&code(input)));
+    }
+
+    #[test]
+    fn values_do_not_cross_blocks() {
+        let input = "a = x * 2; b = if(c, x * 2, 0)";
+        assert_eq!(cse(input), listing(&code(input)));
+    }
+
+    #[test]
+    fn statement_results_are_kept() {
+        assert_eq!(
+            cse("y = a * b; a * b"),
+            ["t1 = id2 * id3", "id1 = t1", "t2 = t1"]
+        );
+        let simplified = ir::simplify_algebra(&code("-(-x)"), FloatMode::Strict);
+        let out = eliminate_common_subexpressions(&simplified);
+        assert_eq!(listing(&out), ["t2 = id1"]);
+        assert_eq!(ir::result_temp(&out), Some(2));
+    }
+
+    #[test]
+    fn identical_values_share_a_node() {
+        let dag = Dag::build(&code("y = a * b + a * b"));
+        let nodes: Vec<(&str, &[usize], Vec<&str>)> = dag
+            .nodes
+            .iter()
+            .map(|node| {
+                let names = node.names.iter().map(String::as_str).collect();
+                (node.label.as_str(), node.children.as_slice(), names)
+            })
+            .collect();
+        assert_eq!(
+            nodes,
+            [
+                ("id2₀", &[][..], vec![]),
+                ("id3₀", &[], vec![]),
+                ("*", &[0, 1], vec!["t1", "t2"]),
+                ("+", &[2, 2], vec!["t3", "id1"]),
+            ]
+        );
+        assert!(dag.nodes[0].is_leaf && !dag.nodes[2].is_leaf);
+    }
+}
@@ -0,0 +1,184 @@
+use crate::error::{EvalError, ParseError};
+use crate::span::Span;
+use crate::token::TokenKind;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A located message about the input, rendered rustc-style with the
+/// offending source line and a caret underline.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: &'static str,
+    pub message: String,
+    pub span: Span,
+    pub label: Option<String>,
+    pub help: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(code: &'static str, message: impl Into<String>, span: Span) -> Self {
+        Self {
+            severity: Severity::Error,
+            code,
+            message: message.into(),
+            span,
+            label: None,
+            help: vec![],
+        }
+    }
+
+    pub fn warning(code: &'static str, message: impl Into<String>, span: Span) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::error(code, message, span)
+        }
+    }
+
+    pub fn with_label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help.push(help.into());
+        self
+    }
+
+    /// Renders the diagnostic against the source it was produced from:
+    ///
+    /// ```text
+    /// error[E0001]: unexpected token `RPAREN`
+    ///  --> 1:5
+    ///   |
+    /// 1 | a + )
+    ///   |     ^ expected an operand
+    ///   = help: ...
+    /// ```
+    pub fn render(&self, source: &str) -> String {
+        let line_text = source.lines().nth(self.span.line - 1).unwrap_or("");
+        let gutter = " ".repeat(self.span.line.to_string().len());
+
+        // Underline up to the end of the span, or the end of the line for
+        // spans crossing a line break; zero-width spans get a single caret.
+        let line_len = line_text.chars().count();
+        let start_col = self.span.column - 1;
+        let end_col = (start_col + (self.span.end - self.span.start)).min(line_len);
+        let width = end_col.saturating_sub(start_col).max(1);
+
+        let mut out = format!("{}[{}]: {}\n", self.severity, self.code, self.message);
+        out.push_str(&format!("{}--> {}\n", gutter, self.span));
+        out.push_str(&format!("{} |\n", gutter));
+        out.push_str(&format!("{} | {}\n", self.span.line, line_text));
+        out.push_str(&format!(
+            "{} | {}{}",
+            gutter,
+            " ".repeat(start_col),
+            "^".repeat(width)
+        ));
+        if let Some(label) = &self.label {
+            out.push_str(&format!(" {}", label));
+        }
+        out.push('\n');
+        for help in &self.help {
+            out.push_str(&format!("{} = help: {}\n", gutter, help));
+        }
+        out
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}[{}]: {} at {}",
+            self.severity, self.code, self.message, self.span
+        )
+    }
+}
+
+impl From<&ParseError> for Diagnostic {
+    fn from(err: &ParseError) -> Self {
+        match err {
+            ParseError::UnexpectedToken(token, span) => {
+                Diagnostic::error("E0001", format!("unexpected token `{}`", token), *span)
+                    .with_label("expected an operand")
+                    .with_help("operands are numbers, identifiers or parenthesized expressions")
+            }
+            ParseError::UnexpectedEndOfInput(span) => {
+                Diagnostic::error("E0002", "unexpected end of input", *span)
+                    .with_label("expression ends here")
+                    .with_help("the expression is incomplete; an operand is missing")
+            }
+            ParseError::InvalidToken(ch, span) => {
+                Diagnostic::error("E0003", format!("invalid character `{}`", ch), *span)
+                    .with_label("not recognized by the lexer")
+            }
+            ParseError::InvalidNumber(text, span) => {
+                Diagnostic::error("E0004", format!("invalid number literal `{}`", text), *span)
+                    .with_label("cannot be parsed as a number")
+                    .with_help("a number may contain at most one decimal point")
+            }
+            ParseError::UnclosedParenthesis(span) => {
+                Diagnostic::error("E0005", "unclosed parenthesis", *span)
+                    .with_label("this `(` is never closed")
+                    .with_help("add a matching `)` after the enclosed expression")
+            }
+            ParseError::TrailingToken(token, span) => {
+                let diagnostic = Diagnostic::error(
+                    "E0006",
+                    format!("unexpected trailing token `{}`", token),
+                    *span,
+                )
+                .with_label("expected `;` or end of line");
+                if *token == TokenKind::RParen {
+                    diagnostic.with_help("this `)` has no matching `(`")
+                } else {
+                    diagnostic.with_help(
+                        "put an operator between the operands, or `;` or a newline between statements",
+                    )
+                }
+            }
+        }
+    }
+}
+
+impl From<&EvalError> for Diagnostic {
+    fn from(err: &EvalError) -> Self {
+        match err {
+            EvalError::UnboundVariable(name, span) => {
+                Diagnostic::error("E0201", format!("unbound variable `{}`", name), *span)
+                    .with_label("has no value")
+                    .with_help("assign it earlier in the program or give it an initial value")
+            }
+            EvalError::UnknownFunction(name, span) => {
+                Diagnostic::error("E0202", format!("unknown function `{}`", name), *span)
+                    .with_label("cannot be called")
+            }
+            EvalError::WrongArity(name, span) => Diagnostic::error(
+                "E0203",
+                format!("wrong number of arguments to `{}`", name),
+                *span,
+            )
+            .with_label("cannot be called with these arguments"),
+            EvalError::InvalidExpression(span) => {
+                Diagnostic::error("E0204", "cannot evaluate an invalid expression", *span)
+                    .with_label("failed to parse")
+            }
+        }
+    }
+}
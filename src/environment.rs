@@ -0,0 +1,21 @@
+use std::collections::HashMap;
+
+/// Values bound to variables during evaluation.
+#[derive(Debug, Clone, Default)]
+pub struct Environment {
+    values: HashMap<String, f64>,
+}
+
+impl Environment {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<f64> {
+        self.values.get(name).copied()
+    }
+
+    pub fn set(&mut self, name: &str, value: f64) {
+        self.values.insert(name.to_string(), value);
+    }
+}
@@ -1,20 +1,75 @@
+use crate::span::Span;
+use crate::token::TokenKind;
 use std::fmt;
 
 #[derive(Debug, Clone)]
 pub enum ParseError {
-    InvalidToken(String),
-    UnexpectedToken(String),
-    UnexpectedEndOfInput,
-    InvalidNumber(String),
+    InvalidToken(String, Span),
+    UnexpectedToken(TokenKind, Span),
+    UnexpectedEndOfInput(Span),
+    InvalidNumber(String, Span),
+    UnclosedParenthesis(Span),
+    TrailingToken(TokenKind, Span),
+}
+
+impl ParseError {
+    pub fn span(&self) -> Span {
+        match self {
+            ParseError::InvalidToken(_, span)
+            | ParseError::UnexpectedToken(_, span)
+            | ParseError::UnexpectedEndOfInput(span)
+            | ParseError::InvalidNumber(_, span)
+            | ParseError::UnclosedParenthesis(span)
+            | ParseError::TrailingToken(_, span) => *span,
+        }
+    }
 }
 
 impl fmt::Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            ParseError::InvalidToken(msg) => write!(f, "Invalid token: {}", msg),
-            ParseError::UnexpectedToken(msg) => write!(f, "Unexpected token: {}", msg),
-            ParseError::UnexpectedEndOfInput => write!(f, "Unexpected end of input"),
-            ParseError::InvalidNumber(msg) => write!(f, "Invalid number: {}", msg),
+            ParseError::InvalidToken(msg, span) => write!(f, "Invalid token: {} at {}", msg, span),
+            ParseError::UnexpectedToken(kind, span) => {
+                write!(f, "Unexpected token: {} at {}", kind, span)
+            }
+            ParseError::UnexpectedEndOfInput(span) => {
+                write!(f, "Unexpected end of input at {}", span)
+            }
+            ParseError::InvalidNumber(msg, span) => {
+                write!(f, "Invalid number: {} at {}", msg, span)
+            }
+            ParseError::UnclosedParenthesis(span) => {
+                write!(f, "Unclosed parenthesis opened at {}", span)
+            }
+            ParseError::TrailingToken(kind, span) => {
+                write!(f, "Unexpected trailing token: {} at {}", kind, span)
+            }
+        }
+    }
+}
+
+/// Failure while evaluating a program against an `Environment`.
+#[derive(Debug, Clone)]
+pub enum EvalError {
+    UnboundVariable(String, Span),
+    UnknownFunction(String, Span),
+    WrongArity(String, Span),
+    InvalidExpression(Span),
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvalError::UnboundVariable(name, span) => {
+                write!(f, "Unbound variable: {} at {}", name, span)
+            }
+            EvalError::UnknownFunction(name, span) => {
+                write!(f, "Unknown function: {} at {}", name, span)
+            }
+            EvalError::WrongArity(name, span) => {
+                write!(f, "Wrong number of arguments to {} at {}", name, span)
+            }
+            EvalError::InvalidExpression(span) => write!(f, "Invalid expression at {}", span),
         }
     }
 }
@@ -0,0 +1,617 @@
+use crate::builtins;
+use crate::cfg;
+use crate::options::FloatMode;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// A value an instruction reads or writes.
+#[derive(Debug, Clone, Copy)]
+pub enum Operand {
+    /// Compiler temporary `tN`, assigned exactly once.
+    Temp(usize),
+    /// User variable `idN`, numbered as in the symbol table.
+    Var(usize),
+    Const(f64),
+}
+
+/// Constants compare by their bits, so that a NaN equals itself and `-0`
+/// stays distinct from `0`.
+impl PartialEq for Operand {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Operand::Temp(a), Operand::Temp(b)) | (Operand::Var(a), Operand::Var(b)) => a == b,
+            (Operand::Const(a), Operand::Const(b)) => a.to_bits() == b.to_bits(),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Temp(n) => write!(f, "t{}", n),
+            Operand::Var(n) => write!(f, "id{}", n),
+            Operand::Const(c) => write!(f, "{}", c),
+        }
+    }
+}
+
+/// Storage an operand can name: a user variable or a temporary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Name {
+    Var(usize),
+    Temp(usize),
+}
+
+impl Operand {
+    pub fn name(self) -> Option<Name> {
+        match self {
+            Operand::Var(n) => Some(Name::Var(n)),
+            Operand::Temp(n) => Some(Name::Temp(n)),
+            Operand::Const(_) => None,
+        }
+    }
+}
+
+impl From<Name> for Operand {
+    fn from(name: Name) -> Self {
+        match name {
+            Name::Var(n) => Operand::Var(n),
+            Name::Temp(n) => Operand::Temp(n),
+        }
+    }
+}
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Operand::from(*self).fmt(f)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Op {
+    /// `dst = arg1`
+    Copy,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+    /// `dst = minus arg1`
+    Neg,
+    /// `dst = sqrt(arg1)`
+    Sqrt,
+    /// `param arg1`, pushed before a call
+    Param,
+    /// `dst = call name, argc`, taking the preceding `argc` params
+    Call {
+        name: String,
+        argc: usize,
+    },
+    /// `return arg1`
+    Return,
+    /// `Ln:`, a jump target
+    Label(usize),
+    /// `goto Ln`
+    Goto(usize),
+    /// `ifFalse arg1 goto Ln`, jumping when `arg1` is zero
+    IfFalse(usize),
+    /// `spill arg1 → sN`, saving a temporary to stack slot N
+    Spill(usize),
+    /// `dst = reload sN`
+    Reload(usize),
+}
+
+impl Op {
+    /// The IR operation for a binary AST operator other than `=`.
+    pub fn from_binary(op: char) -> Option<Op> {
+        match op {
+            '+' => Some(Op::Add),
+            '-' => Some(Op::Sub),
+            '*' => Some(Op::Mul),
+            '/' => Some(Op::Div),
+            '^' => Some(Op::Pow),
+            _ => None,
+        }
+    }
+
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Op::Add => "+",
+            Op::Sub => "-",
+            Op::Mul => "*",
+            Op::Div => "/",
+            Op::Pow => "^",
+            Op::Neg => "minus",
+            Op::Sqrt => "sqrt",
+            Op::Param => "param",
+            Op::Call { .. } => "call",
+            Op::Return => "return",
+            Op::Copy => "=",
+            Op::Label(_) => "label",
+            Op::Goto(_) => "goto",
+            Op::IfFalse(_) => "ifFalse",
+            Op::Spill(_) => "spill",
+            Op::Reload(_) => "reload",
+        }
+    }
+
+    /// Target label of a jump.
+    pub fn jump_target(&self) -> Option<usize> {
+        match self {
+            Op::Goto(label) | Op::IfFalse(label) => Some(*label),
+            _ => None,
+        }
+    }
+
+    /// Result of the operation on constant operands, if it can be folded.
+    /// Division by zero folds to an infinity or NaN, as IEEE-754 defines.
+    pub fn fold(&self, a: f64, b: f64) -> Option<f64> {
+        match self {
+            Op::Copy => Some(a),
+            Op::Add => Some(a + b),
+            Op::Sub => Some(a - b),
+            Op::Mul => Some(a * b),
+            Op::Div => Some(a / b),
+            Op::Pow => Some(a.powf(b)),
+            Op::Neg => Some(-a),
+            Op::Sqrt => Some(a.sqrt()),
+            _ => None,
+        }
+    }
+}
+
+/// One three-address instruction: `dst = arg1 op arg2`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instr {
+    pub dst: Option<Operand>,
+    pub op: Op,
+    pub arg1: Option<Operand>,
+    pub arg2: Option<Operand>,
+}
+
+impl Instr {
+    pub fn binary(dst: Operand, op: Op, arg1: Operand, arg2: Operand) -> Self {
+        Self {
+            dst: Some(dst),
+            op,
+            arg1: Some(arg1),
+            arg2: Some(arg2),
+        }
+    }
+
+    pub fn unary(dst: Operand, op: Op, arg1: Operand) -> Self {
+        Self {
+            dst: Some(dst),
+            op,
+            arg1: Some(arg1),
+            arg2: None,
+        }
+    }
+
+    pub fn copy(dst: Operand, src: Operand) -> Self {
+        Self::unary(dst, Op::Copy, src)
+    }
+
+    pub fn param(arg: Operand) -> Self {
+        Self {
+            dst: None,
+            op: Op::Param,
+            arg1: Some(arg),
+            arg2: None,
+        }
+    }
+
+    pub fn call(dst: Operand, name: &str, argc: usize) -> Self {
+        Self {
+            dst: Some(dst),
+            op: Op::Call {
+                name: name.to_string(),
+                argc,
+            },
+            arg1: None,
+            arg2: None,
+        }
+    }
+
+    pub fn ret(value: Operand) -> Self {
+        Self {
+            dst: None,
+            op: Op::Return,
+            arg1: Some(value),
+            arg2: None,
+        }
+    }
+
+    pub fn label(label: usize) -> Self {
+        Self {
+            dst: None,
+            op: Op::Label(label),
+            arg1: None,
+            arg2: None,
+        }
+    }
+
+    pub fn goto(label: usize) -> Self {
+        Self {
+            dst: None,
+            op: Op::Goto(label),
+            arg1: None,
+            arg2: None,
+        }
+    }
+
+    pub fn if_false(cond: Operand, label: usize) -> Self {
+        Self {
+            dst: None,
+            op: Op::IfFalse(label),
+            arg1: Some(cond),
+            arg2: None,
+        }
+    }
+
+    pub fn spill(temp: Operand, slot: usize) -> Self {
+        Self {
+            dst: None,
+            op: Op::Spill(slot),
+            arg1: Some(temp),
+            arg2: None,
+        }
+    }
+
+    pub fn reload(temp: Operand, slot: usize) -> Self {
+        Self {
+            dst: Some(temp),
+            op: Op::Reload(slot),
+            arg1: None,
+            arg2: None,
+        }
+    }
+
+    /// Whether control can leave the block after this instruction.
+    pub fn ends_block(&self) -> bool {
+        matches!(self.op, Op::Goto(_) | Op::IfFalse(_) | Op::Return)
+    }
+
+    /// Operands read by this instruction.
+    pub fn uses(&self) -> impl Iterator<Item = Operand> {
+        self.arg1.into_iter().chain(self.arg2)
+    }
+}
+
+impl fmt::Display for Instr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let arg = |operand: Option<Operand>| operand.map(|o| o.to_string()).unwrap_or_default();
+        write_instr(
+            f,
+            &arg(self.dst),
+            &self.op,
+            &arg(self.arg1),
+            &arg(self.arg2),
+        )
+    }
+}
+
+/// Writes one instruction in listing syntax from its already formatted
+/// operands; shared with the SSA listing.
+pub fn write_instr(f: &mut fmt::Formatter, dst: &str, op: &Op, a: &str, b: &str) -> fmt::Result {
+    match op {
+        Op::Copy => write!(f, "{} = {}", dst, a),
+        Op::Neg => write!(f, "{} = minus {}", dst, a),
+        Op::Sqrt => write!(f, "{} = sqrt({})", dst, a),
+        Op::Param => write!(f, "param {}", a),
+        Op::Call { name, argc } => write!(f, "{} = call {}, {}", dst, name, argc),
+        Op::Return => write!(f, "return {}", a),
+        Op::Label(label) => write!(f, "L{}:", label),
+        Op::Goto(label) => write!(f, "goto L{}", label),
+        Op::IfFalse(label) => write!(f, "ifFalse {} goto L{}", a, label),
+        Op::Spill(slot) => write!(f, "spill {} → s{}", a, slot),
+        Op::Reload(slot) => write!(f, "{} = reload s{}", dst, slot),
+        op => write!(f, "{} = {} {} {}", dst, a, op.symbol(), b),
+    }
+}
+
+/// Accumulates the instructions of one listing and hands out fresh
+/// temporaries and labels.
+#[derive(Debug, Default)]
+pub struct CodeBuilder {
+    pub code: Vec<Instr>,
+    temps: usize,
+    labels: usize,
+}
+
+impl CodeBuilder {
+    pub fn new_temp(&mut self) -> Operand {
+        self.temps += 1;
+        Operand::Temp(self.temps)
+    }
+
+    pub fn new_label(&mut self) -> usize {
+        self.labels += 1;
+        self.labels
+    }
+
+    pub fn push(&mut self, instr: Instr) {
+        self.code.push(instr);
+    }
+}
+
+/// Temporaries that are assigned more than once or read outside the basic
+/// block that assigns them, such as the result of a conditional. The local
+/// passes must keep their assignments.
+pub fn escaping_temps(code: &[Instr]) -> HashSet<usize> {
+    let mut defined_in: HashMap<usize, usize> = HashMap::new();
+    let mut escaping = HashSet::new();
+    for (block, range) in cfg::block_ranges(code).into_iter().enumerate() {
+        for instr in &code[range] {
+            for operand in instr.uses() {
+                if let Operand::Temp(n) = operand
+                    && defined_in.get(&n) != Some(&block)
+                {
+                    escaping.insert(n);
+                }
+            }
+            if let Some(Operand::Temp(n)) = instr.dst
+                && defined_in.insert(n, block).is_some()
+            {
+                escaping.insert(n);
+            }
+        }
+    }
+    escaping
+}
+
+/// Temporary holding the program's result when its last statement is an
+/// expression: the last one assigned before any trailing label, since both
+/// branches of an `if` assign the same temporary, or the one stored by a
+/// trailing spill.
+pub fn result_temp(code: &[Instr]) -> Option<usize> {
+    let last = code
+        .iter()
+        .rfind(|instr| !matches!(instr.op, Op::Label(_)))?;
+    match (&last.op, last.dst, last.arg1) {
+        (Op::Spill(_), _, Some(Operand::Temp(n))) | (_, Some(Operand::Temp(n)), _) => Some(n),
+        _ => None,
+    }
+}
+
+/// Constant folding: operations and built-in calls whose operands are all
+/// constants are replaced by their value.
+pub fn fold_constants(code: &[Instr]) -> Vec<Instr> {
+    rewrite_values(code, |instr, out| match &instr.op {
+        Op::Call { name, argc } => fold_call(out, name, *argc),
+        _ => fold(instr),
+    })
+}
+
+/// Algebraic simplification: identities such as `x * 1 → x` and
+/// `-(-x) → x`. Strict mode skips the ones that fail for NaN, infinities
+/// or `-0`.
+pub fn simplify_algebra(code: &[Instr], mode: FloatMode) -> Vec<Instr> {
+    rewrite_values(code, |instr, out| simplify(instr, out, mode))
+}
+
+/// Temporaries whose assignments the local passes must not drop: those
+/// from `escaping_temps`, the program's `result_temp`, and the results of
+/// expression statements, which nothing reads.
+pub fn kept_temps(code: &[Instr]) -> HashSet<usize> {
+    let read: HashSet<usize> = code
+        .iter()
+        .flat_map(Instr::uses)
+        .filter_map(|operand| match operand {
+            Operand::Temp(n) => Some(n),
+            _ => None,
+        })
+        .collect();
+    let mut kept = escaping_temps(code);
+    kept.extend(result_temp(code));
+    kept.extend(code.iter().filter_map(|instr| match instr.dst {
+        Some(Operand::Temp(n)) if !read.contains(&n) => Some(n),
+        _ => None,
+    }));
+    kept
+}
+
+/// One forward pass that asks `value_of` for the value of every
+/// instruction. A temporary whose value is known is dropped and its uses
+/// are rewritten, except for the `kept_temps`.
+fn rewrite_values(
+    code: &[Instr],
+    mut value_of: impl FnMut(&Instr, &mut Vec<Instr>) -> Option<Operand>,
+) -> Vec<Instr> {
+    let kept = kept_temps(code);
+    let mut values: HashMap<usize, Operand> = HashMap::new();
+    let mut out: Vec<Instr> = vec![];
+
+    for instr in code {
+        let mut instr = instr.clone();
+        let resolve = |operand: Option<Operand>| match operand {
+            Some(Operand::Temp(n)) => Some(values.get(&n).copied().unwrap_or(Operand::Temp(n))),
+            other => other,
+        };
+        instr.arg1 = resolve(instr.arg1);
+        instr.arg2 = resolve(instr.arg2);
+
+        let value = value_of(&instr, &mut out);
+
+        match (instr.dst, value) {
+            (Some(Operand::Temp(n)), Some(value)) if !kept.contains(&n) => {
+                values.insert(n, value);
+                continue;
+            }
+            (Some(dst), Some(value)) => instr = Instr::copy(dst, value),
+            _ => {}
+        }
+
+        // A temporary standing for this variable must keep the old value,
+        // so it is materialized before the variable changes
+        if let Some(dst @ Operand::Var(_)) = instr.dst {
+            let mut stale: Vec<usize> = values
+                .iter()
+                .filter(|(_, value)| **value == dst)
+                .map(|(n, _)| *n)
+                .collect();
+            stale.sort_unstable();
+            for n in stale {
+                values.remove(&n);
+                out.push(Instr::copy(Operand::Temp(n), dst));
+            }
+        }
+        out.push(instr);
+    }
+
+    remove_dead_temps(code, out)
+}
+
+/// Drops instructions whose temporary was read in `original` but no
+/// longer is, e.g. the inner negation of `-(-x)`. Calls stay, since
+/// removing one would strand its params.
+pub fn remove_dead_temps(original: &[Instr], mut code: Vec<Instr>) -> Vec<Instr> {
+    let read_temps = |code: &[Instr]| -> HashSet<usize> {
+        code.iter()
+            .flat_map(Instr::uses)
+            .filter_map(|operand| match operand {
+                Operand::Temp(n) => Some(n),
+                _ => None,
+            })
+            .collect()
+    };
+    let originally_read = read_temps(original);
+
+    loop {
+        let read = read_temps(&code);
+        let before = code.len();
+        code.retain(|instr| match (instr.dst, &instr.op) {
+            (_, Op::Call { .. }) => true,
+            (Some(Operand::Temp(n)), _) => read.contains(&n) || !originally_read.contains(&n),
+            _ => true,
+        });
+        if code.len() == before {
+            return code;
+        }
+    }
+}
+
+/// Folds a built-in call whose parameters, the last `argc` instructions
+/// emitted, are all constants. The params are removed when it folds.
+fn fold_call(out: &mut Vec<Instr>, name: &str, argc: usize) -> Option<Operand> {
+    let builtin = builtins::lookup(name).filter(|b| b.arity == argc)?;
+    let start = out.len().checked_sub(argc)?;
+    let values: Vec<f64> = out[start..]
+        .iter()
+        .map(|instr| match (&instr.op, instr.arg1) {
+            (Op::Param, Some(Operand::Const(c))) => Some(c),
+            _ => None,
+        })
+        .collect::<Option<_>>()?;
+    out.truncate(start);
+    Some(Operand::Const((builtin.eval)(&values)))
+}
+
+/// Value of an operation on constants.
+fn fold(instr: &Instr) -> Option<Operand> {
+    let a = instr.arg1?;
+    let b = instr.arg2.unwrap_or(Operand::Const(0.0));
+    match (a, b) {
+        (Operand::Const(x), Operand::Const(y)) => instr.op.fold(x, y).map(Operand::Const),
+        _ => None,
+    }
+}
+
+/// The operand an instruction reduces to by an identity, if any.
+fn simplify(instr: &Instr, out: &[Instr], mode: FloatMode) -> Option<Operand> {
+    let a = instr.arg1?;
+    let b = instr.arg2.unwrap_or(Operand::Const(0.0));
+    let fast = mode == FloatMode::FastMath;
+
+    let is = |operand: Operand, c: f64| operand == Operand::Const(c);
+    match instr.op {
+        Op::Copy => Some(a),
+        // x - 0, x * 1, x / 1, x ^ 1 = x
+        Op::Sub if is(b, 0.0) => Some(a),
+        Op::Mul | Op::Div | Op::Pow if is(b, 1.0) => Some(a),
+        // 1 * x = x
+        Op::Mul if is(a, 1.0) => Some(b),
+        // x + 0, 0 + x = x, except that -0 + 0 is +0
+        Op::Add if fast && is(b, 0.0) => Some(a),
+        Op::Add if fast && is(a, 0.0) => Some(b),
+        // x * 0 = 0, except for NaN, infinities and negative x
+        Op::Mul if fast && (is(a, 0.0) || is(b, 0.0)) => Some(Operand::Const(0.0)),
+        // x ^ 0 = 1, even for NaN
+        Op::Pow if is(b, 0.0) => Some(Operand::Const(1.0)),
+        // -(-x) = x
+        Op::Neg => out.iter().rev().find_map(|prev| match prev {
+            Instr {
+                dst: Some(dst),
+                op: Op::Neg,
+                arg1: Some(inner),
+                ..
+            } if *dst == a => Some(*inner),
+            _ => None,
+        }),
+        _ => None,
+    }
+}
+
+/// Rewrites every `sqrt(x)` as `x ^ 0.5`. They differ for `-0` and `-inf`,
+/// so strict mode leaves the code alone.
+pub fn lower_sqrt(code: &[Instr], mode: FloatMode) -> Vec<Instr> {
+    if mode == FloatMode::Strict {
+        return code.to_vec();
+    }
+    code.iter()
+        .map(|instr| match (instr.op.clone(), instr.dst, instr.arg1) {
+            (Op::Sqrt, Some(dst), Some(arg)) => {
+                Instr::binary(dst, Op::Pow, arg, Operand::Const(0.5))
+            }
+            _ => instr.clone(),
+        })
+        .collect()
+}
+
+/// Merges `tN = expr; v = tN` into `v = expr` when `tN` has no other use.
+pub fn coalesce_temps(code: &[Instr]) -> Vec<Instr> {
+    let mut use_counts: HashMap<usize, usize> = HashMap::new();
+    for operand in code.iter().flat_map(Instr::uses) {
+        if let Operand::Temp(n) = operand {
+            *use_counts.entry(n).or_default() += 1;
+        }
+    }
+
+    let escaping = escaping_temps(code);
+
+    let mut out: Vec<Instr> = vec![];
+    for instr in code {
+        if let (Op::Copy, Some(Operand::Temp(n))) = (&instr.op, instr.arg1)
+            && use_counts.get(&n) == Some(&1)
+            && !escaping.contains(&n)
+            && let Some(prev) = out.last_mut()
+            && prev.dst == Some(Operand::Temp(n))
+        {
+            prev.dst = instr.dst;
+            continue;
+        }
+        out.push(instr.clone());
+    }
+    out
+}
+
+/// Numbers the remaining temporaries `t1, t2, …` in order of definition.
+pub fn renumber_temps(code: &[Instr]) -> Vec<Instr> {
+    let mut numbers: HashMap<usize, usize> = HashMap::new();
+    let mut rename = |operand: Option<Operand>| match operand {
+        Some(Operand::Temp(n)) => {
+            let next = numbers.len() + 1;
+            Some(Operand::Temp(*numbers.entry(n).or_insert(next)))
+        }
+        other => other,
+    };
+    code.iter()
+        .map(|instr| Instr {
+            arg1: rename(instr.arg1),
+            arg2: rename(instr.arg2),
+            dst: rename(instr.dst),
+            op: instr.op.clone(),
+        })
+        .collect()
+}
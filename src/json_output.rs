@@ -0,0 +1,134 @@
+use crate::bytecode::Chunk;
+use crate::diagnostics::Diagnostic;
+use crate::ir::Instr;
+use crate::parser::ParseResult;
+use crate::tables;
+use crate::x86::Assembly;
+
+/// Renders the diagnostics and generated code of `result` as a JSON
+/// document for other tools. Code fields are empty when compilation
+/// stopped at an error.
+pub fn render(result: &ParseResult) -> String {
+    let diagnostics: Vec<String> = result
+        .errors
+        .iter()
+        .map(Diagnostic::from)
+        .chain(result.semantic_diagnostics.iter().cloned())
+        .map(|d| {
+            object(&[
+                ("severity", string(&d.severity.to_string())),
+                ("code", string(d.code)),
+                ("message", string(&d.message)),
+                ("line", d.span.line.to_string()),
+                ("column", d.span.column.to_string()),
+            ])
+        })
+        .collect();
+
+    let quadruples: Vec<String> = tables::quadruples(&result.three_address_code)
+        .iter()
+        .map(|q| {
+            object(&[
+                ("op", string(&q.op)),
+                ("arg1", string(&q.arg1)),
+                ("arg2", string(&q.arg2)),
+                ("result", string(&q.result)),
+            ])
+        })
+        .collect();
+
+    let indirect = tables::indirect_triples(&result.three_address_code);
+    let triples: Vec<String> = indirect
+        .triples
+        .iter()
+        .map(|t| {
+            object(&[
+                ("op", string(&t.op)),
+                ("arg1", string(&t.arg1)),
+                ("arg2", string(&t.arg2)),
+            ])
+        })
+        .collect();
+    let statements: Vec<String> = indirect
+        .statements
+        .iter()
+        .enumerate()
+        .map(|(i, pos)| object(&[("statement", i.to_string()), ("triple", pos.to_string())]))
+        .collect();
+
+    let mut out = object(&[
+        ("source", string(&result.source)),
+        ("diagnostics", array(&diagnostics)),
+        ("three_address_code", code(&result.three_address_code)),
+        ("quadruples", array(&quadruples)),
+        ("triples", array(&triples)),
+        ("indirect_triples", array(&statements)),
+        (
+            "optimized_three_address_code",
+            code(&result.optimized_three_address_code),
+        ),
+        ("assembly", assembly(&result.assembly)),
+        ("c", string(&result.c_source.to_string())),
+        ("bytecode", bytecode(&result.bytecode)),
+        ("wat", string(&result.wasm.to_wat())),
+    ]);
+    out.push('\n');
+    out
+}
+
+fn code(code: &[Instr]) -> String {
+    let lines: Vec<String> = code
+        .iter()
+        .map(|instr| string(&instr.to_string()))
+        .collect();
+    array(&lines)
+}
+
+fn assembly(assembly: &Assembly) -> String {
+    let lines: Vec<String> = assembly
+        .lines
+        .iter()
+        .map(|line| string(&line.text))
+        .collect();
+    array(&lines)
+}
+
+fn bytecode(chunk: &Chunk) -> String {
+    let lines: Vec<String> = chunk
+        .instructions()
+        .into_iter()
+        .map(|(offset, instruction)| string(&chunk.disassemble(offset, instruction)))
+        .collect();
+    array(&lines)
+}
+
+fn object(fields: &[(&str, String)]) -> String {
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|(key, value)| format!("{}: {}", string(key), value))
+        .collect();
+    format!("{{{}}}", fields.join(", "))
+}
+
+fn array(items: &[String]) -> String {
+    format!("[{}]", items.join(", "))
+}
+
+/// JSON string literal with the required characters escaped.
+fn string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for ch in s.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            ch if (ch as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => out.push(ch),
+        }
+    }
+    out.push('"');
+    out
+}
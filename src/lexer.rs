@@ -1,12 +1,16 @@
 use crate::error::ParseError;
-use crate::token::Token;
+use crate::span::Span;
+use crate::token::{Token, TokenKind};
 use std::collections::HashMap;
 
 pub struct Lexer {
     input: Vec<char>,
     pos: usize,
+    line: usize,
+    column: usize,
     identifier_map: HashMap<String, usize>,
     next_id: usize,
+    errors: Vec<ParseError>,
 }
 
 impl Lexer {
@@ -14,8 +18,11 @@ impl Lexer {
         Self {
             input: input.chars().collect(),
             pos: 0,
+            line: 1,
+            column: 1,
             identifier_map: HashMap::new(),
             next_id: 1,
+            errors: vec![],
         }
     }
 
@@ -37,12 +44,43 @@ impl Lexer {
     fn advance(&mut self) -> Option<char> {
         let ch = self.peek()?;
         self.pos += 1;
+        if ch == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
         Some(ch)
     }
 
+    /// First character from the current position that isn't blank; newlines
+    /// count as non-blank since they end the statement.
+    fn next_non_blank(&self) -> Option<char> {
+        self.input[self.pos..]
+            .iter()
+            .copied()
+            .find(|ch| !ch.is_whitespace() || *ch == '\n')
+    }
+
+    /// Zero-width span at the current position.
+    fn current_span(&self) -> Span {
+        Span::new(self.pos, self.pos, self.line, self.column)
+    }
+
+    /// Span from `start` up to the current position.
+    fn span_from(&self, start: Span) -> Span {
+        Span {
+            end: self.pos,
+            end_line: self.line,
+            end_column: self.column,
+            ..start
+        }
+    }
+
+    /// Skips whitespace except newlines, which separate statements.
     fn skip_whitespace(&mut self) {
         while let Some(ch) = self.peek() {
-            if ch.is_whitespace() {
+            if ch.is_whitespace() && ch != '\n' {
                 self.advance();
             } else {
                 break;
@@ -50,7 +88,8 @@ impl Lexer {
         }
     }
 
-    fn read_number(&mut self) -> Result<f64, ParseError> {
+    fn read_number(&mut self) -> TokenKind {
+        let start = self.current_span();
         let mut num_str = String::new();
         while let Some(ch) = self.peek() {
             if ch.is_numeric() || ch == '.' {
@@ -60,9 +99,16 @@ impl Lexer {
                 break;
             }
         }
-        num_str
-            .parse()
-            .map_err(|_| ParseError::InvalidNumber(num_str))
+        match num_str.parse() {
+            Ok(n) => TokenKind::Number(n),
+            Err(_) => {
+                self.errors.push(ParseError::InvalidNumber(
+                    num_str.clone(),
+                    self.span_from(start),
+                ));
+                TokenKind::Error(num_str)
+            }
+        }
     }
 
     fn read_identifier(&mut self) -> String {
@@ -78,71 +124,96 @@ impl Lexer {
         id
     }
 
-    fn next_token(&mut self) -> Result<Option<Token>, ParseError> {
+    fn next_token(&mut self) -> Option<Token> {
         self.skip_whitespace();
 
-        let ch = match self.peek() {
-            Some(c) => c,
-            None => return Ok(None),
-        };
+        let start = self.current_span();
+        let ch = self.peek()?;
 
-        let token = match ch {
+        let kind = match ch {
             '+' => {
                 self.advance();
-                Token::Plus
+                TokenKind::Plus
             }
             '-' => {
                 self.advance();
-                Token::Minus
+                TokenKind::Minus
             }
             '*' => {
                 self.advance();
-                Token::Multiply
+                TokenKind::Multiply
             }
             '/' => {
                 self.advance();
-                Token::Divide
+                TokenKind::Divide
             }
             '^' => {
                 self.advance();
-                Token::Power
+                TokenKind::Power
             }
             '(' => {
                 self.advance();
-                Token::LParen
+                TokenKind::LParen
             }
             ')' => {
                 self.advance();
-                Token::RParen
+                TokenKind::RParen
             }
             '=' => {
                 self.advance();
-                Token::Assign
+                TokenKind::Assign
             }
-            ch if ch.is_numeric() => Token::Number(self.read_number()?),
+            ',' => {
+                self.advance();
+                TokenKind::Comma
+            }
+            ';' => {
+                self.advance();
+                TokenKind::Semicolon
+            }
+            '\n' => {
+                self.advance();
+                TokenKind::Newline
+            }
+            ch if ch.is_numeric() => self.read_number(),
             ch if ch.is_alphabetic() || ch == '_' => {
                 let name = self.read_identifier();
 
-                // Check if it's the sqrt keyword
-                if name.to_lowercase() == "sqrt" {
-                    return Ok(Some(Token::Sqrt));
+                // A name directly followed by `(` is a function call; it
+                // doesn't get an entry in the identifier table. `sqrt` is
+                // a keyword that also works as a prefix operator, `sqrt x`
+                if self.next_non_blank() == Some('(') || name.to_lowercase() == "sqrt" {
+                    TokenKind::Function(name)
+                } else {
+                    let idx = self.get_identifier_index(&name);
+                    TokenKind::Identifier(name, idx)
                 }
-
-                let idx = self.get_identifier_index(&name);
-                Token::Identifier(name, idx)
             }
-            _ => return Err(ParseError::InvalidToken(ch.to_string())),
+            _ => {
+                // Report and skip characters the language doesn't know
+                self.advance();
+                self.errors.push(ParseError::InvalidToken(
+                    ch.to_string(),
+                    self.span_from(start),
+                ));
+                return self.next_token();
+            }
         };
 
-        Ok(Some(token))
+        Some(Token::new(kind, self.span_from(start)))
     }
 
-    pub fn tokenize(&mut self) -> Result<Vec<Token>, ParseError> {
+    pub fn tokenize(&mut self) -> Vec<Token> {
         let mut tokens = Vec::new();
-        while let Some(token) = self.next_token()? {
+        while let Some(token) = self.next_token() {
             tokens.push(token);
         }
-        Ok(tokens)
+        tokens
+    }
+
+    /// Returns the lexical errors recorded so far.
+    pub fn take_errors(&mut self) -> Vec<ParseError> {
+        std::mem::take(&mut self.errors)
     }
 
     pub fn into_identifier_table(self) -> Vec<(String, usize)> {
@@ -0,0 +1,221 @@
+use crate::cfg::ControlFlowGraph;
+use crate::ir::{Instr, Name, Op, Operand};
+use std::collections::{BTreeSet, HashSet};
+
+/// Names live before and after every instruction: those whose current
+/// value may still be read on some path.
+#[derive(Debug, Clone, Default)]
+pub struct Liveness {
+    pub live_in: Vec<BTreeSet<Name>>,
+    pub live_out: Vec<BTreeSet<Name>>,
+}
+
+impl Liveness {
+    /// Backward dataflow analysis over the control-flow graph of `code`;
+    /// `exit` is live at the end of the program.
+    pub fn analyze(code: &[Instr], exit: &BTreeSet<Name>) -> Self {
+        let cfg = ControlFlowGraph::build(code);
+
+        // Live-in of every block, iterated to a fixpoint
+        let mut block_in: Vec<BTreeSet<Name>> = vec![BTreeSet::new(); cfg.blocks.len()];
+        let block_out = |block_in: &[BTreeSet<Name>], b: usize| -> BTreeSet<Name> {
+            if cfg.successors[b].is_empty() {
+                return exit.clone();
+            }
+            cfg.successors[b]
+                .iter()
+                .flat_map(|succ| block_in[*succ].iter().copied())
+                .collect()
+        };
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for b in (0..cfg.blocks.len()).rev() {
+                let mut live = block_out(&block_in, b);
+                for instr in cfg.blocks[b].code.iter().rev() {
+                    transfer(instr, &mut live);
+                }
+                if live != block_in[b] {
+                    block_in[b] = live;
+                    changed = true;
+                }
+            }
+        }
+
+        let mut liveness = Liveness {
+            live_in: vec![BTreeSet::new(); code.len()],
+            live_out: vec![BTreeSet::new(); code.len()],
+        };
+        for (b, block) in cfg.blocks.iter().enumerate() {
+            let mut live = block_out(&block_in, b);
+            for (i, instr) in block.code.iter().enumerate().rev() {
+                liveness.live_out[block.start + i] = live.clone();
+                transfer(instr, &mut live);
+                liveness.live_in[block.start + i] = live.clone();
+            }
+        }
+        liveness
+    }
+}
+
+/// `live` before `instr`, given `live` after it.
+fn transfer(instr: &Instr, live: &mut BTreeSet<Name>) {
+    if let Some(name) = instr.dst.and_then(Operand::name) {
+        live.remove(&name);
+    }
+    live.extend(instr.uses().filter_map(Operand::name));
+}
+
+/// Names live at the end of the program: the variables in `live_vars`, or
+/// every variable when it is `None`, and the temporaries that are never
+/// read, which hold the values of expression statements.
+pub fn exit_names(code: &[Instr], live_vars: Option<&HashSet<usize>>) -> BTreeSet<Name> {
+    let read: HashSet<Name> = code
+        .iter()
+        .flat_map(Instr::uses)
+        .filter_map(Operand::name)
+        .collect();
+    code.iter()
+        .flat_map(|instr| instr.dst.into_iter().chain(instr.uses()))
+        .filter_map(Operand::name)
+        .filter(|name| match name {
+            Name::Var(v) => live_vars.is_none_or(|vars| vars.contains(v)),
+            Name::Temp(_) => !read.contains(name),
+        })
+        .collect()
+}
+
+/// Variables read before they are assigned on some path, by symbol table
+/// index: the inputs of a program.
+pub fn inputs(code: &[Instr]) -> Vec<usize> {
+    Liveness::analyze(code, &BTreeSet::new())
+        .live_in
+        .first()
+        .into_iter()
+        .flatten()
+        .filter_map(|name| match name {
+            Name::Var(n) => Some(*n),
+            Name::Temp(_) => None,
+        })
+        .collect()
+}
+
+/// Removes assignments whose value is never read afterwards, repeating
+/// until none are left, since removing one can make the assignments it
+/// reads dead too. A dead call takes its params with it.
+pub fn eliminate_dead_assignments(
+    code: &[Instr],
+    live_vars: Option<&HashSet<usize>>,
+) -> Vec<Instr> {
+    let exit = exit_names(code, live_vars);
+    let mut code = code.to_vec();
+
+    loop {
+        let liveness = Liveness::analyze(&code, &exit);
+        let mut dead = vec![false; code.len()];
+        let mut dead_params = 0;
+        for (i, instr) in code.iter().enumerate().rev() {
+            if instr.op == Op::Param && dead_params > 0 {
+                dead[i] = true;
+                dead_params -= 1;
+            } else if let Some(name) = instr.dst.and_then(Operand::name)
+                && !liveness.live_out[i].contains(&name)
+            {
+                dead[i] = true;
+                if let Op::Call { argc, .. } = instr.op {
+                    dead_params = argc;
+                }
+            }
+        }
+
+        if !dead.contains(&true) {
+            return code;
+        }
+        code = code
+            .into_iter()
+            .zip(dead)
+            .filter(|(_, dead)| !dead)
+            .map(|(instr, _)| instr)
+            .collect();
+    }
+}
+
+/// `{id1, t2}`
+pub fn format_set(names: &BTreeSet<Name>) -> String {
+    let names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+    format!("{{{}}}", names.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{code, listing};
+
+    /// Live-in and live-out of every instruction, every variable live at
+    /// the end.
+    fn sets(input: &str) -> Vec<(String, String)> {
+        let code = code(input);
+        let liveness = Liveness::analyze(&code, &exit_names(&code, None));
+        liveness
+            .live_in
+            .iter()
+            .zip(&liveness.live_out)
+            .map(|(live_in, live_out)| (format_set(live_in), format_set(live_out)))
+            .collect()
+    }
+
+    fn pairs(sets: &[(&str, &str)]) -> Vec<(String, String)> {
+        sets.iter()
+            .map(|(live_in, live_out)| (live_in.to_string(), live_out.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn straight_line_sets() {
+        // t1 = id2 + 1; id1 = t1; t2 = id1 * 2; id3 = t2
+        assert_eq!(
+            sets("a = x + 1; b = a * 2"),
+            pairs(&[
+                ("{id2}", "{id2, t1}"),
+                ("{id2, t1}", "{id1, id2}"),
+                ("{id1, id2}", "{id1, id2, t2}"),
+                ("{id1, id2, t2}", "{id1, id2, id3}"),
+            ])
+        );
+    }
+
+    #[test]
+    fn sets_flow_over_both_branches() {
+        let sets = sets("a = if(c, x, y); a");
+        // ifFalse id2 goto L1 needs the condition and both branches' inputs
+        assert_eq!(sets[0].0, "{id2, id3, id4}");
+        // goto L2 carries the then value to the join
+        assert_eq!(sets[2].1, "{id2, id3, id4, t1}");
+        // The result temp is live at the end, as nothing reads it
+        assert_eq!(sets.last().unwrap().1, "{id1, id2, id3, id4, t2}");
+    }
+
+    #[test]
+    fn inputs_are_read_before_assignment() {
+        assert_eq!(inputs(&code("a = 1; b = a + x")), [3]);
+        assert_eq!(inputs(&code("a = if(c, x, y); a")), [2, 3, 4]);
+        assert!(inputs(&code("a = 1; a * 2")).is_empty());
+    }
+
+    #[test]
+    fn dead_assignments_are_removed() {
+        let code = code("a = max(x, 1); b = 2; a = 3; x + 1");
+        // Only x (id2) is needed afterwards; the dead call takes its params
+        assert_eq!(
+            listing(&eliminate_dead_assignments(
+                &code,
+                Some(&HashSet::from([2]))
+            )),
+            ["t2 = id2 + 1"]
+        );
+        assert_eq!(
+            listing(&eliminate_dead_assignments(&code, None)),
+            ["id3 = 2", "id1 = 3", "t2 = id2 + 1"]
+        );
+    }
+}
@@ -1,12 +1,42 @@
 mod ast;
+mod builtins;
+mod bytecode;
+mod c_source;
+mod cfg;
+mod dag;
+mod diagnostics;
+mod environment;
 mod error;
+mod ir;
+mod json_output;
 mod lexer;
+mod liveness;
+mod options;
 mod parser;
+mod passes;
+mod propagation;
+mod regalloc;
+mod span;
+mod ssa;
+mod strength;
+mod tables;
+#[cfg(test)]
+mod test_support;
+mod text_output;
 mod token;
 mod tree_view;
 mod ui;
+mod vm;
+mod wasm;
+mod x86;
 
 use eframe::egui;
+use environment::Environment;
+use options::{CompileOptions, FloatMode};
+use parser::ParseResult;
+use passes::Pass;
+use std::io::{Read, Write};
+use strength::Rule;
 use ui::ExpressionParserApp;
 
 const WINDOW_WIDTH: f32 = 900.0;
@@ -15,6 +45,124 @@ const MIN_WINDOW_WIDTH: f32 = 600.0;
 const MIN_WINDOW_HEIGHT: f32 = 400.0;
 
 fn main() -> Result<(), eframe::Error> {
+    // `--text [FLAGS] [EXPR]` prints every phase to stdout instead of opening
+    // the GUI, `--json` the generated code as JSON, `--asm` the x86-64
+    // assembly alone, `--c` the C source and `--wat`/`--wasm` the
+    // WebAssembly module; the expression is read from stdin when not given
+    // on the command line. Variables get values with `--set=x=3`.
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mode = args.first().map(String::as_str);
+    if matches!(
+        mode,
+        Some("--text" | "--json" | "--asm" | "--c" | "--wat" | "--wasm")
+    ) {
+        let (flags, rest): (Vec<&String>, Vec<&String>) =
+            args[1..].iter().partition(|arg| arg.starts_with("--"));
+
+        let mut compile_options = CompileOptions::default();
+        let mut env = Environment::new();
+        for flag in flags {
+            match flag.as_str() {
+                "--lower-sqrt" => compile_options.set_enabled(Pass::SqrtLowering, true),
+                "--ssa" => compile_options.set_enabled(Pass::Ssa, true),
+                "--fixpoint" => compile_options.fixpoint = true,
+                "--fast-math" => compile_options.float_mode = FloatMode::FastMath,
+                // `--disable-pass=NAME` skips one optimization pass
+                name if let Some(pass) = name
+                    .strip_prefix("--disable-pass=")
+                    .and_then(Pass::from_name) =>
+                {
+                    compile_options.set_enabled(pass, false);
+                }
+                // `--passes=a,b` runs exactly those passes, in that order
+                names
+                    if let Some(order) = names.strip_prefix("--passes=").and_then(|names| {
+                        names
+                            .split(',')
+                            .map(|name| Pass::from_name(name.trim()))
+                            .collect::<Option<Vec<Pass>>>()
+                    }) =>
+                {
+                    compile_options.set_order(&order);
+                }
+                // `--disable-rule=NAME` switches off one strength reduction
+                name if let Some(rule) = name
+                    .strip_prefix("--disable-rule=")
+                    .and_then(Rule::from_name) =>
+                {
+                    compile_options.disabled_rules.insert(rule);
+                }
+                // `--live-out=a,b` keeps only those variables live at the end
+                names if let Some(names) = names.strip_prefix("--live-out=") => {
+                    compile_options.live_out = Some(
+                        names
+                            .split(',')
+                            .map(str::trim)
+                            .filter(|name| !name.is_empty())
+                            .map(String::from)
+                            .collect(),
+                    );
+                }
+                // `--registers=N` colors the temporaries with N registers
+                count
+                    if let Some(count) = count
+                        .strip_prefix("--registers=")
+                        .and_then(|n| n.parse::<usize>().ok())
+                        .filter(|n| (2..=x86::REGISTERS).contains(n)) =>
+                {
+                    compile_options.registers = count;
+                }
+                // `--set=NAME=VALUE` gives a variable its initial value
+                binding
+                    if let Some((name, value)) = binding
+                        .strip_prefix("--set=")
+                        .and_then(|b| b.split_once('='))
+                        && let Ok(value) = value.trim().parse() =>
+                {
+                    env.set(name.trim(), value);
+                }
+                _ => {
+                    eprintln!("unknown flag: {}", flag);
+                    std::process::exit(2);
+                }
+            }
+        }
+
+        let input = if !rest.is_empty() {
+            rest.iter()
+                .map(|arg| arg.as_str())
+                .collect::<Vec<_>>()
+                .join(" ")
+        } else {
+            let mut buf = String::new();
+            std::io::stdin()
+                .read_to_string(&mut buf)
+                .expect("failed to read stdin");
+            buf
+        };
+
+        let result = ParseResult::from_input(&input, &compile_options);
+        if mode == Some("--json") {
+            print!("{}", json_output::render(&result));
+        } else if mode == Some("--asm") {
+            print!("{}", result.assembly);
+        } else if mode == Some("--c") {
+            print!("{}", result.c_source);
+        } else if mode == Some("--wat") {
+            print!("{}", result.wasm);
+        } else if mode == Some("--wasm") {
+            std::io::stdout()
+                .write_all(&result.wasm.to_wasm())
+                .expect("failed to write stdout");
+        } else {
+            print!("{}", text_output::render(&result, env));
+        }
+        if result.has_errors() {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
             .with_inner_size([WINDOW_WIDTH, WINDOW_HEIGHT])
@@ -0,0 +1,89 @@
+use crate::passes::Pass;
+use crate::strength::Rule;
+use crate::x86;
+use std::collections::HashSet;
+
+/// Which algebraic rewrites the optimizer may use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FloatMode {
+    /// Only rewrites that give the same IEEE-754 result for every input,
+    /// NaN, infinities and signed zeros included.
+    #[default]
+    Strict,
+    /// Also rewrites that hold for real numbers, such as `x * 0 → 0`.
+    FastMath,
+}
+
+impl FloatMode {
+    pub fn label(self) -> &'static str {
+        match self {
+            FloatMode::Strict => "Strict IEEE-754",
+            FloatMode::FastMath => "Fast-math",
+        }
+    }
+}
+
+/// User-selectable switches for the compiler pipeline.
+#[derive(Debug, Clone)]
+pub struct CompileOptions {
+    /// Every optimization pass in the order it runs, and whether it is
+    /// enabled.
+    pub passes: Vec<(Pass, bool)>,
+    /// Repeat the passes until they stop changing the code.
+    pub fixpoint: bool,
+    pub float_mode: FloatMode,
+    /// Variables whose values are needed after the program ends; `None`
+    /// keeps every variable live.
+    pub live_out: Option<Vec<String>>,
+    /// Strength reduction rules that are switched off.
+    pub disabled_rules: HashSet<Rule>,
+    /// Registers the register allocator may use, from 2 to
+    /// `x86::REGISTERS`.
+    pub registers: usize,
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        Self {
+            passes: Pass::ALL
+                .into_iter()
+                .map(|pass| (pass, pass.enabled_by_default()))
+                .collect(),
+            fixpoint: false,
+            float_mode: FloatMode::default(),
+            live_out: None,
+            disabled_rules: HashSet::new(),
+            registers: x86::REGISTERS,
+        }
+    }
+}
+
+impl CompileOptions {
+    pub fn enabled_passes(&self) -> impl Iterator<Item = Pass> + '_ {
+        self.passes
+            .iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(pass, _)| *pass)
+    }
+
+    pub fn set_enabled(&mut self, pass: Pass, enabled: bool) {
+        for (p, on) in &mut self.passes {
+            if *p == pass {
+                *on = enabled;
+            }
+        }
+    }
+
+    /// Runs exactly `order`, in that order; the other passes are disabled.
+    pub fn set_order(&mut self, order: &[Pass]) {
+        let rest: Vec<Pass> = Pass::ALL
+            .into_iter()
+            .filter(|pass| !order.contains(pass))
+            .collect();
+        self.passes = order
+            .iter()
+            .map(|pass| (*pass, true))
+            .chain(rest.into_iter().map(|pass| (pass, false)))
+            .collect();
+    }
+}
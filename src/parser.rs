@@ -1,282 +1,572 @@
-use crate::ast::ASTNode;
+use crate::ast::{ASTNode, Program};
+use crate::bytecode::Chunk;
+use crate::c_source::CSource;
+use crate::cfg::ControlFlowGraph;
+use crate::dag::Dag;
+use crate::diagnostics::{Diagnostic, Severity};
 use crate::error::ParseError;
-use crate::token::Token;
-
+use crate::ir::Instr;
+use crate::liveness::{self, Liveness};
+use crate::options::{CompileOptions, FloatMode};
+use crate::passes::{self, PassRun};
+use crate::regalloc::{self, Allocation};
+use crate::span::Span;
+use crate::ssa::SsaProgram;
+use crate::token::{Token, TokenKind};
+use crate::wasm::WasmModule;
+use crate::x86::Assembly;
+use std::collections::HashSet;
+
+/// Recursive-descent parser with panic-mode error recovery.
+///
+/// Syntax errors are collected instead of aborting the parse: a missing
+/// operand becomes an `ASTNode::Error` placeholder, a missing `)` is assumed
+/// to be present, and stray tokens are skipped up to the next statement
+/// separator (`;` or newline).
 pub struct Parser {
     tokens: Vec<Token>,
     pos: usize,
+    eof: Span,
+    paren_depth: usize,
+    errors: Vec<ParseError>,
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self {
-        Self { tokens, pos: 0 }
+        let eof = tokens
+            .last()
+            .map(|token| token.span.end_point())
+            .unwrap_or_default();
+        Self {
+            tokens,
+            pos: 0,
+            eof,
+            paren_depth: 0,
+            errors: vec![],
+        }
     }
 
-    fn peek(&self) -> Option<&Token> {
-        self.tokens.get(self.pos)
+    fn peek(&self) -> Option<&TokenKind> {
+        self.tokens.get(self.pos).map(|token| &token.kind)
     }
 
     fn advance(&mut self) -> Result<Token, ParseError> {
         self.tokens
             .get(self.pos)
             .cloned()
-            .ok_or(ParseError::UnexpectedEndOfInput)
+            .ok_or(ParseError::UnexpectedEndOfInput(self.eof))
             .inspect(|_| self.pos += 1)
     }
 
-    pub fn parse(&mut self) -> Result<ASTNode, ParseError> {
-        self.parse_assignment()
+    /// Returns the syntax errors recorded so far.
+    pub fn take_errors(&mut self) -> Vec<ParseError> {
+        std::mem::take(&mut self.errors)
+    }
+
+    pub fn parse(&mut self) -> Program {
+        let mut statements = vec![];
+
+        loop {
+            while self.peek().is_some_and(TokenKind::is_separator) {
+                self.pos += 1;
+            }
+            if self.peek().is_none() {
+                break;
+            }
+
+            statements.push(self.parse_statement());
+        }
+
+        if statements.is_empty() {
+            self.errors.push(ParseError::UnexpectedEndOfInput(self.eof));
+        }
+
+        Program { statements }
+    }
+
+    fn parse_statement(&mut self) -> ASTNode {
+        let statement = into_function_def(self.parse_assignment());
+
+        // A statement must be followed by a separator; report the first
+        // stray token and discard the rest of the statement
+        if let Some(token) = self.tokens.get(self.pos)
+            && !token.kind.is_separator()
+        {
+            self.errors
+                .push(ParseError::TrailingToken(token.kind.clone(), token.span));
+            while self.peek().is_some_and(|kind| !kind.is_separator()) {
+                self.pos += 1;
+            }
+        }
+
+        statement
     }
 
-    fn parse_assignment(&mut self) -> Result<ASTNode, ParseError> {
-        let left = self.parse_expr()?;
-
-        if matches!(self.peek(), Some(Token::Assign)) {
-            self.advance()?;
-            let right = self.parse_assignment()?;
-            return Ok(ASTNode::BinaryOp {
-                op: '=',
-                left: Box::new(left),
-                right: Box::new(right),
-            });
+    fn parse_assignment(&mut self) -> ASTNode {
+        let left = self.parse_expr();
+
+        if matches!(self.peek(), Some(TokenKind::Assign)) {
+            self.pos += 1;
+            let right = self.parse_assignment();
+            return binary('=', left, right);
         }
 
-        Ok(left)
+        left
     }
 
-    fn parse_expr(&mut self) -> Result<ASTNode, ParseError> {
+    fn parse_expr(&mut self) -> ASTNode {
         self.parse_add_sub()
     }
 
-    fn parse_add_sub(&mut self) -> Result<ASTNode, ParseError> {
-        let mut left = self.parse_mul_div()?;
+    fn parse_add_sub(&mut self) -> ASTNode {
+        let mut left = self.parse_mul_div();
 
         while let Some(token) = self.peek() {
             let op = match token {
-                Token::Plus => '+',
-                Token::Minus => '-',
+                TokenKind::Plus => '+',
+                TokenKind::Minus => '-',
                 _ => break,
             };
-            self.advance()?;
-            let right = self.parse_mul_div()?;
-            left = ASTNode::BinaryOp {
-                op,
-                left: Box::new(left),
-                right: Box::new(right),
-            };
+            self.pos += 1;
+            let right = self.parse_mul_div();
+            left = binary(op, left, right);
         }
 
-        Ok(left)
+        left
     }
 
-    fn parse_mul_div(&mut self) -> Result<ASTNode, ParseError> {
-        let mut left = self.parse_power()?;
+    fn parse_mul_div(&mut self) -> ASTNode {
+        let mut left = self.parse_unary();
 
         while let Some(token) = self.peek() {
             let op = match token {
-                Token::Multiply => '*',
-                Token::Divide => '/',
+                TokenKind::Multiply => '*',
+                TokenKind::Divide => '/',
                 _ => break,
             };
-            self.advance()?;
-            let right = self.parse_power()?;
-            left = ASTNode::BinaryOp {
-                op,
-                left: Box::new(left),
-                right: Box::new(right),
-            };
+            self.pos += 1;
+            let right = self.parse_unary();
+            left = binary(op, left, right);
         }
 
-        Ok(left)
+        left
     }
 
-    fn parse_power(&mut self) -> Result<ASTNode, ParseError> {
-        let mut left = self.parse_unary()?;
+    /// Prefix `-` and `+` bind looser than `^`, so `-2^2` is `-(2^2)`.
+    fn parse_unary(&mut self) -> ASTNode {
+        let op = match self.peek() {
+            Some(TokenKind::Minus) => "-",
+            Some(TokenKind::Plus) => "+",
+            _ => return self.parse_power(),
+        };
+        let op_span = self.tokens[self.pos].span;
+        self.pos += 1;
+
+        let operand = self.parse_unary();
+        let span = op_span.to(operand.span());
+        ASTNode::UnaryOp {
+            op: op.to_string(),
+            operand: Box::new(operand),
+            span,
+        }
+    }
 
-        if matches!(self.peek(), Some(Token::Power)) {
-            self.advance()?;
-            let right = self.parse_power()?;
-            left = ASTNode::BinaryOp {
-                op: '^',
-                left: Box::new(left),
-                right: Box::new(right),
-            };
+    fn parse_power(&mut self) -> ASTNode {
+        let mut left = self.parse_call();
+
+        if matches!(self.peek(), Some(TokenKind::Power)) {
+            self.pos += 1;
+            // The exponent may carry its own sign, as in `2^-1`
+            let right = self.parse_unary();
+            left = binary('^', left, right);
         }
 
-        Ok(left)
+        left
     }
 
-    fn parse_unary(&mut self) -> Result<ASTNode, ParseError> {
-        if matches!(self.peek(), Some(Token::Sqrt)) {
-            self.advance()?;
-            let operand = self.parse_primary()?;
-            // Transform sqrt(x) into x^0.5
-            return Ok(ASTNode::BinaryOp {
-                op: '^',
-                left: Box::new(operand),
-                right: Box::new(ASTNode::Number(0.5)),
-            });
+    /// Function calls `f(a, b, ...)`. The lexer only emits a function token
+    /// when the name is followed by `(`, or for `sqrt`; `sqrt` with a single
+    /// argument stays a unary operator, and may also prefix an operand
+    /// without parentheses, as in `sqrt 16` or `sqrt -x`, binding like
+    /// unary minus.
+    fn parse_call(&mut self) -> ASTNode {
+        let Some(TokenKind::Function(name)) = self.peek().cloned() else {
+            return self.parse_primary();
+        };
+        let name_span = self.tokens[self.pos].span;
+        self.pos += 1;
+
+        if !matches!(self.peek(), Some(TokenKind::LParen)) {
+            let operand = self.parse_unary();
+            let span = name_span.to(operand.span());
+            return ASTNode::UnaryOp {
+                op: "sqrt".to_string(),
+                operand: Box::new(operand),
+                span,
+            };
+        }
+
+        let lparen_span = self.tokens[self.pos].span;
+        self.pos += 1;
+        self.paren_depth += 1;
+
+        let mut args = vec![];
+        if !matches!(self.peek(), Some(TokenKind::RParen)) {
+            args.push(self.parse_expr());
+            while matches!(self.peek(), Some(TokenKind::Comma)) {
+                self.pos += 1;
+                args.push(self.parse_expr());
+            }
         }
-        self.parse_primary()
+
+        self.paren_depth -= 1;
+        if matches!(self.peek(), Some(TokenKind::RParen)) {
+            self.pos += 1;
+        } else {
+            self.errors
+                .push(ParseError::UnclosedParenthesis(lparen_span));
+        }
+        let span = name_span.to(self.tokens[self.pos - 1].span);
+
+        if name.to_lowercase() == "sqrt" && args.len() == 1 {
+            return ASTNode::UnaryOp {
+                op: "sqrt".to_string(),
+                operand: Box::new(args.remove(0)),
+                span,
+            };
+        }
+        ASTNode::Call { name, args, span }
     }
 
-    fn parse_primary(&mut self) -> Result<ASTNode, ParseError> {
-        match self.advance()? {
-            Token::Number(n) => Ok(ASTNode::Number(n)),
-            Token::Identifier(name, idx) => Ok(ASTNode::Identifier(name, idx)),
-            Token::LParen => {
-                let expr = self.parse_expr()?;
-                if matches!(self.peek(), Some(Token::RParen)) {
-                    self.advance()?;
-                }
-                Ok(expr)
+    fn parse_primary(&mut self) -> ASTNode {
+        let token = match self.advance() {
+            Ok(token) => token,
+            Err(err) => {
+                self.errors.push(err);
+                return ASTNode::Error(self.eof);
+            }
+        };
+
+        match token.kind {
+            TokenKind::Number(n) => ASTNode::Number(n, token.span),
+            TokenKind::Identifier(name, idx) => ASTNode::Identifier(name, idx, token.span),
+            // Already reported by the lexer
+            TokenKind::Error(_) => ASTNode::Error(token.span),
+            TokenKind::LParen => {
+                self.paren_depth += 1;
+                let expr = self.parse_expr();
+                self.paren_depth -= 1;
+                let end = if matches!(self.peek(), Some(TokenKind::RParen)) {
+                    self.pos += 1;
+                    self.tokens[self.pos - 1].span
+                } else {
+                    // Recover by assuming the `)` was there
+                    self.errors
+                        .push(ParseError::UnclosedParenthesis(token.span));
+                    expr.span()
+                };
+                // Diagnostics on the expression underline the parentheses too
+                let span = token.span.to(end);
+                expr.with_span(span)
+            }
+            // A `)` with no open parenthesis can never be matched, so drop it
+            TokenKind::RParen if self.paren_depth == 0 => {
+                self.errors
+                    .push(ParseError::UnexpectedToken(token.kind.clone(), token.span));
+                ASTNode::Error(token.span)
+            }
+            kind => {
+                // Missing operand: leave the token for the enclosing rule
+                self.pos -= 1;
+                self.errors
+                    .push(ParseError::UnexpectedToken(kind, token.span));
+                ASTNode::Error(token.span)
             }
-            token => Err(ParseError::UnexpectedToken(format!("{}", token))),
         }
     }
 }
 
+/// Turns a top-level `f(x, y) = body` assignment into a function definition.
+/// Anything else assigned to a call is left for semantic analysis to reject.
+fn into_function_def(statement: ASTNode) -> ASTNode {
+    let ASTNode::BinaryOp {
+        op: '=',
+        left,
+        right,
+        span,
+    } = statement
+    else {
+        return statement;
+    };
+
+    if let ASTNode::Call { name, args, .. } = &*left {
+        let params: Option<Vec<(String, usize)>> = args
+            .iter()
+            .map(|arg| match arg {
+                ASTNode::Identifier(param, idx, _) => Some((param.clone(), *idx)),
+                _ => None,
+            })
+            .collect();
+        if let Some(params) = params {
+            return ASTNode::FunctionDef {
+                name: name.clone(),
+                params,
+                body: right,
+                span,
+            };
+        }
+    }
+
+    ASTNode::BinaryOp {
+        op: '=',
+        left,
+        right,
+        span,
+    }
+}
+
+/// Builds a binary node whose span covers both operands.
+fn binary(op: char, left: ASTNode, right: ASTNode) -> ASTNode {
+    let span = left.span().to(right.span());
+    ASTNode::BinaryOp {
+        op,
+        left: Box::new(left),
+        right: Box::new(right),
+        span,
+    }
+}
+
 pub struct ParseResult {
+    pub source: String,
     pub tokens: Vec<Token>,
-    pub ast: ASTNode,
+    pub ast: Program,
     pub identifier_table: Vec<(String, usize)>,
-    pub semantic_warnings: Vec<String>,
-    pub three_address_code: Vec<String>,
-    pub optimized_ast: ASTNode,
-    pub optimized_three_address_code: Vec<String>,
+    pub errors: Vec<ParseError>,
+    pub semantic_diagnostics: Vec<Diagnostic>,
+    pub three_address_code: Vec<Instr>,
+    /// Signature and body listing of every user-defined function.
+    pub function_code: Vec<(String, Vec<Instr>)>,
+    /// Basic blocks and control flow of `three_address_code`.
+    pub cfg: ControlFlowGraph,
+    /// Live names around every line of `three_address_code`.
+    pub liveness: Liveness,
+    /// SSA form of the inlined code, then after constant propagation and
+    /// dead code elimination; only built when the SSA pass runs.
+    pub ssa: Option<(SsaProgram, SsaProgram)>,
+    /// Every optimization pass that ran, with before/after snapshots.
+    pub passes: Vec<PassRun>,
+    /// Rewrites the optimizer was allowed to use.
+    pub float_mode: FloatMode,
+    pub optimized_three_address_code: Vec<Instr>,
+    /// Shared-subexpression graph of the optimized code.
+    pub dag: Dag,
+    /// Registers of the optimized code's temporaries, with spill code.
+    pub allocation: Allocation,
+    /// Target code for the allocated code.
+    pub assembly: Assembly,
+    /// C translation unit for the optimized code.
+    pub c_source: CSource,
+    /// Stack-machine code for the program with its functions inlined.
+    pub bytecode: Chunk,
+    /// WebAssembly module for the program with its functions inlined.
+    pub wasm: WasmModule,
 }
 
 impl ParseResult {
-    pub fn from_input(input: &str) -> Result<Self, ParseError> {
+    /// Runs the whole pipeline. Syntax errors do not abort compilation:
+    /// they are collected in `errors` alongside the partial AST, and code
+    /// generation is skipped while any are present.
+    pub fn from_input(input: &str, options: &CompileOptions) -> Self {
         let mut lexer = crate::lexer::Lexer::new(input);
-        let tokens = lexer.tokenize()?;
-
-        if tokens.is_empty() {
-            return Err(ParseError::UnexpectedEndOfInput);
-        }
+        let tokens = lexer.tokenize();
+        let mut errors = lexer.take_errors();
 
         let identifier_table = lexer.into_identifier_table();
         let mut parser = Parser::new(tokens.clone());
-        let ast = parser.parse()?;
+        let ast = parser.parse();
+        errors.extend(parser.take_errors());
+        errors.sort_by_key(|err| err.span().start);
 
         // Semantic analysis
-        let semantic_warnings = ast.semantic_check();
+        let semantic_diagnostics = ast.semantic_check();
 
-        // Intermediate code generation
-        let mut temp_counter = 1;
-        let (three_address_code, _) = ast.to_three_address_code(&mut temp_counter);
+        let mut result = Self {
+            source: input.to_string(),
+            tokens,
+            ast,
+            identifier_table,
+            errors,
+            semantic_diagnostics,
+            three_address_code: vec![],
+            function_code: vec![],
+            cfg: ControlFlowGraph::default(),
+            liveness: Liveness::default(),
+            ssa: None,
+            passes: vec![],
+            float_mode: options.float_mode,
+            optimized_three_address_code: vec![],
+            dag: Dag::default(),
+            allocation: Allocation::default(),
+            assembly: Assembly::default(),
+            c_source: CSource::default(),
+            bytecode: Chunk::default(),
+            wasm: WasmModule::default(),
+        };
+
+        // Intermediate code generation and optimization need a valid program
+        if result.has_errors() {
+            return result;
+        }
 
-        // Code optimization
-        let optimized_ast = ast.optimize();
+        // Intermediate code generation
+        let ast = &result.ast;
+        result.three_address_code = ast.to_three_address_code();
+        result.function_code = ast.function_three_address_code();
+        result.cfg = ControlFlowGraph::build(&result.three_address_code);
+
+        // Variables named as live-out, by symbol table index
+        let live_vars: Option<HashSet<usize>> = options.live_out.as_ref().map(|names| {
+            result
+                .identifier_table
+                .iter()
+                .filter(|(name, _)| names.contains(name))
+                .map(|(_, idx)| *idx)
+                .collect()
+        });
+        let exit = liveness::exit_names(&result.three_address_code, live_vars.as_ref());
+        result.liveness = Liveness::analyze(&result.three_address_code, &exit);
+
+        // Code optimization works on straight-line code, so calls to
+        // user functions are inlined first
+        let inlined = result.ast.inline_functions();
+        let optimization = passes::optimize(
+            &inlined.to_three_address_code(),
+            options,
+            live_vars.as_ref(),
+        );
+        result.optimized_three_address_code = optimization.code;
+        result.passes = optimization.runs;
+        result.ssa = optimization.ssa;
+        let optimized = &result.optimized_three_address_code;
+        result.dag = Dag::build(optimized);
+
+        // Register allocation and code generation
+        result.allocation = regalloc::allocate(optimized, options.registers);
+        result.assembly = Assembly::generate(&result.allocation, &result.identifier_table);
+        result.c_source = CSource::generate(optimized, &result.identifier_table);
+        result.bytecode = Chunk::compile(&inlined);
+        result.wasm = WasmModule::compile(&inlined);
+
+        result
+    }
 
-        // Generate optimized three-address code
-        let mut temp_counter = 1;
-        let (mut optimized_three_address_code, _) =
-            optimized_ast.to_three_address_code(&mut temp_counter);
+    /// Whether any syntax or semantic error prevented code generation.
+    pub fn has_errors(&self) -> bool {
+        !self.errors.is_empty()
+            || self
+                .semantic_diagnostics
+                .iter()
+                .any(|d| d.severity == Severity::Error)
+    }
+}
 
-        // Apply peephole optimization to eliminate unnecessary temporaries
-        optimized_three_address_code = Self::peephole_optimize(optimized_three_address_code);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+
+    fn parse_errors(input: &str) -> Vec<ParseError> {
+        let mut lexer = Lexer::new(input);
+        let tokens = lexer.tokenize();
+        let mut parser = Parser::new(tokens);
+        parser.parse();
+        parser.take_errors()
+    }
 
-        Ok(Self {
-            tokens,
-            ast,
-            identifier_table,
-            semantic_warnings,
-            three_address_code,
-            optimized_ast,
-            optimized_three_address_code,
-        })
+    fn position(span: Span) -> (usize, usize) {
+        (span.line, span.column)
     }
 
-    /// Peephole optimization: eliminate unnecessary temporary variables
-    /// Transforms patterns like:
-    ///   t5 = t4 - 10
-    ///   id1 = t5
-    /// Into:
-    ///   id1 = t4 - 10
-    fn peephole_optimize(code: Vec<String>) -> Vec<String> {
-        use std::collections::HashMap;
-
-        let mut temp_definitions: HashMap<String, String> = HashMap::new();
-        let mut temp_usage_count: HashMap<String, usize> = HashMap::new();
-        let mut skip_indices = std::collections::HashSet::new();
-
-        // First pass: count how many times each temp is used and store definitions
-        for line in &code {
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.len() >= 3 && parts[1] == "=" {
-                let lhs = parts[0];
-                let rhs = parts[2..].join(" ");
-
-                // Store temp definitions
-                if lhs.starts_with('t') && lhs.chars().skip(1).all(|c| c.is_numeric()) {
-                    temp_definitions.insert(lhs.to_string(), rhs.clone());
-                }
+    #[test]
+    fn unclosed_parenthesis_points_at_the_open_paren() {
+        let errors = parse_errors("(a + b");
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(errors[0], ParseError::UnclosedParenthesis(_)));
+        assert_eq!(position(errors[0].span()), (1, 1));
+    }
 
-                // Count usages of temps on the right side
-                for part in &parts[2..] {
-                    let clean_part = part.trim_end_matches(|c: char| !c.is_alphanumeric());
-                    if clean_part.starts_with('t')
-                        && clean_part.chars().skip(1).all(|c| c.is_numeric())
-                    {
-                        *temp_usage_count.entry(clean_part.to_string()).or_insert(0) += 1;
-                    }
-                }
+    #[test]
+    fn only_the_first_trailing_token_is_reported() {
+        let errors = parse_errors("a b c");
+        assert_eq!(errors.len(), 1);
+        match &errors[0] {
+            ParseError::TrailingToken(TokenKind::Identifier(name, _), span) => {
+                assert_eq!(name, "b");
+                assert_eq!(position(*span), (1, 3));
             }
+            err => panic!("expected a trailing `b`, got {:?}", err),
         }
+    }
 
-        // Second pass: check for pattern "temp = expr; var = temp" and mark temp line for removal
-        for i in 0..code.len().saturating_sub(1) {
-            let curr_parts: Vec<&str> = code[i].split_whitespace().collect();
-            let next_parts: Vec<&str> = code[i + 1].split_whitespace().collect();
-
-            if curr_parts.len() >= 3
-                && curr_parts[1] == "="
-                && next_parts.len() >= 3
-                && next_parts[1] == "="
-            {
-                let curr_lhs = curr_parts[0];
-                let next_rhs = next_parts[2..].join(" ");
-
-                // Check if current assigns to a temp, and next line uses only that temp
-                if curr_lhs.starts_with('t')
-                    && curr_lhs.chars().skip(1).all(|c| c.is_numeric())
-                    && next_rhs == curr_lhs
-                    && temp_usage_count.get(curr_lhs).copied().unwrap_or(0) == 1
-                {
-                    // Mark the temp assignment for skipping
-                    skip_indices.insert(i);
-                }
-            }
-        }
+    #[test]
+    fn stray_close_paren_is_a_trailing_token() {
+        let errors = parse_errors("a + b)");
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(
+            errors[0],
+            ParseError::TrailingToken(TokenKind::RParen, _)
+        ));
+        assert_eq!(position(errors[0].span()), (1, 6));
+    }
 
-        // Third pass: build optimized code
-        let mut optimized = Vec::new();
-        for (i, line) in code.iter().enumerate() {
-            if skip_indices.contains(&i) {
-                continue;
-            }
+    #[test]
+    fn nested_unclosed_parenthesis_reports_the_outer_one() {
+        let errors = parse_errors("((a)");
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(errors[0], ParseError::UnclosedParenthesis(_)));
+        assert_eq!(position(errors[0].span()), (1, 1));
+    }
 
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.len() >= 3 && parts[1] == "=" {
-                let lhs = parts[0];
-                let rhs = parts[2..].join(" ");
-
-                // If rhs is a single temp that was used only once, substitute its definition
-                if rhs.starts_with('t')
-                    && rhs.chars().all(|c| c.is_alphanumeric())
-                    && temp_definitions.contains_key(&rhs)
-                    && temp_usage_count.get(&rhs).copied().unwrap_or(0) == 1
-                {
-                    let definition = temp_definitions.get(&rhs).unwrap();
-                    optimized.push(format!("{} = {}", lhs, definition));
-                    continue;
+    #[test]
+    fn sqrt_prefixes_an_operand_without_parentheses() {
+        let mut lexer = Lexer::new("sqrt x");
+        let tokens = lexer.tokenize();
+        assert!(
+            lexer
+                .into_identifier_table()
+                .iter()
+                .all(|(name, _)| name != "sqrt")
+        );
+        let mut parser = Parser::new(tokens);
+        let program = parser.parse();
+        assert!(parser.take_errors().is_empty());
+        assert!(matches!(
+            &program.statements[..],
+            [ASTNode::UnaryOp { op, .. }] if op == "sqrt"
+        ));
+    }
+
+    #[test]
+    fn sqrt_prefix_takes_a_signed_operand() {
+        for input in ["sqrt -4", "sqrt -x", "sqrt +x"] {
+            let mut parser = Parser::new(Lexer::new(input).tokenize());
+            let program = parser.parse();
+            assert!(parser.take_errors().is_empty(), "{}", input);
+            match &program.statements[..] {
+                [ASTNode::UnaryOp { op, operand, .. }] if op == "sqrt" => {
+                    assert!(
+                        matches!(&**operand, ASTNode::UnaryOp { op, .. } if op != "sqrt"),
+                        "{}",
+                        input
+                    );
                 }
+                statements => panic!("{}: {:?}", input, statements),
             }
-
-            optimized.push(line.clone());
         }
+    }
 
-        optimized
+    #[test]
+    fn balanced_input_has_no_errors() {
+        assert!(parse_errors("((a) + b) * c").is_empty());
     }
 }
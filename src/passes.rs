@@ -0,0 +1,384 @@
+use crate::dag;
+use crate::ir::{self, Instr, Operand};
+use crate::liveness;
+use crate::options::{CompileOptions, FloatMode};
+use crate::propagation;
+use crate::ssa::SsaProgram;
+use crate::strength;
+use std::collections::HashSet;
+
+/// One optimization pass over the three-address code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Pass {
+    Ssa,
+    Propagation,
+    StrengthReduction,
+    AlgebraicSimplification,
+    ConstantFolding,
+    SqrtLowering,
+    CommonSubexpressions,
+    DeadAssignments,
+    Peephole,
+}
+
+impl Pass {
+    /// Every pass in the default order.
+    pub const ALL: [Pass; 9] = [
+        Pass::Ssa,
+        Pass::Propagation,
+        Pass::StrengthReduction,
+        Pass::AlgebraicSimplification,
+        Pass::ConstantFolding,
+        Pass::SqrtLowering,
+        Pass::CommonSubexpressions,
+        Pass::DeadAssignments,
+        Pass::Peephole,
+    ];
+
+    /// Short name used on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            Pass::Ssa => "ssa",
+            Pass::Propagation => "propagation",
+            Pass::StrengthReduction => "strength",
+            Pass::ConstantFolding => "folding",
+            Pass::AlgebraicSimplification => "algebra",
+            Pass::SqrtLowering => "lower-sqrt",
+            Pass::CommonSubexpressions => "cse",
+            Pass::DeadAssignments => "dead-assignments",
+            Pass::Peephole => "peephole",
+        }
+    }
+
+    pub fn title(self) -> &'static str {
+        match self {
+            Pass::Ssa => "SSA constant propagation and dead code elimination",
+            Pass::Propagation => "Constant and copy propagation",
+            Pass::StrengthReduction => "Strength reduction",
+            Pass::ConstantFolding => "Constant folding",
+            Pass::AlgebraicSimplification => "Algebraic simplification",
+            Pass::SqrtLowering => "Lower sqrt(x) to x ^ 0.5",
+            Pass::CommonSubexpressions => "Common subexpression elimination",
+            Pass::DeadAssignments => "Dead assignment elimination",
+            Pass::Peephole => "Peephole: merge temporaries into assignments",
+        }
+    }
+
+    /// Whether the pass runs unless switched on or off explicitly.
+    pub fn enabled_by_default(self) -> bool {
+        !matches!(self, Pass::Ssa | Pass::SqrtLowering)
+    }
+
+    pub fn from_name(name: &str) -> Option<Pass> {
+        Pass::ALL.into_iter().find(|pass| pass.name() == name)
+    }
+}
+
+/// Rounds after which a fixpoint run gives up, in case two passes undo
+/// each other.
+pub const MAX_ROUNDS: usize = 10;
+
+/// One pass applied once, with the code it was given and produced.
+#[derive(Debug, Clone)]
+pub struct PassRun {
+    pub pass: Pass,
+    /// Round of a fixpoint run, from 1.
+    pub round: usize,
+    pub before: Vec<Instr>,
+    pub after: Vec<Instr>,
+    /// What the pass changed, for passes that report it.
+    pub notes: Vec<String>,
+}
+
+impl PassRun {
+    pub fn changed(&self) -> bool {
+        self.before != self.after
+    }
+}
+
+/// Result of the pass pipeline.
+#[derive(Debug, Clone, Default)]
+pub struct Optimization {
+    pub code: Vec<Instr>,
+    pub runs: Vec<PassRun>,
+    /// SSA form before and after its optimizations, from the first run of
+    /// the SSA pass.
+    pub ssa: Option<(SsaProgram, SsaProgram)>,
+}
+
+/// Runs the enabled passes in the configured order, once or until a whole
+/// round changes nothing, then numbers the remaining temporaries.
+pub fn optimize(
+    code: &[Instr],
+    options: &CompileOptions,
+    live_vars: Option<&HashSet<usize>>,
+) -> Optimization {
+    let mut optimization = Optimization::default();
+    let mut code = code.to_vec();
+
+    let rounds = if options.fixpoint { MAX_ROUNDS } else { 1 };
+    for round in 1..=rounds {
+        let start = code.clone();
+        for pass in options.enabled_passes() {
+            let (after, mut notes) = run(pass, &code, options, live_vars, &mut optimization);
+            notes.extend(non_finite_folds(&code, &after));
+            optimization.runs.push(PassRun {
+                pass,
+                round,
+                before: std::mem::replace(&mut code, after.clone()),
+                after,
+                notes,
+            });
+        }
+        if code == start {
+            break;
+        }
+    }
+
+    optimization.code = ir::renumber_temps(&code);
+    optimization
+}
+
+fn run(
+    pass: Pass,
+    code: &[Instr],
+    options: &CompileOptions,
+    live_vars: Option<&HashSet<usize>>,
+    optimization: &mut Optimization,
+) -> (Vec<Instr>, Vec<String>) {
+    match pass {
+        Pass::Ssa => {
+            let ssa_form = SsaProgram::from_code(code);
+            let ssa_optimized = ssa_form.propagate_constants().eliminate_dead_code();
+            let code = ssa_optimized.to_code();
+            optimization.ssa.get_or_insert((ssa_form, ssa_optimized));
+            (code, vec![])
+        }
+        Pass::Propagation => {
+            let (code, rewrites) = propagation::propagate(code);
+            (code, describe(&rewrites))
+        }
+        Pass::StrengthReduction => {
+            let (code, reductions) =
+                strength::reduce(code, &options.disabled_rules, options.float_mode);
+            (code, describe(&reductions))
+        }
+        Pass::ConstantFolding => (ir::fold_constants(code), vec![]),
+        Pass::AlgebraicSimplification => (ir::simplify_algebra(code, options.float_mode), vec![]),
+        Pass::SqrtLowering => {
+            let notes = match options.float_mode {
+                FloatMode::Strict => vec![
+                    "sqrt lowering skipped: x ^ 0.5 is not exact under strict IEEE-754; enable fast-math to use it"
+                        .to_string(),
+                ],
+                FloatMode::FastMath => vec![],
+            };
+            (ir::lower_sqrt(code, options.float_mode), notes)
+        }
+        Pass::CommonSubexpressions => (dag::eliminate_common_subexpressions(code), vec![]),
+        Pass::DeadAssignments => (
+            liveness::eliminate_dead_assignments(code, live_vars),
+            vec![],
+        ),
+        Pass::Peephole => (ir::coalesce_temps(code), vec![]),
+    }
+}
+
+/// Warnings for instructions in `after` that hold an infinity or NaN the
+/// pass produced, e.g. by folding `1 / 0`.
+fn non_finite_folds(before: &[Instr], after: &[Instr]) -> Vec<String> {
+    after
+        .iter()
+        .filter(|instr| !before.contains(instr))
+        .filter(|instr| {
+            instr
+                .uses()
+                .any(|arg| matches!(arg, Operand::Const(c) if !c.is_finite()))
+        })
+        .map(|instr| format!("warning: `{}` holds a value that is not finite", instr))
+        .collect()
+}
+
+fn describe(changes: &[impl ToString]) -> Vec<String> {
+    changes.iter().map(ToString::to_string).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{code, listing};
+
+    fn passes_run(optimization: &Optimization) -> Vec<(Pass, usize)> {
+        optimization
+            .runs
+            .iter()
+            .map(|run| (run.pass, run.round))
+            .collect()
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for pass in Pass::ALL {
+            assert_eq!(Pass::from_name(pass.name()), Some(pass));
+        }
+        assert_eq!(Pass::from_name("nope"), None);
+    }
+
+    #[test]
+    fn default_passes_run_once_in_order() {
+        let optimization = optimize(
+            &code("a = x * 1; b = a * 2"),
+            &CompileOptions::default(),
+            None,
+        );
+        let expected: Vec<(Pass, usize)> = Pass::ALL
+            .into_iter()
+            .filter(|pass| pass.enabled_by_default())
+            .map(|pass| (pass, 1))
+            .collect();
+        assert_eq!(passes_run(&optimization), expected);
+        assert!(optimization.ssa.is_none());
+        // Each pass starts from the code the previous one produced
+        for pair in optimization.runs.windows(2) {
+            assert_eq!(pair[0].after, pair[1].before);
+        }
+    }
+
+    #[test]
+    fn order_and_toggles_are_followed() {
+        let mut options = CompileOptions::default();
+        options.set_order(&[Pass::CommonSubexpressions, Pass::Ssa, Pass::Propagation]);
+        let optimization = optimize(&code("a = x * y + x * y"), &options, None);
+        assert_eq!(
+            passes_run(&optimization),
+            [
+                (Pass::CommonSubexpressions, 1),
+                (Pass::Ssa, 1),
+                (Pass::Propagation, 1)
+            ]
+        );
+        assert!(optimization.ssa.is_some());
+
+        options.set_enabled(Pass::CommonSubexpressions, false);
+        let optimization = optimize(&code("a = x * y + x * y"), &options, None);
+        assert_eq!(
+            passes_run(&optimization),
+            [(Pass::Ssa, 1), (Pass::Propagation, 1)]
+        );
+        assert_eq!(
+            listing(&optimization.code),
+            [
+                "t1 = id2 * id3",
+                "t2 = id2 * id3",
+                "t3 = t1 + t2",
+                "id1 = t3"
+            ]
+        );
+    }
+
+    #[test]
+    fn fixpoint_repeats_until_nothing_changes() {
+        let input = "a = x * 1; b = a * 2 - a * 2";
+        let mut options = CompileOptions {
+            float_mode: FloatMode::FastMath,
+            ..CompileOptions::default()
+        };
+        let once = optimize(&code(input), &options, None);
+        assert_eq!(
+            listing(&once.code),
+            ["id1 = id2", "t1 = id2 + id2", "id3 = t1 - t1"]
+        );
+
+        options.fixpoint = true;
+        let repeated = optimize(&code(input), &options, None);
+        assert_eq!(listing(&repeated.code), ["id1 = id2", "id3 = 0"]);
+        let last = repeated.runs.last().unwrap().round;
+        assert_eq!(last, 3);
+        assert!(
+            repeated
+                .runs
+                .iter()
+                .filter(|run| run.round == last)
+                .all(|run| !run.changed())
+        );
+    }
+
+    #[test]
+    fn inexact_rewrites_need_fast_math() {
+        // Each program with its code under strict IEEE and under fast-math
+        let cases: &[(&str, &[&str], &[&str])] = &[
+            ("a = x * 0", &["id1 = id2 * 0"], &["id1 = 0"]),
+            ("a = x + 0", &["id1 = id2 + 0"], &["id1 = id2"]),
+            ("a = x - x", &["id1 = id2 - id2"], &["id1 = 0"]),
+            ("a = x / x", &["id1 = id2 / id2"], &["id1 = 1"]),
+            (
+                "a = (x + 2) + 3",
+                &["t1 = id2 + 2", "id1 = t1 + 3"],
+                &["id1 = id2 + 5"],
+            ),
+            (
+                "a = (x * 2) * 3",
+                &["t1 = id2 + id2", "id1 = t1 * 3"],
+                &["id1 = id2 * 6"],
+            ),
+        ];
+        for (input, strict, fast) in cases {
+            for (mode, expected) in [(FloatMode::Strict, strict), (FloatMode::FastMath, fast)] {
+                let options = CompileOptions {
+                    float_mode: mode,
+                    ..CompileOptions::default()
+                };
+                let optimization = optimize(&code(input), &options, None);
+                assert_eq!(
+                    listing(&optimization.code),
+                    *expected,
+                    "{} {:?}",
+                    input,
+                    mode
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn temporaries_are_renumbered() {
+        // Folding drops t1, so t2, t3 and t4 become t1, t2 and t3
+        let input = "a = 1 + 2; b = x * y + x; b * 2";
+        let optimization = optimize(&code(input), &CompileOptions::default(), None);
+        assert_eq!(
+            listing(&optimization.code),
+            [
+                "id1 = 3",
+                "t1 = id3 * id4",
+                "t2 = t1 + id3",
+                "id2 = t2",
+                "t3 = t2 + t2"
+            ]
+        );
+    }
+
+    #[test]
+    fn strict_mode_notes_skipped_sqrt_lowering() {
+        let mut options = CompileOptions::default();
+        options.set_order(&[Pass::SqrtLowering]);
+        let optimization = optimize(&code("a = sqrt(x)"), &options, None);
+        assert!(!optimization.runs[0].changed());
+        assert_eq!(optimization.runs[0].notes.len(), 1);
+
+        options.float_mode = FloatMode::FastMath;
+        let optimization = optimize(&code("a = sqrt(x)"), &options, None);
+        assert!(optimization.runs[0].notes.is_empty());
+        assert_eq!(listing(&optimization.code), ["t1 = id2 ^ 0.5", "id1 = t1"]);
+    }
+
+    #[test]
+    fn non_finite_folds_are_noted() {
+        let mut options = CompileOptions::default();
+        options.set_order(&[Pass::ConstantFolding]);
+        let optimization = optimize(&code("a = 1 / 0"), &options, None);
+        assert_eq!(
+            optimization.runs[0].notes,
+            ["warning: `id1 = inf` holds a value that is not finite"]
+        );
+    }
+}
@@ -0,0 +1,222 @@
+use crate::cfg::ControlFlowGraph;
+use crate::ir::{self, Instr, Name, Op, Operand};
+use std::collections::HashMap;
+use std::fmt;
+
+/// What is known about names at one point of the program: each holds a
+/// constant, or the same value as another name.
+type Facts = HashMap<Name, Operand>;
+
+/// One instruction changed by propagation.
+#[derive(Debug, Clone)]
+pub struct Rewrite {
+    /// Position in the code that was propagated, from 1.
+    pub line: usize,
+    pub before: Instr,
+    pub after: Instr,
+}
+
+impl fmt::Display for Rewrite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:2}: {}  ⇒  {}", self.line, self.before, self.after)
+    }
+}
+
+/// Constant and copy propagation across statements and blocks: a forward
+/// dataflow analysis finds the names whose value is known on every path
+/// into each block, then their uses are replaced by that value and
+/// operations on constants are folded. Temporaries left unread are
+/// dropped.
+pub fn propagate(code: &[Instr]) -> (Vec<Instr>, Vec<Rewrite>) {
+    let cfg = ControlFlowGraph::build(code);
+    let order = cfg.reverse_postorder();
+
+    // Facts on leaving every block; `None` until the block is visited
+    let mut block_out: Vec<Option<Facts>> = vec![None; cfg.blocks.len()];
+    let block_in = |block_out: &[Option<Facts>], b: usize| -> Facts {
+        if b == 0 {
+            return Facts::new();
+        }
+        // Only what every visited predecessor agrees on
+        let mut preds = cfg.predecessors[b]
+            .iter()
+            .filter_map(|pred| block_out[*pred].as_ref());
+        let mut facts = preds.next().cloned().unwrap_or_default();
+        for pred in preds {
+            facts.retain(|name, value| pred.get(name) == Some(value));
+        }
+        facts
+    };
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for b in &order {
+            let mut facts = block_in(&block_out, *b);
+            for instr in &cfg.blocks[*b].code {
+                transfer(&rewrite(instr, &facts), &mut facts);
+            }
+            if block_out[*b].as_ref() != Some(&facts) {
+                block_out[*b] = Some(facts);
+                changed = true;
+            }
+        }
+    }
+
+    let mut out = vec![];
+    let mut rewrites = vec![];
+    for (b, block) in cfg.blocks.iter().enumerate() {
+        let mut facts = block_in(&block_out, b);
+        for (i, instr) in block.code.iter().enumerate() {
+            let rewritten = rewrite(instr, &facts);
+            transfer(&rewritten, &mut facts);
+            if rewritten != *instr {
+                rewrites.push(Rewrite {
+                    line: block.start + i + 1,
+                    before: instr.clone(),
+                    after: rewritten.clone(),
+                });
+            }
+            out.push(rewritten);
+        }
+    }
+    (ir::remove_dead_temps(code, out), rewrites)
+}
+
+/// `instr` with known values substituted, folded if that leaves only
+/// constants.
+fn rewrite(instr: &Instr, facts: &Facts) -> Instr {
+    let resolve = |arg: Option<Operand>| {
+        arg.map(|arg| {
+            arg.name()
+                .and_then(|name| facts.get(&name).copied())
+                .unwrap_or(arg)
+        })
+    };
+    let instr = Instr {
+        arg1: resolve(instr.arg1),
+        arg2: resolve(instr.arg2),
+        ..instr.clone()
+    };
+
+    let constant = |arg: Option<Operand>| match arg {
+        None => Some(0.0),
+        Some(Operand::Const(c)) => Some(c),
+        Some(_) => None,
+    };
+    if let Some(dst) = instr.dst
+        && instr.op != Op::Copy
+        && instr.arg1.is_some()
+        && let (Some(a), Some(b)) = (constant(instr.arg1), constant(instr.arg2))
+        && let Some(folded) = instr.op.fold(a, b)
+    {
+        return Instr::copy(dst, Operand::Const(folded));
+    }
+    instr
+}
+
+/// Facts after `instr`, given the facts before it.
+fn transfer(instr: &Instr, facts: &mut Facts) {
+    let Some(dst) = instr.dst.and_then(Operand::name) else {
+        return;
+    };
+    // Whatever was known about the old value of `dst` is gone
+    facts.remove(&dst);
+    facts.retain(|_, value| *value != Operand::from(dst));
+
+    if instr.op == Op::Copy
+        && let Some(src) = instr.arg1
+        && src != Operand::from(dst)
+    {
+        facts.insert(dst, src);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{code, listing};
+
+    fn propagated(input: &str) -> Vec<String> {
+        listing(&propagate(&code(input)).0)
+    }
+
+    #[test]
+    fn constants_cross_statements() {
+        let (out, rewrites) = propagate(&code("a = 2; b = a * x; c = a + b"));
+        assert_eq!(
+            listing(&out),
+            [
+                "id1 = 2",
+                "t1 = 2 * id3",
+                "id2 = t1",
+                "t2 = 2 + t1",
+                "id4 = t2"
+            ]
+        );
+        let lines: Vec<usize> = rewrites.iter().map(|rewrite| rewrite.line).collect();
+        assert_eq!(lines, [2, 4]);
+        assert_eq!(rewrites[0].before.to_string(), "t1 = id1 * id3");
+    }
+
+    #[test]
+    fn constant_operations_fold() {
+        assert_eq!(
+            propagated("a = 2; b = a * 3; c = b + x"),
+            ["id1 = 2", "id2 = 6", "t2 = 6 + id4", "id3 = t2"]
+        );
+    }
+
+    #[test]
+    fn reassignment_kills_copies() {
+        assert_eq!(
+            propagated("a = x; b = a + 1; a = 5; c = a + b"),
+            [
+                "id1 = id2",
+                "t1 = id2 + 1",
+                "id3 = t1",
+                "id1 = 5",
+                "t2 = 5 + t1",
+                "id4 = t2"
+            ]
+        );
+    }
+
+    #[test]
+    fn facts_hold_only_if_true_on_every_path() {
+        // Both branches assign 1
+        assert_eq!(
+            propagated("a = if(c, 1, 1); b = a * x"),
+            [
+                "ifFalse id2 goto L1",
+                "goto L2",
+                "L1:",
+                "L2:",
+                "id1 = 1",
+                "t2 = 1 * id4",
+                "id3 = t2"
+            ]
+        );
+        // 1 on one path and 2 on the other
+        let input = "a = if(c, 1, 2); b = a * x";
+        assert_eq!(propagated(input)[..7], listing(&code(input))[..7]);
+    }
+
+    #[test]
+    fn facts_flow_into_dominated_blocks() {
+        assert_eq!(
+            propagated("a = x; b = if(c, a, 2) + a"),
+            [
+                "id1 = id2",
+                "ifFalse id4 goto L1",
+                "t1 = id2",
+                "goto L2",
+                "L1:",
+                "t1 = 2",
+                "L2:",
+                "t2 = t1 + id2",
+                "id3 = t2",
+            ]
+        );
+    }
+}
@@ -0,0 +1,372 @@
+use crate::ir::{Instr, Name, Op, Operand};
+use crate::liveness::Liveness;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Temporaries that are live at the same time and so cannot share a
+/// register. Variables stay in memory and are not part of it.
+#[derive(Debug, Clone, Default)]
+pub struct InterferenceGraph {
+    /// Neighbours of every temporary.
+    pub adjacency: BTreeMap<usize, BTreeSet<usize>>,
+}
+
+impl InterferenceGraph {
+    /// A temporary interferes with everything live after the instruction
+    /// that assigns it, except the source of a copy, which may share its
+    /// register.
+    pub fn build(code: &[Instr]) -> InterferenceGraph {
+        let liveness = Liveness::analyze(code, &BTreeSet::new());
+        let mut graph = InterferenceGraph::default();
+        for (i, instr) in code.iter().enumerate() {
+            for operand in instr.uses().chain(instr.dst) {
+                if let Operand::Temp(n) = operand {
+                    graph.adjacency.entry(n).or_default();
+                }
+            }
+            let Some(Operand::Temp(dst)) = instr.dst else {
+                continue;
+            };
+            let copied = match (&instr.op, instr.arg1) {
+                (Op::Copy, Some(Operand::Temp(src))) => Some(src),
+                _ => None,
+            };
+            for name in &liveness.live_out[i] {
+                if let Name::Temp(n) = *name
+                    && n != dst
+                    && Some(n) != copied
+                {
+                    graph.add_edge(dst, n);
+                }
+            }
+        }
+        graph
+    }
+
+    fn add_edge(&mut self, a: usize, b: usize) {
+        self.adjacency.entry(a).or_default().insert(b);
+        self.adjacency.entry(b).or_default().insert(a);
+    }
+
+    /// Every edge once, as `(a, b)` with `a < b`.
+    pub fn edges(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.adjacency.iter().flat_map(|(a, neighbours)| {
+            neighbours
+                .iter()
+                .filter(move |b| a < *b)
+                .map(move |b| (*a, *b))
+        })
+    }
+}
+
+/// Result of register allocation.
+#[derive(Debug, Clone, Default)]
+pub struct Allocation {
+    /// Registers the code was colored with.
+    pub registers: usize,
+    /// Interference graph of the code before any spilling.
+    pub graph: InterferenceGraph,
+    /// The code with spill code inserted: every spilled temporary is stored
+    /// to its stack slot after each assignment and reloaded into a fresh
+    /// temporary before each read.
+    pub code: Vec<Instr>,
+    /// Register of every temporary in `code`, from 0.
+    pub assignment: BTreeMap<usize, usize>,
+    /// Stack slot of every spilled temporary.
+    pub spilled: BTreeMap<usize, usize>,
+    /// Temporaries that stand in for every spilled one in `code`: those it
+    /// is stored from and reloaded into.
+    pub renamed: BTreeMap<usize, Vec<usize>>,
+}
+
+impl Allocation {
+    /// `t6 → s0`, followed by the temporaries that replace `t6` in `code`.
+    pub fn describe_spill(&self, temp: usize) -> String {
+        let renamed: Vec<String> = self.renamed[&temp]
+            .iter()
+            .map(|n| format!("t{}", n))
+            .collect();
+        format!(
+            "t{} → s{} (renamed {})",
+            temp,
+            self.spilled[&temp],
+            renamed.join(", ")
+        )
+    }
+}
+
+/// Chaitin/Briggs graph coloring with `registers` colors, at least two.
+/// Calls, including the one `^` compiles to, clobber every register, so
+/// temporaries live across one are spilled first. Coloring is then retried
+/// on the rewritten code until every temporary has a register.
+pub fn allocate(code: &[Instr], registers: usize) -> Allocation {
+    let registers = registers.max(2);
+    let graph = InterferenceGraph::build(code);
+    let mut spiller = Spiller {
+        next_temp: code
+            .iter()
+            .flat_map(|instr| instr.dst.into_iter().chain(instr.uses()))
+            .filter_map(|operand| match operand {
+                Operand::Temp(n) => Some(n + 1),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(1),
+        ..Spiller::default()
+    };
+
+    let mut code = spiller.spill(code, &live_across_calls(code));
+    loop {
+        match color(&InterferenceGraph::build(&code), registers, &spiller) {
+            Ok(assignment) => {
+                return Allocation {
+                    registers,
+                    graph,
+                    code,
+                    assignment,
+                    spilled: spiller.slots,
+                    renamed: spiller.renamed,
+                };
+            }
+            Err(spills) => code = spiller.spill(&code, &spills),
+        }
+    }
+}
+
+/// Whether the instruction becomes a call, which clobbers every register.
+pub fn is_call(instr: &Instr) -> bool {
+    matches!(instr.op, Op::Call { .. } | Op::Pow)
+}
+
+/// Temporaries still read after a call that they are live across.
+fn live_across_calls(code: &[Instr]) -> BTreeSet<usize> {
+    let liveness = Liveness::analyze(code, &BTreeSet::new());
+    code.iter()
+        .enumerate()
+        .filter(|(_, instr)| is_call(instr))
+        .flat_map(|(i, instr)| {
+            liveness.live_out[i]
+                .iter()
+                .filter_map(|name| match name {
+                    Name::Temp(n) => Some(*n),
+                    Name::Var(_) => None,
+                })
+                .filter(move |n| instr.dst != Some(Operand::Temp(*n)))
+        })
+        .collect()
+}
+
+/// Colors the graph, or returns the temporaries to spill. Simplify removes
+/// nodes with fewer than `registers` neighbours; when none is left the one
+/// with the most neighbours is removed anyway, optimistically, in case its
+/// neighbours end up sharing colors. Select then colors in reverse order.
+fn color(
+    graph: &InterferenceGraph,
+    registers: usize,
+    spiller: &Spiller,
+) -> Result<BTreeMap<usize, usize>, BTreeSet<usize>> {
+    let mut remaining: BTreeSet<usize> = graph.adjacency.keys().copied().collect();
+    let degree = |n: usize, remaining: &BTreeSet<usize>| {
+        graph.adjacency[&n]
+            .iter()
+            .filter(|m| remaining.contains(m))
+            .count()
+    };
+
+    let mut stack = vec![];
+    while !remaining.is_empty() {
+        let node = remaining
+            .iter()
+            .copied()
+            .find(|n| degree(*n, &remaining) < registers)
+            .or_else(|| {
+                // Spill candidate; reload temporaries live too briefly to help
+                remaining
+                    .iter()
+                    .copied()
+                    .filter(|n| !spiller.reloads.contains(n))
+                    .max_by_key(|n| (degree(*n, &remaining), std::cmp::Reverse(*n)))
+            })
+            .or_else(|| remaining.first().copied())
+            .unwrap();
+        remaining.remove(&node);
+        stack.push(node);
+    }
+
+    let mut assignment = BTreeMap::new();
+    let mut spills = BTreeSet::new();
+    for node in stack.into_iter().rev() {
+        let taken: BTreeSet<usize> = graph.adjacency[&node]
+            .iter()
+            .filter_map(|m| assignment.get(m).copied())
+            .collect();
+        match (0..registers).find(|r| !taken.contains(r)) {
+            Some(register) => {
+                assignment.insert(node, register);
+            }
+            None if !spiller.reloads.contains(&node) => {
+                spills.insert(node);
+            }
+            // Only the neighbours that can be spilled make room
+            None => spills.extend(
+                graph.adjacency[&node]
+                    .iter()
+                    .filter(|m| !spiller.reloads.contains(m)),
+            ),
+        }
+    }
+
+    if spills.is_empty() {
+        Ok(assignment)
+    } else {
+        Err(spills)
+    }
+}
+
+#[derive(Default)]
+struct Spiller {
+    next_temp: usize,
+    /// Stack slot of every spilled temporary.
+    slots: BTreeMap<usize, usize>,
+    /// Temporaries created by spilling, which are never spilled themselves.
+    reloads: BTreeSet<usize>,
+    /// Temporaries created for every spilled one.
+    renamed: BTreeMap<usize, Vec<usize>>,
+}
+
+impl Spiller {
+    /// A new temporary standing in for spilled `temp`.
+    fn fresh(&mut self, temp: usize) -> usize {
+        self.next_temp += 1;
+        self.reloads.insert(self.next_temp - 1);
+        self.renamed
+            .entry(temp)
+            .or_default()
+            .push(self.next_temp - 1);
+        self.next_temp - 1
+    }
+
+    /// `code` with `temps` kept in stack slots.
+    fn spill(&mut self, code: &[Instr], temps: &BTreeSet<usize>) -> Vec<Instr> {
+        for temp in temps {
+            let slot = self.slots.len();
+            self.slots.entry(*temp).or_insert(slot);
+        }
+
+        let mut out = vec![];
+        for instr in code {
+            let mut instr = instr.clone();
+            let mut reloaded: BTreeMap<usize, usize> = BTreeMap::new();
+            for arg in [&mut instr.arg1, &mut instr.arg2].into_iter().flatten() {
+                if let Operand::Temp(n) = *arg
+                    && temps.contains(&n)
+                {
+                    let fresh = match reloaded.get(&n) {
+                        Some(fresh) => *fresh,
+                        None => {
+                            let fresh = self.fresh(n);
+                            out.push(Instr::reload(Operand::Temp(fresh), self.slots[&n]));
+                            reloaded.insert(n, fresh);
+                            fresh
+                        }
+                    };
+                    *arg = Operand::Temp(fresh);
+                }
+            }
+
+            match instr.dst {
+                Some(Operand::Temp(n)) if temps.contains(&n) => {
+                    let fresh = self.fresh(n);
+                    instr.dst = Some(Operand::Temp(fresh));
+                    out.push(instr);
+                    out.push(Instr::spill(Operand::Temp(fresh), self.slots[&n]));
+                }
+                _ => out.push(instr),
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{code, listing};
+
+    const PRESSURE: &str = "a = (x + y) * (x - y) / (x * y + 1) - (x / y) * (y - 1)";
+
+    /// Checks that every temporary in the final code has one of the
+    /// registers and that interfering ones differ.
+    fn assert_colored(allocation: &Allocation) {
+        let graph = InterferenceGraph::build(&allocation.code);
+        for (temp, neighbours) in &graph.adjacency {
+            let register = allocation.assignment[temp];
+            assert!(register < allocation.registers);
+            for neighbour in neighbours {
+                assert_ne!(
+                    register, allocation.assignment[neighbour],
+                    "t{} t{}",
+                    temp, neighbour
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn temporaries_live_together_interfere() {
+        let graph = InterferenceGraph::build(&code("a = (x + y) * (x - y)"));
+        assert_eq!(graph.edges().collect::<Vec<_>>(), [(1, 2)]);
+        // The operands of t6 = t3 / t5 are live together, and t6 is live
+        // while t7, t8 and t9 are computed
+        let graph = InterferenceGraph::build(&code(PRESSURE));
+        assert_eq!(
+            graph.edges().collect::<Vec<_>>(),
+            [(1, 2), (3, 4), (3, 5), (6, 7), (6, 8), (6, 9), (7, 8)]
+        );
+    }
+
+    #[test]
+    fn enough_registers_need_no_spills() {
+        let allocation = allocate(&code(PRESSURE), 4);
+        assert!(allocation.spilled.is_empty());
+        assert_eq!(allocation.code, code(PRESSURE));
+        assert_colored(&allocation);
+    }
+
+    #[test]
+    fn two_registers_spill() {
+        let allocation = allocate(&code(PRESSURE), 2);
+        assert_eq!(allocation.spilled, BTreeMap::from([(6, 0)]));
+        assert_eq!(allocation.renamed, BTreeMap::from([(6, vec![11, 12])]));
+        assert_eq!(
+            listing(&allocation.code)[5..11],
+            [
+                "t11 = t3 / t5",
+                "spill t11 → s0",
+                "t7 = id2 / id3",
+                "t8 = id3 - 1",
+                "t9 = t7 * t8",
+                "t12 = reload s0",
+            ]
+        );
+        assert_eq!(allocation.describe_spill(6), "t6 → s0 (renamed t11, t12)");
+        assert_colored(&allocation);
+    }
+
+    #[test]
+    fn registers_are_at_least_two() {
+        let allocation = allocate(&code(PRESSURE), 0);
+        assert_eq!(allocation.registers, 2);
+        assert_colored(&allocation);
+    }
+
+    #[test]
+    fn values_live_across_calls_are_spilled() {
+        for input in ["(x * y) + sin(x)", "(x * y) + x ^ 3"] {
+            let allocation = allocate(&code(input), 4);
+            assert_eq!(allocation.spilled, BTreeMap::from([(1, 0)]), "{}", input);
+            let code = listing(&allocation.code);
+            assert_eq!(code[..2], ["t4 = id1 * id2", "spill t4 → s0"], "{}", input);
+            assert_colored(&allocation);
+        }
+    }
+}
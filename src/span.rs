@@ -0,0 +1,74 @@
+use std::fmt;
+
+/// Location of a piece of source text.
+///
+/// `start` and `end` are char offsets into the input (end exclusive), while
+/// `line` and `column` are 1-based and point at the first character and
+/// `end_line` and `end_column` just past the last one. Offsets count chars
+/// rather than bytes because the lexer and the GUI both work on chars;
+/// `str::char_indices` gives the byte offset of one when needed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+}
+
+impl Span {
+    /// A span that doesn't cross a newline.
+    pub fn new(start: usize, end: usize, line: usize, column: usize) -> Self {
+        Self {
+            start,
+            end,
+            line,
+            column,
+            end_line: line,
+            end_column: column + (end - start),
+        }
+    }
+
+    /// Smallest span covering both `self` and `other`.
+    pub fn to(self, other: Span) -> Span {
+        let first = if self.start <= other.start {
+            self
+        } else {
+            other
+        };
+        let last = if self.end >= other.end { self } else { other };
+        Span {
+            start: first.start,
+            end: last.end,
+            line: first.line,
+            column: first.column,
+            end_line: last.end_line,
+            end_column: last.end_column,
+        }
+    }
+
+    /// Zero-width span right after this one.
+    pub fn end_point(self) -> Span {
+        Span {
+            start: self.end,
+            end: self.end,
+            line: self.end_line,
+            column: self.end_column,
+            end_line: self.end_line,
+            end_column: self.end_column,
+        }
+    }
+}
+
+impl Default for Span {
+    fn default() -> Self {
+        Self::new(0, 0, 1, 1)
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
@@ -0,0 +1,750 @@
+use crate::cfg::ControlFlowGraph;
+use crate::ir::{self, Instr, Name, Op, Operand};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// An SSA operand: a versioned name, `id1_2`, or a constant. Version 0 is
+/// the value a name has on entry.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Name(Name, usize),
+    Const(f64),
+}
+
+impl Value {
+    fn of(operand: Operand) -> Value {
+        match operand {
+            Operand::Const(c) => Value::Const(c),
+            _ => Value::Name(operand.name().unwrap(), 0),
+        }
+    }
+
+    /// The operand after leaving SSA: the version is dropped.
+    fn operand(self) -> Operand {
+        match self {
+            Value::Name(name, _) => name.into(),
+            Value::Const(c) => Operand::Const(c),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Name(base, version) => write!(f, "{}_{}", base, version),
+            Value::Const(c) => write!(f, "{}", c),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SsaInstr {
+    pub dst: Option<Value>,
+    pub op: Op,
+    pub args: Vec<Value>,
+}
+
+impl fmt::Display for SsaInstr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let arg = |value: Option<&Value>| value.map(|v| v.to_string()).unwrap_or_default();
+        ir::write_instr(
+            f,
+            &arg(self.dst.as_ref()),
+            &self.op,
+            &arg(self.args.first()),
+            &arg(self.args.get(1)),
+        )
+    }
+}
+
+/// `dst = phi(...)`: picks the argument of the predecessor block that
+/// control came from.
+#[derive(Debug, Clone)]
+pub struct Phi {
+    pub dst: Value,
+    pub args: Vec<(usize, Value)>,
+}
+
+impl fmt::Display for Phi {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let args: Vec<String> = self
+            .args
+            .iter()
+            .map(|(pred, value)| format!("{} [B{}]", value, pred))
+            .collect();
+        write!(f, "{} = phi({})", self.dst, args.join(", "))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SsaBlock {
+    pub phis: Vec<Phi>,
+    pub code: Vec<SsaInstr>,
+}
+
+/// Intermediate code in static single assignment form: every name is
+/// assigned once, and phi functions merge names where paths join.
+#[derive(Debug, Clone, Default)]
+pub struct SsaProgram {
+    pub blocks: Vec<SsaBlock>,
+    pub successors: Vec<Vec<usize>>,
+    /// Temporaries that are never read: the values of expression
+    /// statements, which dead code elimination must keep.
+    results: HashSet<Name>,
+}
+
+impl SsaProgram {
+    /// Converts `code` to SSA: phis are placed on the iterated dominance
+    /// frontiers of every assignment, then names are versioned in a walk
+    /// of the dominator tree.
+    pub fn from_code(code: &[Instr]) -> Self {
+        let cfg = ControlFlowGraph::build(code);
+        let mut blocks: Vec<SsaBlock> = cfg
+            .blocks
+            .iter()
+            .map(|block| SsaBlock {
+                phis: vec![],
+                code: block
+                    .code
+                    .iter()
+                    .map(|instr| SsaInstr {
+                        dst: instr.dst.map(Value::of),
+                        op: instr.op.clone(),
+                        args: instr.uses().map(Value::of).collect(),
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        let mut def_blocks: HashMap<Name, Vec<usize>> = HashMap::new();
+        for (i, block) in cfg.blocks.iter().enumerate() {
+            for base in block
+                .code
+                .iter()
+                .filter_map(|instr| instr.dst.and_then(Operand::name))
+            {
+                def_blocks.entry(base).or_default().push(i);
+            }
+        }
+
+        // Phi insertion
+        let frontiers = cfg.dominance_frontiers();
+        let mut bases: Vec<Name> = def_blocks.keys().copied().collect();
+        bases.sort_unstable();
+        for base in bases {
+            let mut work = def_blocks[&base].clone();
+            let mut has_phi: HashSet<usize> = HashSet::new();
+            while let Some(block) = work.pop() {
+                for frontier in &frontiers[block] {
+                    if has_phi.insert(*frontier) {
+                        blocks[*frontier].phis.push(Phi {
+                            dst: Value::Name(base, 0),
+                            args: vec![],
+                        });
+                        work.push(*frontier);
+                    }
+                }
+            }
+        }
+
+        // Renaming
+        let mut renamer = Renamer {
+            successors: &cfg.successors,
+            children: cfg.dominator_tree(),
+            versions: HashMap::new(),
+            stacks: HashMap::new(),
+        };
+        let mut renamed = vec![false; blocks.len()];
+        for block in 0..blocks.len() {
+            if !renamed[block] {
+                renamer.rename(block, &mut blocks, &mut renamed);
+            }
+        }
+
+        let read: HashSet<Name> = code
+            .iter()
+            .flat_map(Instr::uses)
+            .filter_map(Operand::name)
+            .collect();
+        let results = code
+            .iter()
+            .filter_map(|instr| instr.dst.and_then(Operand::name))
+            .filter(|base| matches!(base, Name::Temp(_)) && !read.contains(base))
+            .collect();
+
+        Self {
+            blocks,
+            successors: cfg.successors,
+            results,
+        }
+    }
+
+    /// One line per block header, phi and instruction.
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![];
+        for (i, block) in self.blocks.iter().enumerate() {
+            lines.push(format!("B{}:", i));
+            let (labels, rest): (Vec<&SsaInstr>, Vec<&SsaInstr>) = block
+                .code
+                .iter()
+                .partition(|instr| matches!(instr.op, Op::Label(_)));
+            lines.extend(labels.iter().map(|instr| format!("  {}", instr)));
+            lines.extend(block.phis.iter().map(|phi| format!("  {}", phi)));
+            lines.extend(rest.iter().map(|instr| format!("  {}", instr)));
+        }
+        lines
+    }
+
+    /// Block that a jump to `label` enters.
+    fn label_block(&self, label: usize) -> Option<usize> {
+        self.blocks
+            .iter()
+            .position(|block| matches!(block.code.first(), Some(SsaInstr { op: Op::Label(l), .. }) if *l == label))
+    }
+
+    /// Sparse conditional constant propagation: finds names with a constant
+    /// value, assuming only branches that can actually be taken, then
+    /// replaces them with their values and drops blocks that can never run.
+    /// A branch on a constant condition becomes a `goto` or disappears.
+    pub fn propagate_constants(&self) -> SsaProgram {
+        let mut values: HashMap<(Name, usize), Lattice> = HashMap::new();
+        let mut executable: HashSet<(usize, usize)> = HashSet::new();
+        let mut reachable: HashSet<usize> = HashSet::new();
+        if !self.blocks.is_empty() {
+            reachable.insert(0);
+        }
+
+        let lookup = |values: &HashMap<(Name, usize), Lattice>, value: &Value| match value {
+            Value::Const(c) => Lattice::Const(*c),
+            Value::Name(_, 0) => Lattice::Varying,
+            Value::Name(base, version) => values
+                .get(&(*base, *version))
+                .copied()
+                .unwrap_or(Lattice::Unknown),
+        };
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            let update =
+                |values: &mut HashMap<(Name, usize), Lattice>, dst: &Value, new: Lattice| {
+                    if let Value::Name(base, version) = dst {
+                        let old = values
+                            .get(&(*base, *version))
+                            .copied()
+                            .unwrap_or(Lattice::Unknown);
+                        let merged = old.meet(new);
+                        if merged != old {
+                            values.insert((*base, *version), merged);
+                            return true;
+                        }
+                    }
+                    false
+                };
+
+            for (b, block) in self.blocks.iter().enumerate() {
+                if !reachable.contains(&b) {
+                    continue;
+                }
+
+                for phi in &block.phis {
+                    let merged = phi
+                        .args
+                        .iter()
+                        .filter(|(pred, _)| executable.contains(&(*pred, b)))
+                        .map(|(_, value)| lookup(&values, value))
+                        .fold(Lattice::Unknown, Lattice::meet);
+                    changed |= update(&mut values, &phi.dst, merged);
+                }
+
+                for instr in &block.code {
+                    let Some(dst) = &instr.dst else {
+                        continue;
+                    };
+                    let args: Vec<Lattice> =
+                        instr.args.iter().map(|a| lookup(&values, a)).collect();
+                    changed |= update(&mut values, dst, Lattice::evaluate(&instr.op, &args));
+                }
+
+                for succ in self.live_successors(b, |value| lookup(&values, value)) {
+                    if executable.insert((b, succ)) {
+                        reachable.insert(succ);
+                        changed = true;
+                    }
+                }
+            }
+        }
+
+        // Rewrite with the constants found
+        let substitute = |value: &Value| match lookup(&values, value) {
+            Lattice::Const(c) => Value::Const(c),
+            _ => *value,
+        };
+        let mut blocks: Vec<SsaBlock> = vec![];
+        let mut renumber: HashMap<usize, usize> = HashMap::new();
+        for b in 0..self.blocks.len() {
+            if reachable.contains(&b) {
+                renumber.insert(b, renumber.len());
+            }
+        }
+
+        for (b, block) in self.blocks.iter().enumerate() {
+            if !reachable.contains(&b) {
+                continue;
+            }
+            let mut new_block = SsaBlock::default();
+            let mut constants = vec![];
+            for phi in &block.phis {
+                let args: Vec<(usize, Value)> = phi
+                    .args
+                    .iter()
+                    .filter(|(pred, _)| executable.contains(&(*pred, b)))
+                    .map(|(pred, value)| (renumber[pred], substitute(value)))
+                    .collect();
+                match lookup(&values, &phi.dst) {
+                    Lattice::Const(c) => constants.push(SsaInstr {
+                        dst: Some(phi.dst),
+                        op: Op::Copy,
+                        args: vec![Value::Const(c)],
+                    }),
+                    _ => new_block.phis.push(Phi { dst: phi.dst, args }),
+                }
+            }
+
+            for instr in &block.code {
+                let args: Vec<Value> = instr.args.iter().map(substitute).collect();
+                let instr = match (&instr.op, instr.dst.map(|dst| lookup(&values, &dst))) {
+                    (Op::IfFalse(label), _) => match args[0] {
+                        Value::Const(0.0) => SsaInstr {
+                            dst: None,
+                            op: Op::Goto(*label),
+                            args: vec![],
+                        },
+                        Value::Const(_) => continue,
+                        _ => SsaInstr {
+                            args,
+                            ..instr.clone()
+                        },
+                    },
+                    (Op::Call { .. }, _) => SsaInstr {
+                        args,
+                        ..instr.clone()
+                    },
+                    (_, Some(Lattice::Const(c))) => SsaInstr {
+                        dst: instr.dst,
+                        op: Op::Copy,
+                        args: vec![Value::Const(c)],
+                    },
+                    _ => SsaInstr {
+                        args,
+                        ..instr.clone()
+                    },
+                };
+                new_block.code.push(instr);
+            }
+
+            // Phis that became constants go right after the label
+            let at = usize::from(matches!(
+                new_block.code.first(),
+                Some(SsaInstr {
+                    op: Op::Label(_),
+                    ..
+                })
+            ));
+            new_block.code.splice(at..at, constants);
+            blocks.push(new_block);
+        }
+
+        // A resolved branch may leave a jump to the block that follows anyway
+        for b in 1..blocks.len() {
+            if let (
+                Some(SsaInstr {
+                    op: Op::Goto(target),
+                    ..
+                }),
+                Some(SsaInstr {
+                    op: Op::Label(label),
+                    ..
+                }),
+            ) = (blocks[b - 1].code.last(), blocks[b].code.first())
+                && target == label
+            {
+                blocks[b - 1].code.pop();
+            }
+        }
+
+        SsaProgram::linked(blocks, self.results.clone())
+    }
+
+    /// Removes instructions whose results are never needed. Jumps, calls,
+    /// assignments to user variables and the values of expression
+    /// statements are needed; so is whatever they read, transitively.
+    pub fn eliminate_dead_code(&self) -> SsaProgram {
+        let critical = |instr: &SsaInstr| match instr.dst {
+            Some(Value::Name(base @ Name::Temp(_), _)) => {
+                matches!(instr.op, Op::Call { .. }) || self.results.contains(&base)
+            }
+            _ => true,
+        };
+
+        let mut definitions: HashMap<(Name, usize), Vec<Value>> = HashMap::new();
+        let mut work: Vec<Value> = vec![];
+        for block in &self.blocks {
+            for phi in &block.phis {
+                let args = phi.args.iter().map(|(_, value)| *value).collect();
+                if let Value::Name(base, version) = phi.dst {
+                    definitions.insert((base, version), args);
+                    if matches!(base, Name::Var(_)) {
+                        work.push(phi.dst);
+                    }
+                }
+            }
+            for instr in &block.code {
+                if let Some(Value::Name(base, version)) = instr.dst {
+                    definitions.insert((base, version), instr.args.clone());
+                }
+                if critical(instr) {
+                    work.extend(instr.args.iter().copied());
+                    work.extend(instr.dst);
+                }
+            }
+        }
+
+        let mut live: HashSet<(Name, usize)> = HashSet::new();
+        while let Some(value) = work.pop() {
+            if let Value::Name(base, version) = value
+                && live.insert((base, version))
+                && let Some(args) = definitions.get(&(base, version))
+            {
+                work.extend(args.iter().copied());
+            }
+        }
+
+        let is_live = |value: &Value| match value {
+            Value::Name(base, version) => live.contains(&(*base, *version)),
+            Value::Const(_) => true,
+        };
+        let blocks = self
+            .blocks
+            .iter()
+            .map(|block| SsaBlock {
+                phis: block
+                    .phis
+                    .iter()
+                    .filter(|phi| is_live(&phi.dst))
+                    .cloned()
+                    .collect(),
+                code: block
+                    .code
+                    .iter()
+                    .filter(|instr| critical(instr) || instr.dst.as_ref().is_none_or(is_live))
+                    .cloned()
+                    .collect(),
+            })
+            .collect();
+
+        SsaProgram {
+            blocks,
+            successors: self.successors.clone(),
+            results: self.results.clone(),
+        }
+    }
+
+    /// Leaves SSA: versions are dropped, and each phi becomes a copy at the
+    /// end of the predecessor blocks whose argument isn't already in place.
+    pub fn to_code(&self) -> Vec<Instr> {
+        let mut copies: HashMap<usize, Vec<Instr>> = HashMap::new();
+        for block in &self.blocks {
+            for phi in &block.phis {
+                for (pred, value) in &phi.args {
+                    let (dst, src) = (phi.dst.operand(), value.operand());
+                    if dst != src {
+                        copies.entry(*pred).or_default().push(Instr::copy(dst, src));
+                    }
+                }
+            }
+        }
+
+        let mut code = vec![];
+        for (b, block) in self.blocks.iter().enumerate() {
+            let mut block_code: Vec<Instr> = block
+                .code
+                .iter()
+                .map(|instr| {
+                    let mut args = instr.args.iter().map(|value| value.operand());
+                    Instr {
+                        dst: instr.dst.map(Value::operand),
+                        op: instr.op.clone(),
+                        arg1: args.next(),
+                        arg2: args.next(),
+                    }
+                })
+                .collect();
+
+            if let Some(block_copies) = copies.remove(&b) {
+                let at = match block_code.last() {
+                    Some(last) if last.ends_block() => block_code.len() - 1,
+                    _ => block_code.len(),
+                };
+                block_code.splice(at..at, block_copies);
+            }
+            code.extend(block_code);
+        }
+        code
+    }
+
+    /// Blocks with their successor edges recomputed from the jumps.
+    fn linked(blocks: Vec<SsaBlock>, results: HashSet<Name>) -> SsaProgram {
+        let mut program = SsaProgram {
+            successors: vec![vec![]; blocks.len()],
+            blocks,
+            results,
+        };
+        for b in 0..program.blocks.len() {
+            program.successors[b] = program.live_successors(b, |_| Lattice::Varying);
+        }
+        program
+    }
+
+    /// Successors of block `b` that control can reach given the values of
+    /// branch conditions.
+    fn live_successors(&self, b: usize, value_of: impl Fn(&Value) -> Lattice) -> Vec<usize> {
+        let next = (b + 1 < self.blocks.len()).then_some(b + 1);
+        let Some(last) = self.blocks[b].code.last() else {
+            return next.into_iter().collect();
+        };
+        match &last.op {
+            Op::Goto(label) => self.label_block(*label).into_iter().collect(),
+            Op::Return => vec![],
+            Op::IfFalse(label) => match value_of(&last.args[0]) {
+                Lattice::Unknown => vec![],
+                Lattice::Const(0.0) => self.label_block(*label).into_iter().collect(),
+                Lattice::Const(_) => next.into_iter().collect(),
+                Lattice::Varying => {
+                    let mut succs: Vec<usize> = next.into_iter().collect();
+                    succs.extend(
+                        self.label_block(*label)
+                            .filter(|target| Some(*target) != next),
+                    );
+                    succs
+                }
+            },
+            _ => next.into_iter().collect(),
+        }
+    }
+}
+
+/// Constant propagation lattice: no value seen yet, one constant, or more
+/// than one possible value.
+#[derive(Debug, Clone, Copy)]
+enum Lattice {
+    Unknown,
+    Const(f64),
+    Varying,
+}
+
+/// Bitwise on constants, so that a NaN does not keep the analysis going.
+impl PartialEq for Lattice {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Lattice::Const(a), Lattice::Const(b)) => a.to_bits() == b.to_bits(),
+            _ => std::mem::discriminant(self) == std::mem::discriminant(other),
+        }
+    }
+}
+
+impl Lattice {
+    fn meet(self, other: Lattice) -> Lattice {
+        match (self, other) {
+            (Lattice::Unknown, x) | (x, Lattice::Unknown) => x,
+            (Lattice::Const(a), Lattice::Const(b)) if a.to_bits() == b.to_bits() => self,
+            _ => Lattice::Varying,
+        }
+    }
+
+    fn evaluate(op: &Op, args: &[Lattice]) -> Lattice {
+        if matches!(op, Op::Call { .. }) || args.contains(&Lattice::Varying) {
+            return Lattice::Varying;
+        }
+        if args.contains(&Lattice::Unknown) {
+            return Lattice::Unknown;
+        }
+        let constant = |i: usize| match args.get(i) {
+            Some(Lattice::Const(c)) => *c,
+            _ => 0.0,
+        };
+        match op.fold(constant(0), constant(1)) {
+            Some(c) => Lattice::Const(c),
+            None => Lattice::Varying,
+        }
+    }
+}
+
+/// Versioning state for the dominator-tree walk.
+struct Renamer<'a> {
+    successors: &'a [Vec<usize>],
+    children: Vec<Vec<usize>>,
+    /// Last version handed out per name.
+    versions: HashMap<Name, usize>,
+    /// Versions visible at the current point of the walk.
+    stacks: HashMap<Name, Vec<usize>>,
+}
+
+impl Renamer<'_> {
+    fn current(&self, value: Value) -> Value {
+        match value {
+            Value::Name(base, _) => Value::Name(
+                base,
+                self.stacks
+                    .get(&base)
+                    .and_then(|s| s.last())
+                    .copied()
+                    .unwrap_or(0),
+            ),
+            constant => constant,
+        }
+    }
+
+    fn define(&mut self, value: Value, pushed: &mut Vec<Name>) -> Value {
+        let Value::Name(base, _) = value else {
+            return value;
+        };
+        let version = self.versions.entry(base).or_insert(0);
+        *version += 1;
+        self.stacks.entry(base).or_default().push(*version);
+        pushed.push(base);
+        Value::Name(base, *version)
+    }
+
+    fn rename(&mut self, b: usize, blocks: &mut [SsaBlock], renamed: &mut [bool]) {
+        renamed[b] = true;
+        let mut pushed = vec![];
+
+        for i in 0..blocks[b].phis.len() {
+            let dst = blocks[b].phis[i].dst;
+            blocks[b].phis[i].dst = self.define(dst, &mut pushed);
+        }
+        for i in 0..blocks[b].code.len() {
+            let args: Vec<Value> = blocks[b].code[i]
+                .args
+                .iter()
+                .map(|a| self.current(*a))
+                .collect();
+            blocks[b].code[i].args = args;
+            if let Some(dst) = blocks[b].code[i].dst {
+                blocks[b].code[i].dst = Some(self.define(dst, &mut pushed));
+            }
+        }
+
+        for succ in &self.successors[b] {
+            for i in 0..blocks[*succ].phis.len() {
+                let arg = self.current(blocks[*succ].phis[i].dst);
+                blocks[*succ].phis[i].args.push((b, arg));
+            }
+        }
+
+        for child in self.children[b].clone() {
+            self.rename(child, blocks, renamed);
+        }
+
+        for base in pushed {
+            if let Some(stack) = self.stacks.get_mut(&base) {
+                stack.pop();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{code, listing};
+
+    fn ssa(input: &str) -> SsaProgram {
+        SsaProgram::from_code(&code(input))
+    }
+
+    #[test]
+    fn every_assignment_gets_a_new_version() {
+        assert_eq!(
+            ssa("a = 1; a = a + x; a * 2").lines(),
+            [
+                "B0:",
+                "  id1_1 = 1",
+                "  t1_1 = id1_1 + id2_0",
+                "  id1_2 = t1_1",
+                "  t2_1 = id1_2 * 2",
+            ]
+        );
+    }
+
+    #[test]
+    fn phis_merge_branches_at_the_join() {
+        let program = ssa("a = if(c, 1, 2); a + 1");
+        assert!(
+            program.blocks[..3]
+                .iter()
+                .all(|block| block.phis.is_empty())
+        );
+        let phis: Vec<String> = program.blocks[3].phis.iter().map(Phi::to_string).collect();
+        assert_eq!(phis, ["t1_3 = phi(t1_1 [B1], t1_2 [B2])"]);
+        assert_eq!(program.successors, [vec![1, 2], vec![3], vec![3], vec![]]);
+    }
+
+    #[test]
+    fn constants_flow_through_phis() {
+        let lines = ssa("a = if(c, 1, 2); a + 1").propagate_constants().lines();
+        assert!(lines.contains(&"  t1_3 = phi(1 [B1], 2 [B2])".to_string()));
+    }
+
+    #[test]
+    fn constant_branches_are_folded() {
+        assert_eq!(
+            ssa("a = 2; b = a * 3; c = if(b - 6, x, b)")
+                .propagate_constants()
+                .lines(),
+            [
+                "B0:",
+                "  id1_1 = 2",
+                "  t1_1 = 6",
+                "  id2_1 = 6",
+                "  t2_1 = 0",
+                "B1:",
+                "  L1:",
+                "  t3_2 = 6",
+                "B2:",
+                "  L2:",
+                "  t3_3 = 6",
+                "  id3_1 = 6",
+            ]
+        );
+    }
+
+    #[test]
+    fn untaken_branches_are_dropped() {
+        let program = ssa("c = 1; a = if(c, x, 2); a + 1").propagate_constants();
+        assert_eq!(
+            listing(&program.to_code()),
+            ["id1 = 1", "t1 = id3", "L2:", "id2 = t1", "t2 = id2 + 1"]
+        );
+    }
+
+    #[test]
+    fn dead_code_is_removed_but_results_stay() {
+        let program = ssa("a = 2; b = a * 3; c = if(b - 6, x, b)")
+            .propagate_constants()
+            .eliminate_dead_code();
+        assert_eq!(
+            listing(&program.to_code()),
+            ["id1 = 2", "id2 = 6", "L1:", "L2:", "id3 = 6"]
+        );
+        let program = ssa("x + 1; y = 3").eliminate_dead_code();
+        assert_eq!(listing(&program.to_code()), ["t1 = id1 + 1", "id2 = 3"]);
+    }
+
+    #[test]
+    fn phis_become_copies_in_predecessors() {
+        let input = "a = if(c, 1, 2); a + 1";
+        assert_eq!(listing(&ssa(input).to_code()), listing(&code(input)));
+    }
+}
@@ -0,0 +1,357 @@
+use crate::ir::{self, Instr, Op, Operand};
+use crate::options::FloatMode;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// A rewrite of an operation into a cheaper one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Rule {
+    /// `x ^ 2 → x * x`
+    Square,
+    /// `x ^ 0.5 → sqrt(x)`
+    HalfPower,
+    /// `x * 2 → x + x`
+    Double,
+    /// `x / c → x * (1 / c)` when `1 / c` is exact
+    Reciprocal,
+    /// `x - x → 0`
+    SelfSubtract,
+    /// `x / x → 1`
+    SelfDivide,
+    /// `(x + 2) + 3 → x + 5`, `(x * 2) * 3 → x * 6`
+    Reassociate,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 7] = [
+        Rule::Square,
+        Rule::HalfPower,
+        Rule::Double,
+        Rule::Reciprocal,
+        Rule::SelfSubtract,
+        Rule::SelfDivide,
+        Rule::Reassociate,
+    ];
+
+    /// Short name used on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            Rule::Square => "square",
+            Rule::HalfPower => "half-power",
+            Rule::Double => "double",
+            Rule::Reciprocal => "reciprocal",
+            Rule::SelfSubtract => "self-subtract",
+            Rule::SelfDivide => "self-divide",
+            Rule::Reassociate => "reassociate",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Rule::Square => "x ^ 2 → x * x",
+            Rule::HalfPower => "x ^ 0.5 → sqrt(x)",
+            Rule::Double => "x * 2 → x + x",
+            Rule::Reciprocal => "x / c → x * (1/c) when exact",
+            Rule::SelfSubtract => "x - x → 0",
+            Rule::SelfDivide => "x / x → 1",
+            Rule::Reassociate => "(x + 2) + 3 → x + 5",
+        }
+    }
+
+    /// Inputs for which the rewrite changes the result.
+    pub fn caveat(self) -> Option<&'static str> {
+        match self {
+            Rule::SelfSubtract => Some("not 0 when x is infinite"),
+            Rule::SelfDivide => Some("not 1 when x is 0 or infinite"),
+            _ => None,
+        }
+    }
+
+    /// Whether the rewrite gives the same IEEE-754 result for every input.
+    /// `x ^ 0.5` and `sqrt(x)` differ for `-0` and `-inf`, and
+    /// reassociation rounds differently.
+    pub fn is_exact(self) -> bool {
+        matches!(self, Rule::Square | Rule::Double | Rule::Reciprocal)
+    }
+
+    pub fn from_name(name: &str) -> Option<Rule> {
+        Rule::ALL.into_iter().find(|rule| rule.name() == name)
+    }
+}
+
+/// One instruction rewritten by a rule.
+#[derive(Debug, Clone)]
+pub struct Reduction {
+    /// Position in the code that was reduced, from 1.
+    pub line: usize,
+    pub rule: Rule,
+    pub before: Instr,
+    pub after: Instr,
+}
+
+impl fmt::Display for Reduction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:2}: {}  ⇒  {}  [{}]",
+            self.line,
+            self.before,
+            self.after,
+            self.rule.name()
+        )?;
+        if let Some(caveat) = self.rule.caveat() {
+            write!(f, "  warning: {}", caveat)?;
+        }
+        Ok(())
+    }
+}
+
+/// `base + c` or `base * c`: a temporary that a following constant can be
+/// merged into.
+#[derive(Debug, Clone)]
+struct Chain {
+    op: Op,
+    base: Operand,
+    constant: f64,
+}
+
+/// Applies every rule not in `disabled`, at most one per instruction;
+/// strict mode allows only the exact ones. Reassociation looks back only
+/// within the current basic block.
+pub fn reduce(
+    code: &[Instr],
+    disabled: &HashSet<Rule>,
+    mode: FloatMode,
+) -> (Vec<Instr>, Vec<Reduction>) {
+    let escaping = ir::escaping_temps(code);
+    let mut chains: HashMap<usize, Chain> = HashMap::new();
+    let mut out = vec![];
+    let mut reductions = vec![];
+
+    for (i, instr) in code.iter().enumerate() {
+        if matches!(instr.op, Op::Label(_)) {
+            chains.clear();
+        }
+
+        let rewritten = Rule::ALL
+            .into_iter()
+            .filter(|rule| !disabled.contains(rule))
+            .filter(|rule| mode == FloatMode::FastMath || rule.is_exact())
+            .find_map(|rule| apply(rule, instr, &chains).map(|after| (rule, after)));
+        let after = match rewritten {
+            Some((rule, after)) => {
+                reductions.push(Reduction {
+                    line: i + 1,
+                    rule,
+                    before: instr.clone(),
+                    after: after.clone(),
+                });
+                after
+            }
+            None => instr.clone(),
+        };
+
+        if let Some(dst) = after.dst {
+            chains.retain(|_, chain| chain.base != dst);
+            if let Operand::Temp(n) = dst
+                && !escaping.contains(&n)
+                && let Some(chain) = chain_of(&after).or_else(|| chain_of(instr))
+            {
+                chains.insert(n, chain);
+            }
+        }
+        if after.ends_block() {
+            chains.clear();
+        }
+        out.push(after);
+    }
+
+    // Temporaries skipped over by reassociation are no longer read
+    (ir::remove_dead_temps(code, out), reductions)
+}
+
+fn chain_of(instr: &Instr) -> Option<Chain> {
+    let (a, b) = (instr.arg1?, instr.arg2?);
+    let (op, base, constant) = match (&instr.op, a, b) {
+        (_, Operand::Const(_), Operand::Const(_)) => return None,
+        (Op::Add | Op::Mul, base, Operand::Const(c))
+        | (Op::Add | Op::Mul, Operand::Const(c), base) => (instr.op.clone(), base, c),
+        (Op::Sub, base, Operand::Const(c)) => (Op::Add, base, -c),
+        _ => return None,
+    };
+    Some(Chain { op, base, constant })
+}
+
+/// `instr` rewritten by `rule`, if it applies.
+fn apply(rule: Rule, instr: &Instr, chains: &HashMap<usize, Chain>) -> Option<Instr> {
+    let dst = instr.dst?;
+    let (a, b) = (instr.arg1?, instr.arg2?);
+    let is = |operand: Operand, c: f64| operand == Operand::Const(c);
+    let variable = |operand: Operand| !matches!(operand, Operand::Const(_));
+
+    match (rule, &instr.op) {
+        (Rule::Square, Op::Pow) if variable(a) && is(b, 2.0) => {
+            Some(Instr::binary(dst, Op::Mul, a, a))
+        }
+        (Rule::HalfPower, Op::Pow) if variable(a) && is(b, 0.5) => {
+            Some(Instr::unary(dst, Op::Sqrt, a))
+        }
+        (Rule::Double, Op::Mul) if variable(a) && is(b, 2.0) => {
+            Some(Instr::binary(dst, Op::Add, a, a))
+        }
+        (Rule::Double, Op::Mul) if is(a, 2.0) && variable(b) => {
+            Some(Instr::binary(dst, Op::Add, b, b))
+        }
+        (Rule::Reciprocal, Op::Div) if variable(a) => match b {
+            Operand::Const(c) if c != 1.0 && exact_reciprocal(c) => {
+                Some(Instr::binary(dst, Op::Mul, a, Operand::Const(1.0 / c)))
+            }
+            _ => None,
+        },
+        (Rule::SelfSubtract, Op::Sub) if variable(a) && a == b => {
+            Some(Instr::copy(dst, Operand::Const(0.0)))
+        }
+        (Rule::SelfDivide, Op::Div) if variable(a) && a == b => {
+            Some(Instr::copy(dst, Operand::Const(1.0)))
+        }
+        (Rule::Reassociate, _) => {
+            let outer = chain_of(instr)?;
+            let Operand::Temp(n) = outer.base else {
+                return None;
+            };
+            let inner = chains.get(&n).filter(|inner| inner.op == outer.op)?;
+            let constant = match outer.op {
+                Op::Add => inner.constant + outer.constant,
+                _ => inner.constant * outer.constant,
+            };
+            Some(match outer.op {
+                Op::Add if constant < 0.0 => {
+                    Instr::binary(dst, Op::Sub, inner.base, Operand::Const(-constant))
+                }
+                op => Instr::binary(dst, op, inner.base, Operand::Const(constant)),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Whether `1 / c` is exactly representable, i.e. `c` is a power of two.
+fn exact_reciprocal(c: f64) -> bool {
+    const MANTISSA: u64 = (1 << 52) - 1;
+    c.is_normal() && c.to_bits() & MANTISSA == 0 && (1.0 / c).is_normal()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{code, listing};
+
+    /// Each rule with a program it rewrites and the code it rewrites it to.
+    const CASES: &[(Rule, &str, &[&str])] = &[
+        (Rule::Square, "a = x ^ 2", &["t1 = id2 * id2", "id1 = t1"]),
+        (
+            Rule::HalfPower,
+            "a = x ^ 0.5",
+            &["t1 = sqrt(id2)", "id1 = t1"],
+        ),
+        (Rule::Double, "a = x * 2", &["t1 = id2 + id2", "id1 = t1"]),
+        (Rule::Double, "a = 2 * x", &["t1 = id2 + id2", "id1 = t1"]),
+        (
+            Rule::Reciprocal,
+            "a = x / 4",
+            &["t1 = id2 * 0.25", "id1 = t1"],
+        ),
+        (Rule::SelfSubtract, "a = x - x", &["t1 = 0", "id1 = t1"]),
+        (Rule::SelfDivide, "a = x / x", &["t1 = 1", "id1 = t1"]),
+        (
+            Rule::Reassociate,
+            "a = (x + 2) + 3",
+            &["t2 = id2 + 5", "id1 = t2"],
+        ),
+        (
+            Rule::Reassociate,
+            "a = (x + 2) - 5",
+            &["t2 = id2 - 3", "id1 = t2"],
+        ),
+        (
+            Rule::Reassociate,
+            "a = (x * 3) * 4",
+            &["t2 = id2 * 12", "id1 = t2"],
+        ),
+    ];
+
+    fn reduced(input: &str, disabled: &HashSet<Rule>, mode: FloatMode) -> Vec<String> {
+        listing(&reduce(&code(input), disabled, mode).0)
+    }
+
+    #[test]
+    fn every_rule_rewrites_under_fast_math() {
+        for (rule, input, expected) in CASES {
+            let (out, reductions) = reduce(&code(input), &HashSet::new(), FloatMode::FastMath);
+            assert_eq!(listing(&out), *expected, "{}", input);
+            let rules: Vec<Rule> = reductions.iter().map(|reduction| reduction.rule).collect();
+            assert_eq!(rules, [*rule], "{}", input);
+        }
+    }
+
+    #[test]
+    fn strict_mode_applies_only_exact_rules() {
+        let exact: Vec<Rule> = Rule::ALL
+            .into_iter()
+            .filter(|rule| rule.is_exact())
+            .collect();
+        assert_eq!(exact, [Rule::Square, Rule::Double, Rule::Reciprocal]);
+        for (rule, input, expected) in CASES {
+            let out = reduced(input, &HashSet::new(), FloatMode::Strict);
+            if rule.is_exact() {
+                assert_eq!(out, *expected, "{}", input);
+            } else {
+                assert_eq!(out, listing(&code(input)), "{}", input);
+            }
+        }
+    }
+
+    #[test]
+    fn disabled_rules_do_not_apply() {
+        for (rule, input, _) in CASES {
+            let disabled = HashSet::from([*rule]);
+            assert_eq!(
+                reduced(input, &disabled, FloatMode::FastMath),
+                listing(&code(input)),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn rules_leave_other_operations_alone() {
+        for input in [
+            "a = x / 3",
+            "a = x / 0",
+            "a = (x + 2) * 3",
+            "a = 2 ^ 2",
+            "a = x - y",
+        ] {
+            assert_eq!(
+                reduced(input, &HashSet::new(), FloatMode::FastMath),
+                listing(&code(input)),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn reductions_report_line_and_caveat() {
+        let (_, reductions) = reduce(
+            &code("a = 1; b = a - a"),
+            &HashSet::new(),
+            FloatMode::FastMath,
+        );
+        assert_eq!(
+            reductions[0].to_string(),
+            " 2: t1 = id1 - id1  ⇒  t1 = 0  [self-subtract]  warning: not 0 when x is infinite"
+        );
+    }
+}
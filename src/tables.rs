@@ -0,0 +1,122 @@
+use crate::ir::{self, Instr, Op, Operand};
+use std::collections::HashMap;
+
+/// `(op, arg1, arg2, result)`: every operand, temporaries included, is named.
+#[derive(Debug, Clone)]
+pub struct Quadruple {
+    pub op: String,
+    pub arg1: String,
+    pub arg2: String,
+    pub result: String,
+}
+
+/// `(op, arg1, arg2)`: a temporary is replaced by `(i)`, the position of
+/// the triple that computes it.
+#[derive(Debug, Clone)]
+pub struct Triple {
+    pub op: String,
+    pub arg1: String,
+    pub arg2: String,
+}
+
+/// Triples plus a statement list of positions into them, so statements
+/// can be reordered without renumbering the triples.
+#[derive(Debug, Clone)]
+pub struct IndirectTriples {
+    pub statements: Vec<usize>,
+    pub triples: Vec<Triple>,
+}
+
+fn operand(operand: Option<Operand>) -> String {
+    operand.map(|o| o.to_string()).unwrap_or_default()
+}
+
+/// Operator and argument columns shared by every table; calls show their
+/// name in `arg1` and argument count in `arg2`.
+fn columns(instr: &Instr, arg: impl Fn(Option<Operand>) -> String) -> (String, String, String) {
+    match &instr.op {
+        Op::Call { name, argc } => ("call".to_string(), name.clone(), argc.to_string()),
+        Op::Copy => ("=".to_string(), arg(instr.arg1), String::new()),
+        // Triples have no result column, so jump targets go in the
+        // first free argument
+        Op::Label(label) | Op::Goto(label) => (
+            instr.op.symbol().to_string(),
+            format!("L{}", label),
+            String::new(),
+        ),
+        Op::IfFalse(label) => (
+            instr.op.symbol().to_string(),
+            arg(instr.arg1),
+            format!("L{}", label),
+        ),
+        op => (op.symbol().to_string(), arg(instr.arg1), arg(instr.arg2)),
+    }
+}
+
+pub fn quadruples(code: &[Instr]) -> Vec<Quadruple> {
+    code.iter()
+        .map(|instr| {
+            let (op, mut arg1, mut arg2) = columns(instr, operand);
+            // Quadruples name jump targets in the result column
+            let result = match instr.op {
+                Op::Label(label) | Op::Goto(label) => {
+                    arg1.clear();
+                    format!("L{}", label)
+                }
+                Op::IfFalse(label) => {
+                    arg2.clear();
+                    format!("L{}", label)
+                }
+                _ => operand(instr.dst),
+            };
+            Quadruple {
+                op,
+                arg1,
+                arg2,
+                result,
+            }
+        })
+        .collect()
+}
+
+pub fn triples(code: &[Instr]) -> Vec<Triple> {
+    // A temporary assigned on both sides of a branch has no single triple
+    // to point at, so it keeps its name
+    let escaping = ir::escaping_temps(code);
+    let mut positions: HashMap<usize, usize> = HashMap::new();
+    code.iter()
+        .enumerate()
+        .map(|(i, instr)| {
+            let reference = |arg: Option<Operand>| match arg {
+                Some(Operand::Temp(n)) if positions.contains_key(&n) => {
+                    format!("({})", positions[&n])
+                }
+                other => operand(other),
+            };
+            let (op, arg1, arg2) = match (&instr.op, instr.dst) {
+                // A copy into a variable (or a named temporary) names it as
+                // the first argument, since a triple has no result column
+                (Op::Copy, Some(dst))
+                    if !matches!(dst, Operand::Temp(n) if !escaping.contains(&n)) =>
+                {
+                    ("=".to_string(), dst.to_string(), reference(instr.arg1))
+                }
+                _ => columns(instr, reference),
+            };
+            if let Some(Operand::Temp(n)) = instr.dst
+                && !escaping.contains(&n)
+            {
+                positions.insert(n, i);
+            }
+            Triple { op, arg1, arg2 }
+        })
+        .collect()
+}
+
+pub fn indirect_triples(code: &[Instr]) -> IndirectTriples {
+    let triples = triples(code);
+    IndirectTriples {
+        statements: (0..triples.len()).collect(),
+        triples,
+    }
+}
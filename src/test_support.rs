@@ -0,0 +1,251 @@
+use crate::ast::{ASTNode, Program};
+use crate::builtins::BUILTINS;
+use crate::environment::Environment;
+use crate::ir::Instr;
+use crate::lexer::Lexer;
+use crate::liveness;
+use crate::parser::{ParseResult, Parser};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Parses a program that must be free of errors.
+pub fn parse(input: &str) -> Program {
+    let mut lexer = Lexer::new(input);
+    let mut parser = Parser::new(lexer.tokenize());
+    let program = parser.parse();
+    let (lex_errors, parse_errors) = (lexer.take_errors(), parser.take_errors());
+    assert!(
+        lex_errors.is_empty() && parse_errors.is_empty(),
+        "{}: {:?} {:?}",
+        input,
+        lex_errors,
+        parse_errors
+    );
+    program
+}
+
+/// Unoptimized three-address code of a program, user functions inlined.
+pub fn code(input: &str) -> Vec<Instr> {
+    parse(input).inline_functions().to_three_address_code()
+}
+
+/// Each instruction as the listings print it.
+pub fn listing(code: &[Instr]) -> Vec<String> {
+    code.iter().map(Instr::to_string).collect()
+}
+
+/// An environment binding each input.
+pub fn environment(inputs: &[(&str, f64)]) -> Environment {
+    let mut env = Environment::new();
+    for (name, value) in inputs {
+        env.set(name, *value);
+    }
+    env
+}
+
+/// Programs every backend must agree with the evaluator on, with the
+/// values of their inputs.
+pub const CORPUS: &[(&str, &[(&str, f64)])] = &[
+    ("a = 2; b = a * 3; c = a + b - 1 / b", &[]),
+    ("y = x ^ 2 + 2 * x + 1; x = -y", &[("x", 3.0)]),
+    ("-x + sqrt(x)", &[("x", 16.0)]),
+    ("a = b = 5; a + b", &[]),
+    ("x = 0; if(x, 1, 2) + if(x - 1, 3, 4)", &[]),
+    ("a = if(c, 1, 2)", &[("c", f64::NAN)]),
+    (
+        "a = if(c, if(d, 1, 2), if(d, 3, 4))",
+        &[("c", 0.0), ("d", 1.0)],
+    ),
+    ("f(n) = if(n, n * 2, -1); f(0) + f(x)", &[("x", 3.0)]),
+    (
+        "f(n) = n * 2; g(m, k) = f(m) - k; a = g(x, 1)",
+        &[("x", 2.5)],
+    ),
+    ("x + 1; a = 2 * x; a ^ 2; sqrt(a)", &[("x", 4.5)]),
+    ("1 + 2; 3", &[]),
+    ("a = x / 0; b = a - a; c = 0 * a", &[("x", -1.0)]),
+];
+
+/// Programs whose last statement reduces to a plain name or constant,
+/// which the native backends must still print as the result.
+pub const NAME_RESULTS: &[(&str, &[(&str, f64)])] = &[
+    ("x", &[("x", 1.5)]),
+    ("a = 2; a", &[]),
+    ("+x", &[("x", 1.5)]),
+    ("-(-x)", &[("x", 1.5)]),
+    ("y = 2; y * 1", &[]),
+    ("y = a * b; a * b", &[("a", 2.0), ("b", 3.0)]),
+    ("3", &[]),
+];
+
+/// A call of every built-in on the inputs `p`, `q` and `r`.
+pub fn builtin_cases() -> Vec<(String, &'static [(&'static str, f64)])> {
+    BUILTINS
+        .iter()
+        .map(|builtin| {
+            let args = ["p", "q", "r"][..builtin.arity].join(", ");
+            let inputs: &[(&str, f64)] = &[("p", 0.7), ("q", 2.5), ("r", -1.5)];
+            (format!("a = {}({})", builtin.name, args), inputs)
+        })
+        .collect()
+}
+
+/// Which statement value a backend reports.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reported {
+    /// The last statement's, assignments included.
+    Last,
+    /// The last statement's when it is an expression rather than an
+    /// assignment.
+    TrailingExpression,
+    Nothing,
+}
+
+/// What a backend printed or returned after running a program.
+#[derive(Debug, Default)]
+pub struct Run {
+    pub result: Option<f64>,
+    /// Final values of the variables the backend reports.
+    pub vars: Vec<(String, f64)>,
+}
+
+/// Equal bits, or both NaN: printed NaNs lose their payload.
+pub fn same(a: f64, b: f64) -> bool {
+    a.to_bits() == b.to_bits() || a.is_nan() && b.is_nan()
+}
+
+/// Runs `input` through `run` and checks that its result and every
+/// variable it reports agree with `Program::evaluate` from the same
+/// inputs.
+pub fn check(
+    input: &str,
+    inputs: &[(&str, f64)],
+    reported: Reported,
+    run: impl Fn(&str, &[(&str, f64)]) -> Run,
+) {
+    let program = parse(input);
+    let mut env = environment(inputs);
+    let last = program.evaluate(&mut env).unwrap();
+    let actual = run(input, inputs);
+
+    let ends_in_assignment = matches!(
+        program
+            .statements
+            .iter()
+            .rfind(|statement| !matches!(statement, ASTNode::FunctionDef { .. })),
+        Some(ASTNode::BinaryOp { op: '=', .. })
+    );
+    let expected = match reported {
+        Reported::Last => last,
+        Reported::TrailingExpression if !ends_in_assignment => last,
+        _ => None,
+    };
+    match (actual.result, expected) {
+        (Some(actual), Some(expected)) => {
+            assert!(
+                same(actual, expected),
+                "{}: result {} ≠ {}",
+                input,
+                actual,
+                expected
+            )
+        }
+        (None, None) => {}
+        (actual, expected) => panic!("{}: result {:?}, expected {:?}", input, actual, expected),
+    }
+    for (name, value) in &actual.vars {
+        let expected = env.get(name);
+        assert!(
+            expected.is_some_and(|expected| same(*value, expected)),
+            "{}: {} = {}, expected {:?}",
+            input,
+            name,
+            value,
+            expected
+        );
+    }
+}
+
+/// `check` over the corpus and a call of every built-in.
+pub fn check_corpus(reported: Reported, run: impl Fn(&str, &[(&str, f64)]) -> Run) {
+    for (input, inputs) in CORPUS {
+        check(input, inputs, reported, &run);
+    }
+    for (input, inputs) in builtin_cases() {
+        check(&input, inputs, reported, &run);
+    }
+}
+
+/// Whether there is a system `cc` to build the native backends' output
+/// with; their tests pass vacuously without one.
+pub fn has_cc() -> bool {
+    Command::new("cc").arg("--version").output().is_ok()
+}
+
+/// The values of a compiled program's inputs in the order `main` takes
+/// them as arguments.
+pub fn native_args(result: &ParseResult, inputs: &[(&str, f64)]) -> Vec<String> {
+    liveness::inputs(&result.optimized_three_address_code)
+        .into_iter()
+        .map(|n| {
+            let (name, _) = result
+                .identifier_table
+                .iter()
+                .find(|(_, idx)| *idx == n)
+                .unwrap();
+            let (_, value) = inputs.iter().find(|(input, _)| input == name).unwrap();
+            value.to_string()
+        })
+        .collect()
+}
+
+/// Builds `source`, saved as `file`, with `cc -Wall -Wextra -Werror`, runs
+/// it with `args` and reads back the `name = value` lines it prints, the
+/// `result` line being the program's result.
+pub fn run_native(file: &str, source: &str, args: &[String]) -> Run {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!(
+        "rust-sandbox-{}-{}",
+        std::process::id(),
+        COUNT.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (path, binary) = (dir.join(file), dir.join("program"));
+    std::fs::write(&path, source).unwrap();
+
+    let compile = Command::new("cc")
+        .args(["-Wall", "-Wextra", "-Werror", "-o"])
+        .arg(&binary)
+        .arg(&path)
+        .arg("-lm")
+        .output()
+        .unwrap();
+    assert!(
+        compile.status.success(),
+        "{}\n{}",
+        source,
+        String::from_utf8_lossy(&compile.stderr)
+    );
+    let output = Command::new(&binary).args(args).output().unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(
+        output.status.success(),
+        "{}\n{}",
+        source,
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let mut run = Run::default();
+    for line in String::from_utf8(output.stdout).unwrap().lines() {
+        let (name, value) = line.split_once(" = ").unwrap();
+        // glibc prints `-nan` for NaN with the sign bit set
+        let value = if value == "-nan" { "nan" } else { value };
+        let value: f64 = value.parse().unwrap();
+        if name == "result" {
+            run.result = Some(value);
+        } else {
+            run.vars.push((name.to_string(), value));
+        }
+    }
+    run
+}
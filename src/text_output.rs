@@ -0,0 +1,314 @@
+use crate::diagnostics::Diagnostic;
+use crate::environment::Environment;
+use crate::ir::Instr;
+use crate::liveness;
+use crate::parser::ParseResult;
+use crate::tables;
+use crate::vm::Vm;
+use std::fmt::Write;
+
+/// Renders every compiler phase of `result` as plain text, the terminal
+/// counterpart of the GUI. Syntax errors come first, followed by whatever
+/// phases could still run on the partial tree. The program is evaluated
+/// starting from the variable values in `env`.
+pub fn render(result: &ParseResult, mut env: Environment) -> String {
+    let mut out = String::new();
+
+    if !result.errors.is_empty() {
+        section(
+            &mut out,
+            &format!("{} Syntax Error(s)", result.errors.len()),
+        );
+        for err in &result.errors {
+            out.push_str(&Diagnostic::from(err).render(&result.source));
+        }
+    }
+
+    section(&mut out, "Lexical Analysis");
+    let tokens: Vec<String> = result.tokens.iter().map(|t| t.to_string()).collect();
+    writeln!(out, "{}", tokens.join(" ")).unwrap();
+    for (name, idx) in &result.identifier_table {
+        writeln!(out, "  id{} = {}", idx, name).unwrap();
+    }
+
+    section(&mut out, "Syntax Analysis");
+    for statement in &result.ast.statements {
+        writeln!(out, "{}", statement).unwrap();
+    }
+
+    section(&mut out, "Semantic Analysis");
+    if result.semantic_diagnostics.is_empty() {
+        writeln!(out, "no semantic issues").unwrap();
+    }
+    for diagnostic in &result.semantic_diagnostics {
+        out.push_str(&diagnostic.render(&result.source));
+    }
+
+    if result.has_errors() {
+        return out;
+    }
+
+    section(&mut out, "Intermediate Code");
+    listing(&mut out, &result.three_address_code);
+    for (signature, code) in &result.function_code {
+        writeln!(out, "function {}:", signature).unwrap();
+        listing(&mut out, code);
+    }
+
+    let quadruples = tables::quadruples(&result.three_address_code);
+    section(&mut out, "Quadruples");
+    table(
+        &mut out,
+        &["#", "op", "arg1", "arg2", "result"],
+        quadruples
+            .iter()
+            .enumerate()
+            .map(|(i, q)| {
+                vec![
+                    i.to_string(),
+                    q.op.clone(),
+                    q.arg1.clone(),
+                    q.arg2.clone(),
+                    q.result.clone(),
+                ]
+            })
+            .collect(),
+    );
+
+    let indirect = tables::indirect_triples(&result.three_address_code);
+    section(&mut out, "Triples");
+    table(
+        &mut out,
+        &["#", "op", "arg1", "arg2"],
+        indirect
+            .triples
+            .iter()
+            .enumerate()
+            .map(|(i, t)| {
+                vec![
+                    format!("({})", i),
+                    t.op.clone(),
+                    t.arg1.clone(),
+                    t.arg2.clone(),
+                ]
+            })
+            .collect(),
+    );
+
+    section(&mut out, "Indirect Triples");
+    table(
+        &mut out,
+        &["statement", "triple"],
+        indirect
+            .statements
+            .iter()
+            .enumerate()
+            .map(|(i, pos)| vec![i.to_string(), format!("({})", pos)])
+            .collect(),
+    );
+
+    section(&mut out, "Control-Flow Graph");
+    let idoms = result.cfg.immediate_dominators();
+    let block_names = |blocks: &[usize]| -> String {
+        let names: Vec<String> = blocks.iter().map(|b| format!("B{}", b)).collect();
+        if names.is_empty() {
+            "-".to_string()
+        } else {
+            names.join(", ")
+        }
+    };
+    for (i, block) in result.cfg.blocks.iter().enumerate() {
+        writeln!(
+            out,
+            "B{} (from line {}): pred {}; succ {}; idom {}",
+            i,
+            block.start + 1,
+            block_names(&result.cfg.predecessors[i]),
+            block_names(&result.cfg.successors[i]),
+            block_names(&idoms[i].into_iter().collect::<Vec<_>>()),
+        )
+        .unwrap();
+        for instr in &block.code {
+            writeln!(out, "    {}", instr).unwrap();
+        }
+    }
+    for (i, children) in result.cfg.dominator_tree().iter().enumerate() {
+        if !children.is_empty() {
+            writeln!(out, "dominator tree: B{} -> {}", i, block_names(children)).unwrap();
+        }
+    }
+
+    section(&mut out, "Liveness");
+    table(
+        &mut out,
+        &["#", "instruction", "live in", "live out"],
+        result
+            .three_address_code
+            .iter()
+            .enumerate()
+            .map(|(i, instr)| {
+                vec![
+                    (i + 1).to_string(),
+                    instr.to_string(),
+                    liveness::format_set(&result.liveness.live_in[i]),
+                    liveness::format_set(&result.liveness.live_out[i]),
+                ]
+            })
+            .collect(),
+    );
+
+    if let Some((ssa_form, ssa_optimized)) = &result.ssa {
+        section(&mut out, "SSA Form");
+        for line in ssa_form.lines() {
+            writeln!(out, "{}", line).unwrap();
+        }
+        writeln!(out, "after constant propagation and dead code elimination:").unwrap();
+        for line in ssa_optimized.lines() {
+            writeln!(out, "{}", line).unwrap();
+        }
+    }
+
+    section(&mut out, "Optimization Passes");
+    writeln!(out, "floating point: {}", result.float_mode.label()).unwrap();
+    for run in &result.passes {
+        if !run.changed() {
+            writeln!(out, "round {}: {}: no change", run.round, run.pass.title()).unwrap();
+            for note in &run.notes {
+                writeln!(out, "  {}", note).unwrap();
+            }
+            continue;
+        }
+        writeln!(
+            out,
+            "round {}: {}: {} → {} instructions",
+            run.round,
+            run.pass.title(),
+            run.before.len(),
+            run.after.len()
+        )
+        .unwrap();
+        for note in &run.notes {
+            writeln!(out, "  {}", note).unwrap();
+        }
+        listing(&mut out, &run.after);
+    }
+
+    section(&mut out, "Optimized Code");
+    listing(&mut out, &result.optimized_three_address_code);
+
+    section(&mut out, "DAG");
+    for (i, node) in result.dag.nodes.iter().enumerate() {
+        let children: Vec<String> = node.children.iter().map(|c| format!("n{}", c)).collect();
+        write!(out, "n{}: {}", i, node.label).unwrap();
+        if !children.is_empty() {
+            write!(out, " ({})", children.join(", ")).unwrap();
+        }
+        if !node.names.is_empty() {
+            write!(out, " [{}]", node.names.join(", ")).unwrap();
+        }
+        out.push('\n');
+    }
+
+    let allocation = &result.allocation;
+    section(&mut out, "Register Allocation");
+    writeln!(out, "registers: {}", allocation.registers).unwrap();
+    let edges: Vec<String> = allocation
+        .graph
+        .edges()
+        .map(|(a, b)| format!("t{} - t{}", a, b))
+        .collect();
+    if edges.is_empty() {
+        writeln!(out, "interference: none").unwrap();
+    } else {
+        writeln!(out, "interference: {}", edges.join(", ")).unwrap();
+    }
+    for temp in allocation.spilled.keys() {
+        writeln!(out, "spilled: {}", allocation.describe_spill(*temp)).unwrap();
+    }
+    for (temp, register) in &allocation.assignment {
+        writeln!(out, "t{}: r{}", temp, register).unwrap();
+    }
+    if !allocation.spilled.is_empty() {
+        writeln!(out, "with spill code:").unwrap();
+        listing(&mut out, &allocation.code);
+    }
+
+    section(&mut out, "Assembly (x86-64)");
+    // Each instruction's lines are preceded by the code they came from
+    let mut source = None;
+    for line in &result.assembly.lines {
+        if line.source != source
+            && let Some(i) = line.source
+        {
+            let instr = &allocation.code[i];
+            writeln!(out, "    # {}: {}", i + 1, instr).unwrap();
+        }
+        source = line.source;
+        writeln!(out, "{}", line.text).unwrap();
+    }
+
+    section(&mut out, "C Source");
+    write!(out, "{}", result.c_source).unwrap();
+
+    section(&mut out, "Bytecode");
+    out.push_str(&result.bytecode.to_string());
+    match Vm::new(env.clone()).run(&result.bytecode) {
+        Ok(Some(value)) => writeln!(out, "vm result: {}", value).unwrap(),
+        Ok(None) => writeln!(out, "vm result: none").unwrap(),
+        Err(err) => writeln!(out, "vm error: {}", err).unwrap(),
+    }
+
+    section(&mut out, "WebAssembly");
+    out.push_str(&result.wasm.to_wat());
+    writeln!(out, "binary: {} bytes", result.wasm.to_wasm().len()).unwrap();
+
+    section(&mut out, "Result");
+    match result.ast.evaluate(&mut env) {
+        Ok(Some(value)) if !value.is_finite() => {
+            writeln!(out, "{}  (warning: not a finite number)", value).unwrap()
+        }
+        Ok(Some(value)) => writeln!(out, "{}", value).unwrap(),
+        Ok(None) => writeln!(out, "no expression to evaluate").unwrap(),
+        Err(err) => out.push_str(&Diagnostic::from(&err).render(&result.source)),
+    }
+    for (name, _) in &result.identifier_table {
+        if let Some(value) = env.get(name) {
+            writeln!(out, "  {} = {}", name, value).unwrap();
+        }
+    }
+
+    out
+}
+
+fn section(out: &mut String, title: &str) {
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    writeln!(out, "== {} ==", title).unwrap();
+}
+
+fn listing(out: &mut String, code: &[Instr]) {
+    for (i, line) in code.iter().enumerate() {
+        writeln!(out, "{:2}: {}", i + 1, line).unwrap();
+    }
+}
+
+/// Left-aligned columns padded to their widest cell.
+fn table(out: &mut String, headers: &[&str], rows: Vec<Vec<String>>) {
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.chars().count()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let header: Vec<String> = headers.iter().map(|h| h.to_string()).collect();
+    for row in std::iter::once(&header).chain(&rows) {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect();
+        writeln!(out, "{}", cells.join("  ").trim_end()).unwrap();
+    }
+}
@@ -1,7 +1,8 @@
+use crate::span::Span;
 use std::fmt;
 
-#[derive(Debug, Clone)]
-pub enum Token {
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
     Number(f64),
     Identifier(String, usize),
     Plus,
@@ -11,24 +12,58 @@ pub enum Token {
     Power,
     LParen,
     RParen,
-    Sqrt,
+    Function(String),
+    Comma,
     Assign,
+    Semicolon,
+    Newline,
+    /// A malformed lexeme; the lexer has already reported it.
+    Error(String),
 }
 
-impl fmt::Display for Token {
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+impl Token {
+    pub fn new(kind: TokenKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+}
+
+impl TokenKind {
+    /// Whether this token ends a statement.
+    pub fn is_separator(&self) -> bool {
+        matches!(self, TokenKind::Semicolon | TokenKind::Newline)
+    }
+}
+
+impl fmt::Display for TokenKind {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Token::Number(n) => write!(f, "NUMBER({})", n),
-            Token::Identifier(_, idx) => write!(f, "id{}", idx),
-            Token::Plus => write!(f, "PLUS"),
-            Token::Minus => write!(f, "MINUS"),
-            Token::Multiply => write!(f, "MUL"),
-            Token::Divide => write!(f, "DIV"),
-            Token::Power => write!(f, "POW"),
-            Token::LParen => write!(f, "LPAREN"),
-            Token::RParen => write!(f, "RPAREN"),
-            Token::Sqrt => write!(f, "SQRT"),
-            Token::Assign => write!(f, "ASSIGN"),
+            TokenKind::Number(n) => write!(f, "NUMBER({})", n),
+            TokenKind::Identifier(_, idx) => write!(f, "id{}", idx),
+            TokenKind::Plus => write!(f, "PLUS"),
+            TokenKind::Minus => write!(f, "MINUS"),
+            TokenKind::Multiply => write!(f, "MUL"),
+            TokenKind::Divide => write!(f, "DIV"),
+            TokenKind::Power => write!(f, "POW"),
+            TokenKind::LParen => write!(f, "LPAREN"),
+            TokenKind::RParen => write!(f, "RPAREN"),
+            TokenKind::Function(name) => write!(f, "{}", name.to_uppercase()),
+            TokenKind::Comma => write!(f, "COMMA"),
+            TokenKind::Assign => write!(f, "ASSIGN"),
+            TokenKind::Semicolon => write!(f, "SEMI"),
+            TokenKind::Newline => write!(f, "NEWLINE"),
+            TokenKind::Error(text) => write!(f, "ERROR({})", text),
         }
     }
 }
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.kind)
+    }
+}
@@ -1,5 +1,9 @@
-use crate::ast::ASTNode;
+use crate::ast::{ASTNode, Program};
+use crate::cfg::ControlFlowGraph;
+use crate::dag::Dag;
+use crate::regalloc::Allocation;
 use eframe::egui;
+use std::collections::HashMap;
 
 // Tree layout constants
 const NODE_WIDTH: f32 = 120.0;
@@ -7,11 +11,19 @@ const NODE_HEIGHT: f32 = 40.0;
 const LEVEL_SPACING: f32 = 80.0;
 const SIBLING_SPACING: f32 = 20.0;
 
+// Control-flow graph layout constants
+const CFG_CHAR_WIDTH: f32 = 8.0;
+const CFG_LINE_HEIGHT: f32 = 17.0;
+const CFG_BLOCK_PADDING: f32 = 8.0;
+
 // Colors
 const OPERATOR_COLOR: egui::Color32 = egui::Color32::from_rgb(220, 50, 50);
 const NUMBER_COLOR: egui::Color32 = egui::Color32::from_rgb(50, 150, 220);
 const VARIABLE_COLOR: egui::Color32 = egui::Color32::from_rgb(150, 100, 200);
 const FUNCTION_COLOR: egui::Color32 = egui::Color32::from_rgb(220, 140, 50);
+const BLOCK_COLOR: egui::Color32 = egui::Color32::from_rgb(45, 60, 80);
+const PROGRAM_COLOR: egui::Color32 = egui::Color32::from_rgb(60, 140, 90);
+const ERROR_COLOR: egui::Color32 = egui::Color32::from_rgb(90, 90, 90);
 const LINE_COLOR: egui::Color32 = egui::Color32::from_rgb(100, 100, 100);
 
 #[derive(Clone)]
@@ -24,36 +36,87 @@ struct TreeNode {
 }
 
 impl TreeNode {
+    fn from_program(program: &Program) -> Self {
+        TreeNode {
+            pos: egui::Pos2::ZERO,
+            size: egui::vec2(NODE_WIDTH, NODE_HEIGHT),
+            label: "program".to_string(),
+            color: PROGRAM_COLOR,
+            children: program.statements.iter().map(TreeNode::from_ast).collect(),
+        }
+    }
+
     fn from_ast(ast: &ASTNode) -> Self {
         match ast {
-            ASTNode::Number(n) => TreeNode {
+            ASTNode::Number(n, _) => TreeNode {
                 pos: egui::Pos2::ZERO,
                 size: egui::vec2(NODE_WIDTH, NODE_HEIGHT),
                 label: format!("{}", n),
                 color: NUMBER_COLOR,
                 children: vec![],
             },
-            ASTNode::Identifier(_name, idx) => TreeNode {
+            ASTNode::Identifier(_name, idx, _) => TreeNode {
                 pos: egui::Pos2::ZERO,
                 size: egui::vec2(NODE_WIDTH, NODE_HEIGHT),
                 label: format!("id{}", idx),
                 color: VARIABLE_COLOR,
                 children: vec![],
             },
-            ASTNode::BinaryOp { op, left, right } => TreeNode {
+            ASTNode::BinaryOp {
+                op, left, right, ..
+            } => TreeNode {
                 pos: egui::Pos2::ZERO,
                 size: egui::vec2(NODE_WIDTH, NODE_HEIGHT),
                 label: op.to_string(),
                 color: OPERATOR_COLOR,
                 children: vec![TreeNode::from_ast(left), TreeNode::from_ast(right)],
             },
-            ASTNode::UnaryOp { op, operand } => TreeNode {
+            ASTNode::UnaryOp { op, operand, .. } => TreeNode {
                 pos: egui::Pos2::ZERO,
                 size: egui::vec2(NODE_WIDTH, NODE_HEIGHT),
-                label: op.clone(),
-                color: FUNCTION_COLOR,
+                label: match op.as_str() {
+                    "-" => "neg".to_string(),
+                    _ => op.clone(),
+                },
+                color: match op.as_str() {
+                    "-" | "+" => OPERATOR_COLOR,
+                    _ => FUNCTION_COLOR,
+                },
                 children: vec![TreeNode::from_ast(operand)],
             },
+            ASTNode::Call { name, args, .. } => TreeNode {
+                pos: egui::Pos2::ZERO,
+                size: egui::vec2(NODE_WIDTH, NODE_HEIGHT),
+                label: format!("{}()", name),
+                color: FUNCTION_COLOR,
+                children: args.iter().map(TreeNode::from_ast).collect(),
+            },
+            ASTNode::FunctionDef {
+                name, params, body, ..
+            } => TreeNode {
+                pos: egui::Pos2::ZERO,
+                size: egui::vec2(NODE_WIDTH, NODE_HEIGHT),
+                label: format!("fn {}", name),
+                color: FUNCTION_COLOR,
+                children: params
+                    .iter()
+                    .map(|(_, idx)| TreeNode {
+                        pos: egui::Pos2::ZERO,
+                        size: egui::vec2(NODE_WIDTH, NODE_HEIGHT),
+                        label: format!("param id{}", idx),
+                        color: VARIABLE_COLOR,
+                        children: vec![],
+                    })
+                    .chain(std::iter::once(TreeNode::from_ast(body)))
+                    .collect(),
+            },
+            ASTNode::Error(_) => TreeNode {
+                pos: egui::Pos2::ZERO,
+                size: egui::vec2(NODE_WIDTH, NODE_HEIGHT),
+                label: "error".to_string(),
+                color: ERROR_COLOR,
+                children: vec![],
+            },
         }
     }
 
@@ -100,8 +163,8 @@ impl TreeNode {
     }
 }
 
-pub fn render_tree(ui: &mut egui::Ui, ast: &ASTNode, max_height: f32) {
-    let mut tree = TreeNode::from_ast(ast);
+pub fn render_tree(ui: &mut egui::Ui, program: &Program, max_height: f32) {
+    let mut tree = TreeNode::from_program(program);
 
     // Calculate the tree width
     let tree_width = tree.calculate_width();
@@ -177,26 +240,339 @@ fn draw_tree_with_offset(node: &TreeNode, painter: &egui::Painter, offset_x: f32
         node.size,
     );
 
-    painter.rect_filled(node_rect, 5.0, node.color);
+    draw_node_box(painter, node_rect, node.color, &node.label);
 
-    // Draw outline with 4 lines
-    let stroke = egui::Stroke::new(2.0, egui::Color32::WHITE);
-    painter.line_segment([node_rect.left_top(), node_rect.right_top()], stroke);
-    painter.line_segment([node_rect.right_top(), node_rect.right_bottom()], stroke);
-    painter.line_segment([node_rect.right_bottom(), node_rect.left_bottom()], stroke);
-    painter.line_segment([node_rect.left_bottom(), node_rect.left_top()], stroke);
+    // Draw children recursively
+    for child in &node.children {
+        draw_tree_with_offset(child, painter, offset_x, offset_y);
+    }
+}
+
+fn draw_node_box(
+    painter: &egui::Painter,
+    node_rect: egui::Rect,
+    color: egui::Color32,
+    label: &str,
+) {
+    draw_box(painter, node_rect, color);
 
     // Draw label
     painter.text(
         node_rect.center(),
         egui::Align2::CENTER_CENTER,
-        &node.label,
+        label,
         egui::FontId::proportional(16.0),
         egui::Color32::WHITE,
     );
+}
 
-    // Draw children recursively
-    for child in &node.children {
-        draw_tree_with_offset(child, painter, offset_x, offset_y);
+fn draw_box(painter: &egui::Painter, rect: egui::Rect, color: egui::Color32) {
+    painter.rect_filled(rect, 5.0, color);
+
+    // Draw outline with 4 lines
+    let stroke = egui::Stroke::new(2.0, egui::Color32::WHITE);
+    painter.line_segment([rect.left_top(), rect.right_top()], stroke);
+    painter.line_segment([rect.right_top(), rect.right_bottom()], stroke);
+    painter.line_segment([rect.right_bottom(), rect.left_bottom()], stroke);
+    painter.line_segment([rect.left_bottom(), rect.left_top()], stroke);
+}
+
+/// Draws a DAG in layers: leaves on the bottom row and every operation one
+/// row above its highest operand. A shared node has several incoming edges.
+/// The names holding each value are written under its box.
+pub fn render_dag(ui: &mut egui::Ui, dag: &Dag, max_height: f32) {
+    let mut levels = vec![0; dag.nodes.len()];
+    for (i, node) in dag.nodes.iter().enumerate() {
+        levels[i] = node
+            .children
+            .iter()
+            .map(|child| levels[*child] + 1)
+            .max()
+            .unwrap_or(0);
+    }
+    let height = levels.iter().copied().max().unwrap_or(0);
+
+    let mut rows: Vec<Vec<usize>> = vec![vec![]; height + 1];
+    for (i, level) in levels.iter().enumerate() {
+        rows[height - level].push(i);
     }
+
+    let mut positions = vec![egui::Pos2::ZERO; dag.nodes.len()];
+    let widest = rows.iter().map(Vec::len).max().unwrap_or(0) as f32;
+    let total_width = widest * (NODE_WIDTH + SIBLING_SPACING);
+    for (row, nodes) in rows.iter().enumerate() {
+        let row_width = nodes.len() as f32 * (NODE_WIDTH + SIBLING_SPACING);
+        let start_x = (total_width - row_width + NODE_WIDTH + SIBLING_SPACING) / 2.0;
+        for (i, node) in nodes.iter().enumerate() {
+            positions[*node] = egui::pos2(
+                start_x + i as f32 * (NODE_WIDTH + SIBLING_SPACING),
+                NODE_HEIGHT / 2.0 + row as f32 * LEVEL_SPACING,
+            );
+        }
+    }
+
+    let padding = 40.0;
+    let desired_size = egui::vec2(
+        total_width + padding * 2.0,
+        (height + 1) as f32 * LEVEL_SPACING + padding * 2.0,
+    );
+
+    egui::Frame::default()
+        .fill(egui::Color32::from_rgb(30, 30, 35))
+        .show(ui, |ui| {
+            egui::ScrollArea::both()
+                .id_salt("dag_scroll")
+                .max_height(max_height)
+                .auto_shrink([false, true])
+                .show(ui, |ui| {
+                    let (rect, response) =
+                        ui.allocate_exact_size(desired_size, egui::Sense::hover());
+                    if !ui.is_rect_visible(rect) {
+                        return response;
+                    }
+
+                    let painter = ui.painter();
+                    let offset = rect.min.to_vec2() + egui::vec2(padding, padding);
+                    let size = egui::vec2(NODE_WIDTH, NODE_HEIGHT);
+
+                    for (i, node) in dag.nodes.iter().enumerate() {
+                        let bottom = positions[i] + offset + egui::vec2(0.0, NODE_HEIGHT / 2.0);
+                        for child in &node.children {
+                            let child_top =
+                                positions[*child] + offset - egui::vec2(0.0, NODE_HEIGHT / 2.0);
+                            painter.line_segment(
+                                [bottom, child_top],
+                                egui::Stroke::new(3.0, LINE_COLOR),
+                            );
+                        }
+                    }
+
+                    for (i, node) in dag.nodes.iter().enumerate() {
+                        let color = if !node.is_leaf {
+                            OPERATOR_COLOR
+                        } else if node.label.starts_with("id") {
+                            VARIABLE_COLOR
+                        } else {
+                            NUMBER_COLOR
+                        };
+                        let node_rect = egui::Rect::from_center_size(positions[i] + offset, size);
+                        draw_node_box(painter, node_rect, color, &node.label);
+
+                        if !node.names.is_empty() {
+                            painter.text(
+                                node_rect.center_bottom() + egui::vec2(0.0, 4.0),
+                                egui::Align2::CENTER_TOP,
+                                node.names.join(", "),
+                                egui::FontId::monospace(12.0),
+                                egui::Color32::LIGHT_GRAY,
+                            );
+                        }
+                    }
+
+                    response
+                });
+        });
+}
+
+/// Draws the control-flow graph top-down, one row per block depth, with
+/// every block listing its instructions. Edges are arrows from the bottom
+/// of a block to the top of its successor.
+pub fn render_cfg(ui: &mut egui::Ui, cfg: &ControlFlowGraph, max_height: f32) {
+    // Depth of a block: one more than its deepest forward predecessor
+    let order = cfg.reverse_postorder();
+    let mut rank = vec![usize::MAX; cfg.blocks.len()];
+    for (i, block) in order.iter().enumerate() {
+        rank[*block] = i;
+    }
+    let mut depths = vec![0; cfg.blocks.len()];
+    for block in &order {
+        depths[*block] = cfg.predecessors[*block]
+            .iter()
+            .filter(|pred| rank[**pred] < rank[*block])
+            .map(|pred| depths[*pred] + 1)
+            .max()
+            .unwrap_or(0);
+    }
+
+    let sizes: Vec<egui::Vec2> = cfg
+        .blocks
+        .iter()
+        .map(|block| {
+            let widest = block
+                .code
+                .iter()
+                .map(|instr| instr.to_string().chars().count())
+                .max()
+                .unwrap_or(0)
+                .max(4);
+            egui::vec2(
+                widest as f32 * CFG_CHAR_WIDTH + CFG_BLOCK_PADDING * 2.0,
+                (block.code.len() + 1) as f32 * CFG_LINE_HEIGHT + CFG_BLOCK_PADDING * 2.0,
+            )
+        })
+        .collect();
+
+    let rows = depths.iter().copied().max().map_or(0, |d| d + 1);
+    let mut row_heights = vec![0.0f32; rows];
+    let mut row_widths = vec![0.0f32; rows];
+    for (block, depth) in depths.iter().enumerate() {
+        row_heights[*depth] = row_heights[*depth].max(sizes[block].y);
+        row_widths[*depth] += sizes[block].x + SIBLING_SPACING;
+    }
+    let total_width = row_widths.iter().copied().fold(0.0, f32::max);
+
+    let row_y: Vec<f32> = row_heights
+        .iter()
+        .scan(0.0, |y, height| {
+            let top = *y;
+            *y += height + LEVEL_SPACING;
+            Some(top)
+        })
+        .collect();
+    let mut row_x: Vec<f32> = row_widths
+        .iter()
+        .map(|width| (total_width - width) / 2.0)
+        .collect();
+    let mut rects = vec![egui::Rect::NOTHING; cfg.blocks.len()];
+    for (block, depth) in depths.iter().enumerate() {
+        rects[block] =
+            egui::Rect::from_min_size(egui::pos2(row_x[*depth], row_y[*depth]), sizes[block]);
+        row_x[*depth] += sizes[block].x + SIBLING_SPACING;
+    }
+
+    let padding = 40.0;
+    let total_height =
+        row_heights.iter().sum::<f32>() + rows.saturating_sub(1) as f32 * LEVEL_SPACING;
+    let desired_size = egui::vec2(total_width + padding * 2.0, total_height + padding * 2.0);
+
+    egui::Frame::default()
+        .fill(egui::Color32::from_rgb(30, 30, 35))
+        .show(ui, |ui| {
+            egui::ScrollArea::both()
+                .id_salt("cfg_scroll")
+                .max_height(max_height)
+                .auto_shrink([false, true])
+                .show(ui, |ui| {
+                    let (rect, response) =
+                        ui.allocate_exact_size(desired_size, egui::Sense::hover());
+                    if !ui.is_rect_visible(rect) {
+                        return response;
+                    }
+
+                    let painter = ui.painter();
+                    let offset = rect.min.to_vec2() + egui::vec2(padding, padding);
+
+                    for (block, succs) in cfg.successors.iter().enumerate() {
+                        for succ in succs {
+                            let from = rects[block].center_bottom() + offset;
+                            let to = rects[*succ].center_top() + offset;
+                            painter.arrow(from, to - from, egui::Stroke::new(2.0, LINE_COLOR));
+                        }
+                    }
+
+                    for (i, block) in cfg.blocks.iter().enumerate() {
+                        let block_rect = rects[i].translate(offset);
+                        draw_box(painter, block_rect, BLOCK_COLOR);
+
+                        let mut text = format!("B{}", i);
+                        for instr in &block.code {
+                            text.push('\n');
+                            text.push_str(&instr.to_string());
+                        }
+                        painter.text(
+                            block_rect.left_top()
+                                + egui::vec2(CFG_BLOCK_PADDING, CFG_BLOCK_PADDING),
+                            egui::Align2::LEFT_TOP,
+                            text,
+                            egui::FontId::monospace(13.0),
+                            egui::Color32::WHITE,
+                        );
+                    }
+
+                    response
+                });
+        });
+}
+
+/// Draws the interference graph with its temporaries on a circle, each
+/// colored by its register and labelled with it, or gray with its stack
+/// slot when it was spilled.
+pub fn render_interference_graph(ui: &mut egui::Ui, allocation: &Allocation, max_height: f32) {
+    let temps: Vec<usize> = allocation.graph.adjacency.keys().copied().collect();
+    let radius = (temps.len() as f32 * NODE_HEIGHT * 1.6 / std::f32::consts::TAU).max(NODE_HEIGHT);
+    let padding = 40.0;
+    let size = 2.0 * (radius + padding);
+    let center = egui::vec2(size / 2.0, size / 2.0);
+    let positions: HashMap<usize, egui::Vec2> = temps
+        .iter()
+        .enumerate()
+        .map(|(i, temp)| {
+            let angle = i as f32 / temps.len() as f32 * std::f32::consts::TAU;
+            (
+                *temp,
+                center + radius * egui::vec2(angle.sin(), -angle.cos()),
+            )
+        })
+        .collect();
+
+    egui::Frame::default()
+        .fill(egui::Color32::from_rgb(30, 30, 35))
+        .show(ui, |ui| {
+            egui::ScrollArea::both()
+                .id_salt("interference_scroll")
+                .max_height(max_height)
+                .auto_shrink([false, true])
+                .show(ui, |ui| {
+                    let (rect, response) =
+                        ui.allocate_exact_size(egui::vec2(size, size), egui::Sense::hover());
+                    if !ui.is_rect_visible(rect) {
+                        return response;
+                    }
+
+                    let painter = ui.painter();
+                    let offset = rect.min.to_vec2();
+                    for (a, b) in allocation.graph.edges() {
+                        painter.line_segment(
+                            [
+                                (positions[&a] + offset).to_pos2(),
+                                (positions[&b] + offset).to_pos2(),
+                            ],
+                            egui::Stroke::new(2.0, LINE_COLOR),
+                        );
+                    }
+
+                    for temp in &temps {
+                        let (color, location) = match allocation.assignment.get(temp) {
+                            Some(register) => (
+                                register_color(*register, allocation.registers),
+                                format!("r{}", register),
+                            ),
+                            None => (
+                                ERROR_COLOR,
+                                allocation
+                                    .spilled
+                                    .get(temp)
+                                    .map_or_else(String::new, |slot| format!("s{}", slot)),
+                            ),
+                        };
+                        let node_center = (positions[temp] + offset).to_pos2();
+                        painter.circle_filled(node_center, NODE_HEIGHT / 2.0, color);
+                        painter.text(
+                            node_center,
+                            egui::Align2::CENTER_CENTER,
+                            format!("t{}\n{}", temp, location),
+                            egui::FontId::monospace(12.0),
+                            egui::Color32::WHITE,
+                        );
+                    }
+
+                    response
+                });
+        });
+}
+
+/// One hue per register, spread around the color wheel.
+fn register_color(register: usize, registers: usize) -> egui::Color32 {
+    let hue = register as f32 / registers.max(1) as f32;
+    egui::ecolor::Hsva::new(hue, 0.6, 0.7, 1.0).into()
 }
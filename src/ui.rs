@@ -1,7 +1,21 @@
-use crate::ast::ASTNode;
+use crate::ast::Program;
+use crate::builtins;
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::environment::Environment;
+use crate::error::EvalError;
+use crate::liveness;
+use crate::options::{CompileOptions, FloatMode};
 use crate::parser::ParseResult;
+use crate::passes::{Pass, PassRun};
+use crate::ssa::SsaProgram;
+use crate::strength::Rule;
+use crate::tables;
 use crate::tree_view;
+use crate::vm::Vm;
+use crate::wasm;
+use crate::x86;
 use eframe::egui;
+use std::collections::HashMap;
 
 // UI Constants
 pub const TOKENS_SCROLL_HEIGHT: f32 = 150.0;
@@ -12,22 +26,74 @@ pub const TOKEN_TEXT_COLOR: egui::Color32 = egui::Color32::from_rgb(0, 60, 150);
 pub const IDENTIFIER_COLOR: egui::Color32 = egui::Color32::from_rgb(0, 100, 200);
 pub const SUCCESS_COLOR: egui::Color32 = egui::Color32::from_rgb(0, 150, 0);
 pub const WARNING_COLOR: egui::Color32 = egui::Color32::from_rgb(200, 120, 0);
-pub const PHASE_HEADER_COLOR: egui::Color32 = egui::Color32::from_rgb(70, 130, 180);
+pub const ERROR_COLOR: egui::Color32 = egui::Color32::from_rgb(220, 50, 50);
+pub const DIAGNOSTIC_BG_COLOR: egui::Color32 = egui::Color32::from_rgb(40, 40, 45);
+pub const TAC_BG_COLOR: egui::Color32 = egui::Color32::from_rgb(40, 40, 45);
+pub const OPTIMIZED_TAC_BG_COLOR: egui::Color32 = egui::Color32::from_rgb(30, 50, 35);
+pub const OPTIMIZED_TAC_TEXT_COLOR: egui::Color32 = egui::Color32::from_rgb(150, 255, 150);
 
+const INPUT_ROWS: usize = 3;
+
+/// How phase 4 presents the intermediate code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TacView {
+    Listing,
+    Quadruples,
+    Triples,
+    IndirectTriples,
+    Liveness,
+}
+
+impl TacView {
+    const ALL: [TacView; 5] = [
+        TacView::Listing,
+        TacView::Quadruples,
+        TacView::Triples,
+        TacView::IndirectTriples,
+        TacView::Liveness,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            TacView::Listing => "Listing",
+            TacView::Quadruples => "Quadruples",
+            TacView::Triples => "Triples",
+            TacView::IndirectTriples => "Indirect Triples",
+            TacView::Liveness => "Liveness",
+        }
+    }
+}
 const DEFAULT_EXPRESSION: &str = "A = B + C";
 
 pub struct ExpressionParserApp {
     input: String,
+    options: CompileOptions,
     parse_result: Option<ParseResult>,
-    error: Option<String>,
+    /// Values typed for each variable, kept across recompiles by name.
+    variable_inputs: HashMap<String, String>,
+    tac_view: TacView,
+    /// Pass run shown in the phase 5 trace.
+    pass_step: usize,
+    /// Comma-separated variables kept live at exit when restricted.
+    live_out_input: String,
+    /// Bytecode machine being stepped, started on the first step.
+    vm: Option<Vm>,
+    /// Why the machine stopped early.
+    vm_error: Option<EvalError>,
 }
 
 impl Default for ExpressionParserApp {
     fn default() -> Self {
         Self {
             input: DEFAULT_EXPRESSION.to_string(),
+            options: CompileOptions::default(),
             parse_result: None,
-            error: None,
+            variable_inputs: HashMap::new(),
+            tac_view: TacView::Listing,
+            pass_step: 0,
+            live_out_input: String::new(),
+            vm: None,
+            vm_error: None,
         }
     }
 }
@@ -38,22 +104,17 @@ impl ExpressionParserApp {
     }
 
     fn process_expression(&mut self) {
-        self.error = None;
-        self.parse_result = None;
-
-        match ParseResult::from_input(&self.input) {
-            Ok(result) => {
-                self.parse_result = Some(result);
-            }
-            Err(err) => {
-                self.error = Some(err.to_string());
-            }
-        }
+        self.parse_result = Some(ParseResult::from_input(&self.input, &self.options));
+        // Offsets into the old bytecode mean nothing in the new one
+        self.vm = None;
+        self.vm_error = None;
     }
 
     fn render_header(&self, ui: &mut egui::Ui) {
         ui.heading("Compiler Design - Complete Pipeline");
-        ui.label("Five Phases: Lexical → Syntax → Semantic → Intermediate Code → Optimization");
+        ui.label(
+            "Six Phases: Lexical → Syntax → Semantic → Intermediate Code → Optimization → Code Generation",
+        );
         ui.add_space(10.0);
         ui.separator();
         ui.add_space(10.0);
@@ -61,10 +122,20 @@ impl ExpressionParserApp {
 
     fn render_input_section(&mut self, ui: &mut egui::Ui) {
         ui.horizontal(|ui| {
-            ui.label("Expression:");
-            let response = ui.text_edit_singleline(&mut self.input);
+            ui.label("Program:");
+            // Enter compiles, Shift+Enter starts a new statement line
+            let response = ui.add(
+                egui::TextEdit::multiline(&mut self.input)
+                    .desired_rows(INPUT_ROWS)
+                    .return_key(egui::KeyboardShortcut::new(
+                        egui::Modifiers::SHIFT,
+                        egui::Key::Enter,
+                    )),
+            );
 
-            if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+            if (response.has_focus() || response.lost_focus())
+                && ui.input(|i| i.key_pressed(egui::Key::Enter) && !i.modifiers.shift)
+            {
                 self.process_expression();
             }
 
@@ -84,9 +155,15 @@ impl ExpressionParserApp {
                 "A = B + C",
                 "A = sqrt(B-(C-D)^E) - 10",
                 "a + b * c",
+                "x = a*b + b*a - (a*b)*c",
+                "y = if(x, a + 1, a - 1) * 2",
                 "x^2 + 2*x + 1",
                 "5 + 3 * 0",
                 "(10 - 4) / 2",
+                "-2^2 + 2 * -3",
+                "h = hypot(3, 4) + max(a, sin(b))",
+                "f(x, y) = x^2 + y; z = f(3, a)",
+                "a = 2; b = a * 3; c = a + b",
             ];
 
             for example in examples {
@@ -99,14 +176,112 @@ impl ExpressionParserApp {
         ui.add_space(10.0);
     }
 
-    fn render_error(&self, ui: &mut egui::Ui) {
-        if let Some(error) = &self.error {
-            ui.colored_label(egui::Color32::RED, format!("❌ Error: {}", error));
-            ui.add_space(10.0);
+    fn render_options(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal_wrapped(|ui| {
+            ui.label("Options:");
+
+            let mut restricted = self.options.live_out.is_some();
+            let mut changed = ui
+                .checkbox(&mut restricted, "Only these variables live at exit:")
+                .changed();
+            changed |= ui
+                .add_enabled(
+                    restricted,
+                    egui::TextEdit::singleline(&mut self.live_out_input)
+                        .hint_text("a, b")
+                        .desired_width(120.0),
+                )
+                .changed();
+            self.options.live_out = restricted.then(|| {
+                self.live_out_input
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|name| !name.is_empty())
+                    .map(String::from)
+                    .collect()
+            });
+
+            ui.separator();
+            ui.label("Floating point:");
+            for mode in [FloatMode::Strict, FloatMode::FastMath] {
+                changed |= ui
+                    .radio_value(&mut self.options.float_mode, mode, mode.label())
+                    .changed();
+            }
+
+            // Keep the displayed phases in sync with the options
+            if changed && self.parse_result.is_some() {
+                self.process_expression();
+            }
+        });
+
+        ui.collapsing("Strength reduction rules", |ui| {
+            let mut changed = false;
+            let fast = self.options.float_mode == FloatMode::FastMath;
+            for rule in Rule::ALL {
+                let mut enabled = !self.options.disabled_rules.contains(&rule);
+                let checkbox = ui
+                    .add_enabled(
+                        fast || rule.is_exact(),
+                        egui::Checkbox::new(&mut enabled, rule.description()),
+                    )
+                    .on_disabled_hover_text("Not exact under IEEE-754; needs fast-math");
+                if checkbox.changed() {
+                    changed = true;
+                    if enabled {
+                        self.options.disabled_rules.remove(&rule);
+                    } else {
+                        self.options.disabled_rules.insert(rule);
+                    }
+                }
+            }
+            if changed && self.parse_result.is_some() {
+                self.process_expression();
+            }
+        });
+
+        ui.add_space(10.0);
+    }
+
+    fn render_errors(&self, ui: &mut egui::Ui, result: &ParseResult) {
+        if result.errors.is_empty() {
+            return;
+        }
+
+        ui.colored_label(
+            ERROR_COLOR,
+            format!(
+                "❌ Compilation failed with {} error(s); showing the partial tree",
+                result.errors.len()
+            ),
+        );
+        ui.add_space(3.0);
+        for error in &result.errors {
+            self.render_diagnostic(ui, &Diagnostic::from(error), &result.source);
+            ui.add_space(3.0);
         }
+        ui.add_space(10.0);
+    }
+
+    fn render_diagnostic(&self, ui: &mut egui::Ui, diagnostic: &Diagnostic, source: &str) {
+        let color = match diagnostic.severity {
+            Severity::Error => ERROR_COLOR,
+            Severity::Warning => WARNING_COLOR,
+        };
+
+        egui::Frame::NONE
+            .fill(DIAGNOSTIC_BG_COLOR)
+            .inner_margin(10.0)
+            .show(ui, |ui| {
+                ui.label(
+                    egui::RichText::new(diagnostic.render(source))
+                        .color(color)
+                        .monospace(),
+                );
+            });
     }
 
-    fn render_phase_header(&self, ui: &mut egui::Ui, phase_num: usize, title: &str) {
+    fn render_phase_header(&self, ui: &mut egui::Ui, title: &str) {
         ui.horizontal(|ui| {
             ui.label(egui::RichText::new(title).size(18.0).strong());
         });
@@ -144,7 +319,7 @@ impl ExpressionParserApp {
 
     fn render_phase1_lexical(&self, ui: &mut egui::Ui, result: &ParseResult) {
         ui.group(|ui| {
-            self.render_phase_header(ui, 1, "Lexical Analysis");
+            self.render_phase_header(ui, "Lexical Analysis");
             ui.label("Breaking down the input into tokens (lexemes)");
             ui.add_space(8.0);
 
@@ -164,7 +339,14 @@ impl ExpressionParserApp {
                                     .background_color(TOKEN_BG_COLOR)
                                     .color(TOKEN_TEXT_COLOR)
                                     .monospace(),
-                            );
+                            )
+                            .on_hover_text(format!(
+                                "line {}, column {} (chars {}..{})",
+                                token.span.line,
+                                token.span.column,
+                                token.span.start,
+                                token.span.end
+                            ));
                         }
                     });
                 });
@@ -173,9 +355,9 @@ impl ExpressionParserApp {
         ui.add_space(15.0);
     }
 
-    fn render_phase2_syntax(&self, ui: &mut egui::Ui, ast: &ASTNode) {
+    fn render_phase2_syntax(&self, ui: &mut egui::Ui, ast: &Program) {
         ui.group(|ui| {
-            self.render_phase_header(ui, 2, "Syntax Analysis (Parsing)");
+            self.render_phase_header(ui, "Syntax Analysis (Parsing)");
             ui.label("Building Abstract Syntax Tree (AST) from tokens");
             ui.add_space(8.0);
 
@@ -185,30 +367,29 @@ impl ExpressionParserApp {
         ui.add_space(15.0);
     }
 
-    fn render_phase3_semantic(&self, ui: &mut egui::Ui, warnings: &[String]) {
+    fn render_phase3_semantic(&self, ui: &mut egui::Ui, result: &ParseResult) {
+        let diagnostics = &result.semantic_diagnostics;
         ui.group(|ui| {
-            self.render_phase_header(ui, 3, "Semantic Analysis");
+            self.render_phase_header(ui, "Semantic Analysis");
             ui.label("Checking for semantic errors and type consistency");
             ui.add_space(8.0);
 
-            if warnings.is_empty() {
+            if diagnostics.is_empty() {
                 ui.label(
-                    egui::RichText::new("✓ No semantic warnings detected")
+                    egui::RichText::new("✓ No semantic issues detected")
                         .color(SUCCESS_COLOR)
                         .strong(),
                 );
             } else {
                 ui.label(
-                    egui::RichText::new("Warnings:")
+                    egui::RichText::new("Diagnostics:")
                         .strong()
                         .color(WARNING_COLOR),
                 );
                 ui.add_space(3.0);
-                for warning in warnings {
-                    ui.horizontal(|ui| {
-                        ui.label("⚠");
-                        ui.label(egui::RichText::new(warning).color(WARNING_COLOR));
-                    });
+                for diagnostic in diagnostics {
+                    self.render_diagnostic(ui, diagnostic, &result.source);
+                    ui.add_space(3.0);
                 }
             }
         });
@@ -216,57 +397,363 @@ impl ExpressionParserApp {
         ui.add_space(15.0);
     }
 
-    fn render_phase4_intermediate(&self, ui: &mut egui::Ui, code: &[String]) {
+    fn render_code_listing(
+        &self,
+        ui: &mut egui::Ui,
+        id_salt: &str,
+        code: &[impl std::fmt::Display],
+        fill: egui::Color32,
+        text_color: egui::Color32,
+    ) {
+        egui::ScrollArea::vertical()
+            .id_salt(id_salt)
+            .max_height(CODE_SCROLL_HEIGHT)
+            .show(ui, |ui| {
+                egui::Frame::NONE
+                    .fill(fill)
+                    .inner_margin(10.0)
+                    .show(ui, |ui| {
+                        for (i, line) in code.iter().enumerate() {
+                            ui.horizontal(|ui| {
+                                ui.label(
+                                    egui::RichText::new(format!("{:2}:", i + 1))
+                                        .color(egui::Color32::GRAY)
+                                        .monospace(),
+                                );
+                                ui.label(
+                                    egui::RichText::new(line.to_string())
+                                        .color(text_color)
+                                        .monospace(),
+                                );
+                            });
+                        }
+                    });
+            });
+    }
+
+    /// Striped grid with a bold header row.
+    fn render_table(
+        &self,
+        ui: &mut egui::Ui,
+        id_salt: &str,
+        headers: &[&str],
+        rows: Vec<Vec<String>>,
+    ) {
+        egui::Grid::new(id_salt)
+            .striped(true)
+            .num_columns(headers.len())
+            .spacing([20.0, 4.0])
+            .show(ui, |ui| {
+                for header in headers {
+                    ui.label(egui::RichText::new(*header).strong());
+                }
+                ui.end_row();
+
+                for row in rows {
+                    for cell in row {
+                        ui.label(egui::RichText::new(cell).monospace());
+                    }
+                    ui.end_row();
+                }
+            });
+    }
+
+    fn render_phase4_intermediate(&mut self, ui: &mut egui::Ui, result: &ParseResult) {
         ui.group(|ui| {
-            self.render_phase_header(ui, 4, "Intermediate Code Generation");
+            self.render_phase_header(ui, "Intermediate Code Generation");
             ui.label("Generating Three-Address Code (TAC)");
             ui.add_space(8.0);
 
-            ui.label(egui::RichText::new("Three-Address Code:").strong());
+            ui.horizontal(|ui| {
+                ui.label(egui::RichText::new("Three-Address Code:").strong());
+                for view in TacView::ALL {
+                    ui.selectable_value(&mut self.tac_view, view, view.label());
+                }
+            });
             ui.add_space(3.0);
 
-            egui::ScrollArea::vertical()
-                .id_salt("tac_scroll")
-                .max_height(CODE_SCROLL_HEIGHT)
-                .show(ui, |ui| {
-                    egui::Frame::NONE
-                        .fill(egui::Color32::from_rgb(40, 40, 45))
-                        .inner_margin(10.0)
-                        .show(ui, |ui| {
-                            for (i, line) in code.iter().enumerate() {
-                                ui.horizontal(|ui| {
-                                    ui.label(
-                                        egui::RichText::new(format!("{:2}:", i + 1))
-                                            .color(egui::Color32::GRAY)
-                                            .monospace(),
-                                    );
-                                    ui.label(
-                                        egui::RichText::new(line)
-                                            .color(egui::Color32::WHITE)
-                                            .monospace(),
-                                    );
-                                });
-                            }
-                        });
+            let code = &result.three_address_code;
+            match self.tac_view {
+                TacView::Listing => self.render_code_listing(
+                    ui,
+                    "tac_scroll",
+                    code,
+                    TAC_BG_COLOR,
+                    egui::Color32::WHITE,
+                ),
+                TacView::Quadruples => {
+                    let rows = tables::quadruples(code)
+                        .into_iter()
+                        .enumerate()
+                        .map(|(i, q)| vec![i.to_string(), q.op, q.arg1, q.arg2, q.result])
+                        .collect();
+                    self.render_table(
+                        ui,
+                        "quadruples",
+                        &["#", "op", "arg1", "arg2", "result"],
+                        rows,
+                    );
+                }
+                TacView::Triples => {
+                    let rows = tables::triples(code)
+                        .into_iter()
+                        .enumerate()
+                        .map(|(i, t)| vec![format!("({})", i), t.op, t.arg1, t.arg2])
+                        .collect();
+                    self.render_table(ui, "triples", &["#", "op", "arg1", "arg2"], rows);
+                }
+                TacView::Liveness => {
+                    let rows = code
+                        .iter()
+                        .enumerate()
+                        .map(|(i, instr)| {
+                            vec![
+                                (i + 1).to_string(),
+                                instr.to_string(),
+                                liveness::format_set(&result.liveness.live_in[i]),
+                                liveness::format_set(&result.liveness.live_out[i]),
+                            ]
+                        })
+                        .collect();
+                    self.render_table(
+                        ui,
+                        "liveness",
+                        &["#", "instruction", "live in", "live out"],
+                        rows,
+                    );
+                }
+                TacView::IndirectTriples => {
+                    let indirect = tables::indirect_triples(code);
+                    ui.horizontal_top(|ui| {
+                        let statements = indirect
+                            .statements
+                            .iter()
+                            .enumerate()
+                            .map(|(i, pos)| vec![i.to_string(), format!("({})", pos)])
+                            .collect();
+                        self.render_table(
+                            ui,
+                            "indirect_statements",
+                            &["statement", "triple"],
+                            statements,
+                        );
+                        ui.add_space(20.0);
+                        let triples = indirect
+                            .triples
+                            .into_iter()
+                            .enumerate()
+                            .map(|(i, t)| vec![format!("({})", i), t.op, t.arg1, t.arg2])
+                            .collect();
+                        self.render_table(
+                            ui,
+                            "indirect_triples",
+                            &["#", "op", "arg1", "arg2"],
+                            triples,
+                        );
+                    });
+                }
+            }
+
+            if !result.cfg.blocks.is_empty() {
+                ui.add_space(8.0);
+                ui.label(egui::RichText::new("Control-Flow Graph:").strong());
+                ui.add_space(3.0);
+                tree_view::render_cfg(ui, &result.cfg, AST_SCROLL_HEIGHT);
+
+                let idoms = result.cfg.immediate_dominators();
+                ui.horizontal_wrapped(|ui| {
+                    ui.label("Immediate dominators:");
+                    for (block, idom) in idoms.iter().enumerate() {
+                        if let Some(idom) = idom {
+                            ui.label(
+                                egui::RichText::new(format!("B{} ← B{}", block, idom)).monospace(),
+                            );
+                        }
+                    }
                 });
+            }
+
+            for (signature, code) in &result.function_code {
+                ui.add_space(8.0);
+                ui.label(egui::RichText::new(format!("Function {}:", signature)).strong());
+                ui.add_space(3.0);
+                self.render_code_listing(
+                    ui,
+                    &format!("function_tac_scroll_{}", signature),
+                    code,
+                    TAC_BG_COLOR,
+                    egui::Color32::WHITE,
+                );
+            }
         });
 
         ui.add_space(15.0);
     }
 
-    fn render_phase5_optimization(&self, ui: &mut egui::Ui, result: &ParseResult) {
+    fn render_ssa(&self, ui: &mut egui::Ui, ssa_form: &SsaProgram, ssa_optimized: &SsaProgram) {
         ui.group(|ui| {
-            self.render_phase_header(ui, 5, "Code Optimization");
-            ui.label("Constant folding, algebraic simplification, and dead code elimination");
+            self.render_phase_header(ui, "Static Single Assignment");
+            ui.label("Every name is assigned once; phi functions merge values where branches join");
+            ui.add_space(8.0);
+
+            ui.columns(2, |columns| {
+                columns[0].label(egui::RichText::new("SSA Form:").strong());
+                self.render_code_listing(
+                    &mut columns[0],
+                    "ssa_scroll",
+                    &ssa_form.lines(),
+                    TAC_BG_COLOR,
+                    egui::Color32::WHITE,
+                );
+                columns[1].label(
+                    egui::RichText::new("After Constant Propagation and Dead Code Elimination:")
+                        .strong(),
+                );
+                self.render_code_listing(
+                    &mut columns[1],
+                    "ssa_optimized_scroll",
+                    &ssa_optimized.lines(),
+                    OPTIMIZED_TAC_BG_COLOR,
+                    OPTIMIZED_TAC_TEXT_COLOR,
+                );
+            });
+        });
+
+        ui.add_space(15.0);
+    }
+
+    /// Enable flags and order of the optimization passes.
+    fn render_pass_controls(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing("Passes", |ui| {
+            let mut changed = false;
+            let mut swap = None;
+            let count = self.options.passes.len();
+            let strict = self.options.float_mode == FloatMode::Strict;
+            for (i, (pass, enabled)) in self.options.passes.iter_mut().enumerate() {
+                ui.horizontal(|ui| {
+                    if ui
+                        .add_enabled(i > 0, egui::Button::new("▲").small())
+                        .clicked()
+                    {
+                        swap = Some(i - 1);
+                    }
+                    if ui
+                        .add_enabled(i + 1 < count, egui::Button::new("▼").small())
+                        .clicked()
+                    {
+                        swap = Some(i);
+                    }
+                    let checkbox = ui.checkbox(enabled, pass.title());
+                    changed |= checkbox.changed();
+                    if *pass == Pass::SqrtLowering && strict {
+                        checkbox.on_hover_text(
+                            "Skipped under strict IEEE-754, where x ^ 0.5 differs from sqrt(x) for -0 and -inf",
+                        );
+                    }
+                });
+            }
+            if let Some(i) = swap {
+                self.options.passes.swap(i, i + 1);
+                changed = true;
+            }
+            changed |= ui
+                .checkbox(&mut self.options.fixpoint, "Repeat until nothing changes")
+                .changed();
+
+            if changed {
+                self.process_expression();
+                self.pass_step = 0;
+            }
+        });
+    }
+
+    /// One pass run at a time, with the code before and after it.
+    fn render_pass_trace(&mut self, ui: &mut egui::Ui, runs: &[PassRun]) {
+        if runs.is_empty() {
+            return;
+        }
+        let step = self.pass_step.min(runs.len() - 1);
+        let run = &runs[step];
+
+        ui.add_space(5.0);
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(step > 0, egui::Button::new("◀ Previous"))
+                .clicked()
+            {
+                self.pass_step = step - 1;
+            }
+            if ui
+                .add_enabled(step + 1 < runs.len(), egui::Button::new("Next ▶"))
+                .clicked()
+            {
+                self.pass_step = step + 1;
+            }
+            ui.label(
+                egui::RichText::new(format!(
+                    "Step {} of {}: {} (round {})",
+                    step + 1,
+                    runs.len(),
+                    run.pass.title(),
+                    run.round
+                ))
+                .strong(),
+            );
+        });
+
+        for note in &run.notes {
+            let color = if note.contains("warning:") {
+                WARNING_COLOR
+            } else {
+                egui::Color32::GRAY
+            };
+            ui.label(egui::RichText::new(note).monospace().color(color));
+        }
+        if !run.changed() {
+            ui.label(
+                egui::RichText::new("No changes")
+                    .color(egui::Color32::GRAY)
+                    .italics(),
+            );
+            return;
+        }
+        ui.columns(2, |columns| {
+            columns[0].label("Before:");
+            self.render_code_listing(
+                &mut columns[0],
+                "pass_before_scroll",
+                &run.before,
+                TAC_BG_COLOR,
+                egui::Color32::WHITE,
+            );
+            columns[1].label("After:");
+            self.render_code_listing(
+                &mut columns[1],
+                "pass_after_scroll",
+                &run.after,
+                OPTIMIZED_TAC_BG_COLOR,
+                OPTIMIZED_TAC_TEXT_COLOR,
+            );
+        });
+    }
+
+    fn render_phase5_optimization(&mut self, ui: &mut egui::Ui, result: &ParseResult) {
+        ui.group(|ui| {
+            self.render_phase_header(ui, "Code Optimization");
+            ui.label(
+                "Constant folding, algebraic simplification, common subexpression elimination, and dead assignment elimination",
+            );
+            ui.label(format!(
+                "Floating-point mode: {}",
+                result.float_mode.label()
+            ));
             ui.add_space(8.0);
 
             // Show optimization comparison
             let original_code_len = result.three_address_code.len();
             let optimized_code_len = result.optimized_three_address_code.len();
 
-            if result.ast.to_string() != result.optimized_ast.to_string()
-                || original_code_len != optimized_code_len
-            {
+            if result.three_address_code != result.optimized_three_address_code {
                 ui.label(
                     egui::RichText::new(format!(
                         "✓ Optimizations applied: {} → {} instructions",
@@ -285,59 +772,414 @@ impl ExpressionParserApp {
                 ui.add_space(5.0);
             }
 
+            self.render_pass_controls(ui);
+            self.render_pass_trace(ui, &result.passes);
+
             ui.add_space(8.0);
             ui.label(egui::RichText::new("Optimized Three-Address Code:").strong());
             ui.add_space(3.0);
 
+            self.render_code_listing(
+                ui,
+                "optimized_tac_scroll",
+                &result.optimized_three_address_code,
+                OPTIMIZED_TAC_BG_COLOR,
+                OPTIMIZED_TAC_TEXT_COLOR,
+            );
+
+            if !result.dag.nodes.is_empty() {
+                ui.add_space(8.0);
+                ui.label(egui::RichText::new("DAG (shared subexpressions):").strong());
+                ui.add_space(3.0);
+                tree_view::render_dag(ui, &result.dag, AST_SCROLL_HEIGHT);
+            }
+        });
+
+        ui.add_space(15.0);
+    }
+
+    fn render_phase6_codegen(&mut self, ui: &mut egui::Ui, result: &ParseResult) {
+        let allocation = &result.allocation;
+        ui.group(|ui| {
+            self.render_phase_header(ui, "Code Generation");
+            ui.label("Register allocation by graph coloring, then x86-64 assembly in Intel syntax with SSE2 for the doubles");
+            ui.add_space(8.0);
+
+            ui.label(egui::RichText::new("Register Allocation:").strong());
+            if ui
+                .add(
+                    egui::Slider::new(&mut self.options.registers, 2..=x86::REGISTERS)
+                        .text("registers"),
+                )
+                .changed()
+            {
+                self.process_expression();
+            }
+            ui.label("Temporaries live at the same time interfere and need different registers; calls clobber every register");
+            if !allocation.graph.adjacency.is_empty() {
+                tree_view::render_interference_graph(ui, allocation, AST_SCROLL_HEIGHT);
+            }
+            let assignment: Vec<String> = allocation
+                .assignment
+                .iter()
+                .map(|(temp, register)| format!("t{} → r{}", temp, register))
+                .collect();
+            if !assignment.is_empty() {
+                ui.label(egui::RichText::new(assignment.join(", ")).monospace());
+            }
+            if !allocation.spilled.is_empty() {
+                let spilled: Vec<String> = allocation
+                    .spilled
+                    .keys()
+                    .map(|temp| allocation.describe_spill(*temp))
+                    .collect();
+                ui.label(
+                    egui::RichText::new(format!("Spilled: {}", spilled.join(", ")))
+                        .color(WARNING_COLOR)
+                        .monospace(),
+                );
+            }
+            ui.label(
+                egui::RichText::new(
+                    "Register rN is xmm(N+2) in the assembly; xmm0 and xmm1 carry operands and call arguments",
+                )
+                .color(egui::Color32::GRAY),
+            );
+            ui.add_space(8.0);
+
+            // Every instruction, spill code included, next to the assembly it
+            // became
             egui::ScrollArea::vertical()
-                .id_salt("optimized_tac_scroll")
+                .id_salt("asm_scroll")
                 .max_height(CODE_SCROLL_HEIGHT)
                 .show(ui, |ui| {
-                    egui::Frame::NONE
-                        .fill(egui::Color32::from_rgb(30, 50, 35))
-                        .inner_margin(10.0)
+                    egui::Grid::new("asm_grid")
+                        .striped(true)
+                        .num_columns(2)
+                        .spacing([20.0, 4.0])
                         .show(ui, |ui| {
-                            for (i, line) in result.optimized_three_address_code.iter().enumerate()
-                            {
-                                ui.horizontal(|ui| {
-                                    ui.label(
-                                        egui::RichText::new(format!("{:2}:", i + 1))
-                                            .color(egui::Color32::GRAY)
-                                            .monospace(),
-                                    );
-                                    ui.label(
-                                        egui::RichText::new(line)
-                                            .color(egui::Color32::from_rgb(150, 255, 150))
-                                            .monospace(),
-                                    );
-                                });
+                            ui.label(egui::RichText::new("Three-Address Code").strong());
+                            ui.label(egui::RichText::new("Assembly").strong());
+                            ui.end_row();
+
+                            for (i, instr) in allocation.code.iter().enumerate() {
+                                let asm: Vec<&str> = result
+                                    .assembly
+                                    .lines_for(i)
+                                    .map(|line| line.text.trim())
+                                    .collect();
+                                ui.label(
+                                    egui::RichText::new(format!("{:2}: {}", i + 1, instr))
+                                        .monospace(),
+                                );
+                                ui.label(egui::RichText::new(asm.join("\n")).monospace());
+                                ui.end_row();
                             }
                         });
                 });
+
+            ui.add_space(8.0);
+            ui.collapsing("Complete listing", |ui| {
+                if ui.button("📋 Copy").clicked() {
+                    ui.ctx().copy_text(result.assembly.to_string());
+                }
+                let lines: Vec<&str> = result
+                    .assembly
+                    .lines
+                    .iter()
+                    .map(|line| line.text.as_str())
+                    .collect();
+                self.render_code_listing(
+                    ui,
+                    "asm_listing_scroll",
+                    &lines,
+                    TAC_BG_COLOR,
+                    egui::Color32::WHITE,
+                );
+            });
         });
 
         ui.add_space(15.0);
     }
 
-    fn render_final_result(&self, ui: &mut egui::Ui, ast: &ASTNode) {
+    /// Steps through the bytecode, showing the operand stack and variables.
+    fn render_vm(&mut self, ui: &mut egui::Ui, result: &ParseResult) {
+        let chunk = &result.bytecode;
         ui.group(|ui| {
-            ui.heading("Final Evaluation");
+            self.render_phase_header(ui, "Bytecode Virtual Machine");
+            ui.label(
+                "Stack-machine bytecode for the program with its functions inlined, run from the values given under Final Evaluation",
+            );
+            ui.label(format!(
+                "{} bytes, {} constants, {} variables",
+                chunk.code.len(),
+                chunk.constants.len(),
+                chunk.names.len()
+            ));
             ui.add_space(5.0);
 
-            if ast.has_variables() {
-                ui.label(
-                    egui::RichText::new("Expression contains variables - no numeric evaluation")
-                        .italics()
-                        .color(egui::Color32::GRAY),
-                );
-            } else {
-                let result = ast.evaluate();
-                ui.label(
-                    egui::RichText::new(format!("Result: {}", result))
-                        .size(24.0)
-                        .color(SUCCESS_COLOR)
-                        .strong(),
+            let start = self.input_environment(result);
+            let vm = self.vm.get_or_insert_with(|| Vm::new(start));
+            let running = !vm.finished(chunk) && self.vm_error.is_none();
+            let mut reset = false;
+            ui.horizontal(|ui| {
+                reset = ui.button("⟲ Reset").clicked();
+                if ui
+                    .add_enabled(running, egui::Button::new("Step ▶"))
+                    .clicked()
+                {
+                    self.vm_error = vm.step(chunk).err();
+                }
+                if ui
+                    .add_enabled(running, egui::Button::new("Run ⏩"))
+                    .clicked()
+                {
+                    self.vm_error = vm.run(chunk).err();
+                }
+                let status = if self.vm_error.is_some() {
+                    "stopped with an error".to_string()
+                } else if vm.finished(chunk) {
+                    match vm.stack.last() {
+                        Some(value) => format!("finished: {}", value),
+                        None => "finished".to_string(),
+                    }
+                } else {
+                    format!("next instruction at {:04}", vm.pc)
+                };
+                ui.label(egui::RichText::new(status).strong());
+            });
+
+            ui.columns(2, |columns| {
+                columns[0].label(egui::RichText::new("Disassembly:").strong());
+                egui::ScrollArea::vertical()
+                    .id_salt("bytecode_scroll")
+                    .max_height(CODE_SCROLL_HEIGHT)
+                    .show(&mut columns[0], |ui| {
+                        for (offset, instruction) in chunk.instructions() {
+                            let text = chunk.disassemble(offset, instruction);
+                            let line = if offset == vm.pc {
+                                egui::RichText::new(format!("▶ {}", text))
+                                    .color(SUCCESS_COLOR)
+                                    .strong()
+                            } else {
+                                egui::RichText::new(format!("  {}", text))
+                            };
+                            ui.label(line.monospace());
+                        }
+                    });
+
+                columns[1].label(egui::RichText::new("Operand stack (top first):").strong());
+                if vm.stack.is_empty() {
+                    columns[1].label(
+                        egui::RichText::new("empty")
+                            .color(egui::Color32::GRAY)
+                            .italics(),
+                    );
+                }
+                for value in vm.stack.iter().rev() {
+                    columns[1].label(egui::RichText::new(value.to_string()).monospace());
+                }
+                columns[1].add_space(5.0);
+                columns[1].label(egui::RichText::new("Variables:").strong());
+                for (name, _) in &result.identifier_table {
+                    if let Some(value) = vm.env.get(name) {
+                        columns[1]
+                            .label(egui::RichText::new(format!("{} = {}", name, value)).monospace());
+                    }
+                }
+            });
+
+            if let Some(err) = &self.vm_error {
+                self.render_diagnostic(ui, &Diagnostic::from(err), &result.source);
+            }
+            if reset {
+                self.vm = None;
+                self.vm_error = None;
+            }
+        });
+
+        ui.add_space(15.0);
+    }
+
+    /// The WebAssembly module in the text format, with its binary as hex.
+    fn render_wasm(&self, ui: &mut egui::Ui, result: &ParseResult) {
+        let module = &result.wasm;
+        let binary = module.to_wasm();
+        ui.group(|ui| {
+            self.render_phase_header(ui, "WebAssembly");
+            ui.label(format!(
+                "Exports `{}`: the variables read before being assigned come in as f64 parameters and every assigned variable is returned",
+                wasm::EXPORT
+            ));
+            ui.label(format!(
+                "({}) → ({}), {} bytes",
+                module.params.join(", "),
+                module.results.join(", "),
+                binary.len()
+            ));
+            if !module.imports.is_empty() {
+                let imports: Vec<String> = module
+                    .imports
+                    .iter()
+                    .map(|(name, arity)| format!("{}/{}", name, arity))
+                    .collect();
+                ui.label(format!(
+                    "Imported from `{}`: {}",
+                    wasm::IMPORT_MODULE,
+                    imports.join(", ")
+                ));
+            }
+            ui.add_space(5.0);
+
+            let wat = module.to_wat();
+            if ui.button("📋 Copy .wat").clicked() {
+                ui.ctx().copy_text(wat.clone());
+            }
+            let lines: Vec<&str> = wat.lines().collect();
+            self.render_code_listing(
+                ui,
+                "wat_scroll",
+                &lines,
+                TAC_BG_COLOR,
+                egui::Color32::WHITE,
+            );
+
+            ui.add_space(8.0);
+            ui.collapsing("Binary (.wasm)", |ui| {
+                let hex: Vec<String> = binary
+                    .chunks(16)
+                    .enumerate()
+                    .map(|(i, bytes)| {
+                        let bytes: Vec<String> =
+                            bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+                        format!("{:04x}  {}", i * 16, bytes.join(" "))
+                    })
+                    .collect();
+                if ui.button("📋 Copy hex").clicked() {
+                    ui.ctx().copy_text(hex.join("\n"));
+                }
+                self.render_code_listing(
+                    ui,
+                    "wasm_hex_scroll",
+                    &hex,
+                    TAC_BG_COLOR,
+                    egui::Color32::WHITE,
                 );
+            });
+        });
+
+        ui.add_space(15.0);
+    }
+
+    /// The C translation unit generated from the optimized code.
+    fn render_c_source(&self, ui: &mut egui::Ui, result: &ParseResult) {
+        ui.group(|ui| {
+            self.render_phase_header(ui, "C Source");
+            ui.label(
+                "A self-contained C program for the optimized code: build it with `cc prog.c -lm` and pass the inputs as arguments",
+            );
+            ui.add_space(5.0);
+
+            if ui.button("📋 Copy").clicked() {
+                ui.ctx().copy_text(result.c_source.to_string());
+            }
+            self.render_code_listing(
+                ui,
+                "c_source_scroll",
+                &result.c_source.lines,
+                TAC_BG_COLOR,
+                egui::Color32::WHITE,
+            );
+        });
+
+        ui.add_space(15.0);
+    }
+
+    /// Starting values typed for the variables of `result`.
+    fn input_environment(&self, result: &ParseResult) -> Environment {
+        let mut env = Environment::new();
+        for (name, _) in &result.identifier_table {
+            if let Some(value) = self
+                .variable_inputs
+                .get(name)
+                .and_then(|input| input.trim().parse().ok())
+            {
+                env.set(name, value);
+            }
+        }
+        env
+    }
+
+    fn render_final_result(&mut self, ui: &mut egui::Ui, result: &ParseResult) {
+        ui.group(|ui| {
+            ui.heading("Final Evaluation");
+            ui.add_space(5.0);
+
+            // Starting values for the variables; assignments in the program
+            // override them
+            if !result.identifier_table.is_empty() {
+                egui::Grid::new("variable_inputs")
+                    .num_columns(3)
+                    .spacing([10.0, 4.0])
+                    .show(ui, |ui| {
+                        for (name, idx) in &result.identifier_table {
+                            let input = self.variable_inputs.entry(name.clone()).or_default();
+                            ui.label(egui::RichText::new(name).monospace().strong());
+                            ui.label(egui::RichText::new(format!("id{}", idx)).monospace());
+                            let edit = ui.add(
+                                egui::TextEdit::singleline(input)
+                                    .hint_text("value")
+                                    .desired_width(80.0),
+                            );
+                            // The VM starts from these values, so it restarts
+                            if edit.changed() {
+                                self.vm = None;
+                                self.vm_error = None;
+                            }
+                            ui.end_row();
+                        }
+                    });
+                ui.add_space(5.0);
+            }
+
+            let mut env = self.input_environment(result);
+
+            match result.ast.evaluate(&mut env) {
+                Ok(Some(value)) => {
+                    ui.label(
+                        egui::RichText::new(format!("Result: {}", value))
+                            .size(24.0)
+                            .color(if value.is_finite() {
+                                SUCCESS_COLOR
+                            } else {
+                                WARNING_COLOR
+                            })
+                            .strong(),
+                    );
+                    if !value.is_finite() {
+                        ui.label(
+                            egui::RichText::new("⚠ The result is not a finite number")
+                                .color(WARNING_COLOR),
+                        );
+                    }
+                    for (name, _) in &result.identifier_table {
+                        if let Some(value) = env.get(name) {
+                            ui.label(
+                                egui::RichText::new(format!("{} = {}", name, value)).monospace(),
+                            );
+                        }
+                    }
+                }
+                Ok(None) => {
+                    ui.label(
+                        egui::RichText::new("Program only defines functions - nothing to evaluate")
+                            .italics()
+                            .color(egui::Color32::GRAY),
+                    );
+                }
+                Err(err) => {
+                    self.render_diagnostic(ui, &Diagnostic::from(&err), &result.source);
+                }
             }
         });
     }
@@ -345,23 +1187,37 @@ impl ExpressionParserApp {
     fn render_legend(&self, ui: &mut egui::Ui) {
         ui.collapsing("ℹ️ Supported Operators & Features", |ui| {
             ui.label("= : Assignment");
-            ui.label("+ : Addition");
-            ui.label("- : Subtraction");
+            ui.label("+ : Addition / unary plus");
+            ui.label("- : Subtraction / negation");
             ui.label("* : Multiplication");
             ui.label("/ : Division");
             ui.label("^ : Power");
             ui.label("sqrt() : Square Root");
+            let names: Vec<&str> = builtins::BUILTINS.iter().map(|b| b.name).collect();
+            ui.label(format!("{}() : Built-in functions", names.join(", ")));
+            ui.label(", : Argument separator");
+            ui.label("f(x, y) = ... : Function definition");
+            ui.label("if(c, a, b) : a when c is non-zero, else b");
             ui.label("a-z, A-Z : Identifiers");
             ui.label("( ) : Parentheses");
+            ui.label("; or newline : Statement separator");
             ui.separator();
             ui.label(egui::RichText::new("Optimization Techniques:").strong());
             ui.label("• Constant folding (e.g., 2+3 → 5)");
             ui.label("• Algebraic simplification (e.g., x*1 → x, x+0 → x)");
-            ui.label("• Dead code elimination (e.g., x*0 → 0)");
+            ui.label("• Algebraic zeroing (e.g., x*0 → 0), in fast-math mode only");
+            ui.label("• Dead assignment elimination via liveness analysis");
+            ui.label("• Inlining of user-defined functions");
+            ui.label("• Constant and copy propagation across statements (e.g., a = 2; b = a * 3 → b = 6)");
+            ui.label("• Common subexpression elimination via a DAG (e.g., a*b + b*a)");
+            ui.label("• Strength reduction (e.g., x^2 → x*x, x*2 → x+x, (x+2)+3 → x+5)");
+            ui.label("• Optional sqrt lowering (sqrt(x) → x ^ 0.5)");
+            ui.label("• Optional SSA form with sparse conditional constant propagation");
+            ui.label("• Every pass can be switched off, reordered, repeated to a fixpoint and stepped through");
         });
     }
 
-    fn render_results(&self, ui: &mut egui::Ui, result: &ParseResult) {
+    fn render_results(&mut self, ui: &mut egui::Ui, result: &ParseResult) {
         ui.separator();
         ui.add_space(15.0);
 
@@ -369,14 +1225,31 @@ impl ExpressionParserApp {
 
         self.render_phase2_syntax(ui, &result.ast);
 
-        self.render_phase3_semantic(ui, &result.semantic_warnings);
+        self.render_phase3_semantic(ui, result);
+
+        // Code generation needs a valid program
+        if result.has_errors() {
+            return;
+        }
 
-        self.render_phase4_intermediate(ui, &result.three_address_code);
+        self.render_phase4_intermediate(ui, result);
+
+        if let Some((ssa_form, ssa_optimized)) = &result.ssa {
+            self.render_ssa(ui, ssa_form, ssa_optimized);
+        }
 
         self.render_phase5_optimization(ui, result);
 
+        self.render_phase6_codegen(ui, result);
+
+        self.render_vm(ui, result);
+
+        self.render_wasm(ui, result);
+
+        self.render_c_source(ui, result);
+
         // Final Result
-        self.render_final_result(ui, &result.optimized_ast);
+        self.render_final_result(ui, result);
     }
 }
 
@@ -389,10 +1262,14 @@ impl eframe::App for ExpressionParserApp {
                     self.render_header(ui);
                     self.render_input_section(ui);
                     self.render_examples(ui);
-                    self.render_error(ui);
-
-                    if let Some(result) = &self.parse_result {
-                        self.render_results(ui, result);
+                    self.render_options(ui);
+                    // Taken out for the frame so the phases can update the
+                    // variable inputs
+                    if let Some(result) = self.parse_result.take() {
+                        self.render_errors(ui, &result);
+                        self.render_results(ui, &result);
+                        // Unless a phase recompiled in the meantime
+                        self.parse_result.get_or_insert(result);
                     }
 
                     ui.add_space(10.0);
@@ -0,0 +1,182 @@
+use crate::builtins::BUILTINS;
+use crate::bytecode::{Chunk, Instruction};
+use crate::environment::Environment;
+use crate::error::EvalError;
+
+/// State of a stack machine running a `Chunk`, kept apart from the chunk
+/// so that it can be single-stepped.
+#[derive(Debug, Clone, Default)]
+pub struct Vm {
+    /// Offset of the next instruction.
+    pub pc: usize,
+    pub stack: Vec<f64>,
+    pub env: Environment,
+}
+
+impl Vm {
+    /// A machine at the start of a program, with variables bound in `env`.
+    pub fn new(env: Environment) -> Vm {
+        Vm {
+            env,
+            ..Vm::default()
+        }
+    }
+
+    pub fn finished(&self, chunk: &Chunk) -> bool {
+        self.pc >= chunk.code.len()
+    }
+
+    /// Executes one instruction. Fails on reading an unassigned variable,
+    /// like `Program::evaluate`.
+    pub fn step(&mut self, chunk: &Chunk) -> Result<(), EvalError> {
+        let (instruction, next) = Instruction::decode(&chunk.code, self.pc);
+        let offset = std::mem::replace(&mut self.pc, next);
+
+        match instruction {
+            Instruction::PushConst(n) => self.stack.push(chunk.constants[n as usize]),
+            Instruction::Load(n) => {
+                let name = &chunk.names[n as usize];
+                let value = self.env.get(name).ok_or_else(|| {
+                    EvalError::UnboundVariable(name.clone(), chunk.spans[&offset])
+                })?;
+                self.stack.push(value);
+            }
+            Instruction::Store(n) => {
+                let value = *self.stack.last().expect("STORE on an empty stack");
+                self.env.set(&chunk.names[n as usize], value);
+            }
+            Instruction::Add => self.binary(|a, b| a + b),
+            Instruction::Sub => self.binary(|a, b| a - b),
+            Instruction::Mul => self.binary(|a, b| a * b),
+            Instruction::Div => self.binary(|a, b| a / b),
+            Instruction::Pow => self.binary(f64::powf),
+            Instruction::Neg => {
+                let value = self.pop();
+                self.stack.push(-value);
+            }
+            Instruction::Sqrt => {
+                let value = self.pop();
+                self.stack.push(value.sqrt());
+            }
+            Instruction::CallBuiltin(n) => {
+                let builtin = &BUILTINS[n as usize];
+                let args = self.stack.split_off(self.stack.len() - builtin.arity);
+                self.stack.push((builtin.eval)(&args));
+            }
+            Instruction::Jump(target) => self.pc = target as usize,
+            Instruction::JumpIfFalse(target) => {
+                if self.pop() == 0.0 {
+                    self.pc = target as usize;
+                }
+            }
+            Instruction::Pop => {
+                self.pop();
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs to the end and returns the value of the last statement, `None`
+    /// for a program without statements.
+    pub fn run(&mut self, chunk: &Chunk) -> Result<Option<f64>, EvalError> {
+        while !self.finished(chunk) {
+            self.step(chunk)?;
+        }
+        Ok(self.stack.last().copied())
+    }
+
+    fn pop(&mut self) -> f64 {
+        self.stack.pop().expect("operand stack underflow")
+    }
+
+    fn binary(&mut self, op: impl Fn(f64, f64) -> f64) {
+        let b = self.pop();
+        let a = self.pop();
+        self.stack.push(op(a, b));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, Reported, Run, environment, parse};
+
+    fn compile(input: &str) -> Chunk {
+        Chunk::compile(&parse(input).inline_functions())
+    }
+
+    fn run(input: &str, inputs: &[(&str, f64)]) -> Run {
+        let chunk = compile(input);
+        let mut vm = Vm::new(environment(inputs));
+        let result = vm.run(&chunk).unwrap();
+        let vars = chunk
+            .names
+            .iter()
+            .filter_map(|name| Some((name.clone(), vm.env.get(name)?)))
+            .collect();
+        Run { result, vars }
+    }
+
+    #[test]
+    fn matches_the_evaluator() {
+        test_support::check_corpus(Reported::Last, run);
+    }
+
+    #[test]
+    fn stepping_matches_running() {
+        for (input, inputs) in test_support::CORPUS {
+            let chunk = compile(input);
+            let mut vm = Vm::new(environment(inputs));
+            let mut steps = 0;
+            while !vm.finished(&chunk) {
+                vm.step(&chunk).unwrap();
+                steps += 1;
+                assert!(vm.pc <= chunk.code.len(), "{}: pc {}", input, vm.pc);
+            }
+            assert!(steps > 0);
+            let expected = Vm::new(environment(inputs)).run(&chunk).unwrap();
+            assert_eq!(
+                vm.stack.last().map(|v| v.to_bits()),
+                expected.map(f64::to_bits),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn restarts_from_new_inputs() {
+        let chunk = compile("y = x * 2");
+        let mut vm = Vm::new(environment(&[("x", 1.0)]));
+        assert_eq!(vm.run(&chunk).unwrap(), Some(2.0));
+        // A finished machine stays put; new inputs need a fresh one
+        assert_eq!(vm.run(&chunk).unwrap(), Some(2.0));
+        let mut vm = Vm::new(environment(&[("x", 5.0)]));
+        vm.step(&chunk).unwrap();
+        assert_eq!(vm.stack, [5.0]);
+        assert_eq!(vm.run(&chunk).unwrap(), Some(10.0));
+        assert_eq!(vm.env.get("y"), Some(10.0));
+    }
+
+    #[test]
+    fn unbound_variable_is_an_error() {
+        let program = parse("a = 1; a + b");
+        let err = Vm::new(Environment::new())
+            .run(&Chunk::compile(&program.inline_functions()))
+            .unwrap_err();
+        let expected = program.evaluate(&mut Environment::new()).unwrap_err();
+        match (err, expected) {
+            (EvalError::UnboundVariable(name, span), EvalError::UnboundVariable(_, at)) => {
+                assert_eq!(name, "b");
+                assert_eq!(span, at);
+            }
+            (err, expected) => panic!("VM {:?}, evaluator {:?}", err, expected),
+        }
+    }
+
+    #[test]
+    fn jumps_past_64k() {
+        let input = format!("{}b = if(a, 1, 2)", "a = 1\n".repeat(10_000));
+        test_support::check(&input, &[], Reported::Last, run);
+    }
+}
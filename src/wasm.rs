@@ -0,0 +1,791 @@
+use crate::ast::{self, ASTNode, Program};
+use crate::builtins;
+use std::collections::HashSet;
+use std::fmt;
+
+/// Name of the exported function.
+pub const EXPORT: &str = "run";
+/// Module the math functions are imported from.
+pub const IMPORT_MODULE: &str = "math";
+
+/// The WebAssembly instructions the backend uses.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Instruction {
+    LocalGet(u32),
+    LocalSet(u32),
+    LocalTee(u32),
+    Const(f64),
+    Call(u32),
+    Drop,
+    /// `if (result f64)`, taking an `i32` condition.
+    If,
+    Else,
+    End,
+    /// `f64.ne`, giving an `i32`.
+    Ne,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Neg,
+    Sqrt,
+    Abs,
+    Floor,
+    Ceil,
+}
+
+impl Instruction {
+    fn mnemonic(self) -> &'static str {
+        match self {
+            Instruction::LocalGet(_) => "local.get",
+            Instruction::LocalSet(_) => "local.set",
+            Instruction::LocalTee(_) => "local.tee",
+            Instruction::Const(_) => "f64.const",
+            Instruction::Call(_) => "call",
+            Instruction::Drop => "drop",
+            Instruction::If => "if (result f64)",
+            Instruction::Else => "else",
+            Instruction::End => "end",
+            Instruction::Ne => "f64.ne",
+            Instruction::Add => "f64.add",
+            Instruction::Sub => "f64.sub",
+            Instruction::Mul => "f64.mul",
+            Instruction::Div => "f64.div",
+            Instruction::Neg => "f64.neg",
+            Instruction::Sqrt => "f64.sqrt",
+            Instruction::Abs => "f64.abs",
+            Instruction::Floor => "f64.floor",
+            Instruction::Ceil => "f64.ceil",
+        }
+    }
+
+    fn encode(self, out: &mut Vec<u8>) {
+        match self {
+            Instruction::LocalGet(n) => {
+                out.push(0x20);
+                leb128(out, n as usize);
+            }
+            Instruction::LocalSet(n) => {
+                out.push(0x21);
+                leb128(out, n as usize);
+            }
+            Instruction::LocalTee(n) => {
+                out.push(0x22);
+                leb128(out, n as usize);
+            }
+            Instruction::Const(c) => {
+                out.push(0x44);
+                out.extend(c.to_le_bytes());
+            }
+            Instruction::Call(n) => {
+                out.push(0x10);
+                leb128(out, n as usize);
+            }
+            Instruction::If => out.extend([0x04, F64]),
+            instruction => out.push(match instruction {
+                Instruction::Drop => 0x1a,
+                Instruction::Else => 0x05,
+                Instruction::End => 0x0b,
+                Instruction::Ne => 0x62,
+                Instruction::Abs => 0x99,
+                Instruction::Neg => 0x9a,
+                Instruction::Ceil => 0x9b,
+                Instruction::Floor => 0x9c,
+                Instruction::Sqrt => 0x9f,
+                Instruction::Add => 0xa0,
+                Instruction::Sub => 0xa1,
+                Instruction::Mul => 0xa2,
+                _ => 0xa3,
+            }),
+        }
+    }
+}
+
+/// Value type `f64` in the binary format.
+const F64: u8 = 0x7c;
+
+/// A module with one exported function, `run`, that executes the program.
+/// Its parameters are the variables the program reads before assigning
+/// them, and it returns the final value of every assigned variable. Math
+/// built-ins without a WebAssembly instruction, `^` among them, are
+/// imported from `math`.
+#[derive(Debug, Clone, Default)]
+pub struct WasmModule {
+    /// Imported functions by name, with their arity.
+    pub imports: Vec<(&'static str, usize)>,
+    /// Variables passed in, in parameter order.
+    pub params: Vec<String>,
+    /// Variables returned, in result order.
+    pub results: Vec<String>,
+    /// Every variable by local index: the parameters, then the others.
+    locals: Vec<String>,
+    pub body: Vec<Instruction>,
+}
+
+impl WasmModule {
+    /// Compiles a program whose user functions have been inlined.
+    pub fn compile(program: &Program) -> WasmModule {
+        let mut module = WasmModule::default();
+        let mut assigned = HashSet::new();
+        for statement in &program.statements {
+            module.find_inputs(statement, &mut assigned);
+        }
+        module.locals = module.params.clone();
+        for statement in &program.statements {
+            module.find_results(statement);
+        }
+        for name in module.results.clone() {
+            module.local(&name);
+        }
+
+        for statement in &program.statements {
+            match statement {
+                ASTNode::BinaryOp {
+                    op: '=',
+                    left,
+                    right,
+                    ..
+                } if let ASTNode::Identifier(name, _, _) = &**left => {
+                    module.expression(right);
+                    let n = module.local(name);
+                    module.body.push(Instruction::LocalSet(n));
+                }
+                statement => {
+                    module.expression(statement);
+                    module.body.push(Instruction::Drop);
+                }
+            }
+        }
+        for name in module.results.clone() {
+            let n = module.local(&name);
+            module.body.push(Instruction::LocalGet(n));
+        }
+        module
+    }
+
+    /// Adds the variables `node` reads before they are certainly assigned
+    /// to the parameters. Only an assignment on both branches of an `if`
+    /// counts after it.
+    fn find_inputs(&mut self, node: &ASTNode, assigned: &mut HashSet<String>) {
+        match node {
+            ASTNode::Identifier(name, _, _) => {
+                if !assigned.contains(name) && !self.params.contains(name) {
+                    self.params.push(name.clone());
+                }
+            }
+            ASTNode::BinaryOp {
+                op: '=',
+                left,
+                right,
+                ..
+            } => {
+                self.find_inputs(right, assigned);
+                if let ASTNode::Identifier(name, _, _) = &**left {
+                    assigned.insert(name.clone());
+                }
+            }
+            ASTNode::BinaryOp { left, right, .. } => {
+                self.find_inputs(left, assigned);
+                self.find_inputs(right, assigned);
+            }
+            ASTNode::UnaryOp { operand, .. } => self.find_inputs(operand, assigned),
+            ASTNode::Call { name, args, .. } if ast::is_conditional(name, args) => {
+                self.find_inputs(&args[0], assigned);
+                let mut then_assigned = assigned.clone();
+                self.find_inputs(&args[1], &mut then_assigned);
+                self.find_inputs(&args[2], assigned);
+                assigned.retain(|name| then_assigned.contains(name));
+            }
+            ASTNode::Call { args, .. } => {
+                for arg in args {
+                    self.find_inputs(arg, assigned);
+                }
+            }
+            ASTNode::Number(..) | ASTNode::FunctionDef { .. } | ASTNode::Error(_) => {}
+        }
+    }
+
+    /// Adds every assigned variable to the results, in program order.
+    fn find_results(&mut self, node: &ASTNode) {
+        match node {
+            ASTNode::BinaryOp {
+                op: '=',
+                left,
+                right,
+                ..
+            } => {
+                self.find_results(right);
+                if let ASTNode::Identifier(name, _, _) = &**left
+                    && !self.results.contains(name)
+                {
+                    self.results.push(name.clone());
+                }
+            }
+            ASTNode::BinaryOp { left, right, .. } => {
+                self.find_results(left);
+                self.find_results(right);
+            }
+            ASTNode::UnaryOp { operand, .. } => self.find_results(operand),
+            ASTNode::Call { args, .. } => {
+                for arg in args {
+                    self.find_results(arg);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn local(&mut self, name: &str) -> u32 {
+        let n = match self.locals.iter().position(|local| local == name) {
+            Some(n) => n,
+            None => {
+                self.locals.push(name.to_string());
+                self.locals.len() - 1
+            }
+        };
+        n as u32
+    }
+
+    fn import(&mut self, name: &'static str, arity: usize) -> u32 {
+        let n = match self.imports.iter().position(|(import, _)| *import == name) {
+            Some(n) => n,
+            None => {
+                self.imports.push((name, arity));
+                self.imports.len() - 1
+            }
+        };
+        n as u32
+    }
+
+    /// Appends the instructions leaving the value of `node` on the stack.
+    fn expression(&mut self, node: &ASTNode) {
+        match node {
+            ASTNode::Number(n, _) => self.body.push(Instruction::Const(*n)),
+            ASTNode::Identifier(name, _, _) => {
+                let n = self.local(name);
+                self.body.push(Instruction::LocalGet(n));
+            }
+            ASTNode::BinaryOp {
+                op: '=',
+                left,
+                right,
+                ..
+            } => {
+                self.expression(right);
+                if let ASTNode::Identifier(name, _, _) = &**left {
+                    let n = self.local(name);
+                    self.body.push(Instruction::LocalTee(n));
+                }
+            }
+            ASTNode::BinaryOp {
+                op, left, right, ..
+            } => {
+                self.expression(left);
+                self.expression(right);
+                let instruction = match op {
+                    '+' => Instruction::Add,
+                    '-' => Instruction::Sub,
+                    '*' => Instruction::Mul,
+                    '/' => Instruction::Div,
+                    _ => Instruction::Call(self.import("pow", 2)),
+                };
+                self.body.push(instruction);
+            }
+            ASTNode::UnaryOp { op, operand, .. } => {
+                self.expression(operand);
+                match op.as_str() {
+                    "-" => self.body.push(Instruction::Neg),
+                    "sqrt" => self.body.push(Instruction::Sqrt),
+                    _ => {}
+                }
+            }
+            // Non-zero, NaN included, picks the first branch
+            ASTNode::Call { name, args, .. } if ast::is_conditional(name, args) => {
+                self.expression(&args[0]);
+                self.body.push(Instruction::Const(0.0));
+                self.body.push(Instruction::Ne);
+                self.body.push(Instruction::If);
+                self.expression(&args[1]);
+                self.body.push(Instruction::Else);
+                self.expression(&args[2]);
+                self.body.push(Instruction::End);
+            }
+            ASTNode::Call { name, args, .. } => {
+                for arg in args {
+                    self.expression(arg);
+                }
+                let Some(builtin) = builtins::lookup(name) else {
+                    // Only inlined, valid programs are compiled
+                    self.body.push(Instruction::Const(f64::NAN));
+                    return;
+                };
+                // `f64.min`, `f64.max` and `f64.nearest` differ from Rust's
+                // on NaN and halves, so those are imported too
+                let instruction = match builtin.name {
+                    "sqrt" => Instruction::Sqrt,
+                    "abs" => Instruction::Abs,
+                    "floor" => Instruction::Floor,
+                    "ceil" => Instruction::Ceil,
+                    name => Instruction::Call(self.import(name, builtin.arity)),
+                };
+                self.body.push(instruction);
+            }
+            ASTNode::FunctionDef { .. } | ASTNode::Error(_) => {
+                self.body.push(Instruction::Const(f64::NAN));
+            }
+        }
+    }
+
+    /// The module in the text format.
+    pub fn to_wat(&self) -> String {
+        let mut out = String::from("(module\n");
+        for (name, arity) in &self.imports {
+            out.push_str(&format!(
+                "  (import \"{}\" \"{}\" (func ${} (param{}) (result f64)))\n",
+                IMPORT_MODULE,
+                name,
+                name,
+                " f64".repeat(*arity)
+            ));
+        }
+        out.push_str(&format!("  (func ${} (export \"{}\")", EXPORT, EXPORT));
+        for n in 0..self.params.len() {
+            out.push_str(&format!(" (param {} f64)", self.local_id(n)));
+        }
+        if !self.results.is_empty() {
+            out.push_str(&format!(" (result{})", " f64".repeat(self.results.len())));
+        }
+        out.push('\n');
+        for n in self.params.len()..self.locals.len() {
+            out.push_str(&format!("    (local {} f64)\n", self.local_id(n)));
+        }
+
+        let mut depth = 2;
+        for instruction in &self.body {
+            if matches!(instruction, Instruction::Else | Instruction::End) {
+                depth -= 1;
+            }
+            let operand = match instruction {
+                Instruction::LocalGet(n) | Instruction::LocalSet(n) | Instruction::LocalTee(n) => {
+                    format!(" {}", self.local_id(*n as usize))
+                }
+                Instruction::Const(c) => format!(" {}", wat_float(*c)),
+                Instruction::Call(n) => format!(" ${}", self.imports[*n as usize].0),
+                _ => String::new(),
+            };
+            out.push_str(&format!(
+                "{}{}{}\n",
+                "  ".repeat(depth),
+                instruction.mnemonic(),
+                operand
+            ));
+            if matches!(instruction, Instruction::If | Instruction::Else) {
+                depth += 1;
+            }
+        }
+        out.push_str("  )\n)\n");
+        out
+    }
+
+    /// Text-format id of local `n`: `$` and the variable's name when it is
+    /// ASCII, which the id syntax requires, and `$local.n` otherwise; no
+    /// variable name contains a `.`.
+    fn local_id(&self, n: usize) -> String {
+        let name = &self.locals[n];
+        if name.is_ascii() {
+            format!("${}", name)
+        } else {
+            format!("$local.{}", n)
+        }
+    }
+
+    /// The module in the binary format.
+    pub fn to_wasm(&self) -> Vec<u8> {
+        let mut out = b"\0asm".to_vec();
+        out.extend(1u32.to_le_bytes());
+
+        // Types: one per import arity, then the exported function's
+        let mut arities: Vec<usize> = self.imports.iter().map(|(_, arity)| *arity).collect();
+        arities.sort_unstable();
+        arities.dedup();
+        let mut types = vec![];
+        leb128(&mut types, arities.len() + 1);
+        for arity in &arities {
+            function_type(&mut types, *arity, 1);
+        }
+        function_type(&mut types, self.params.len(), self.results.len());
+        section(&mut out, 1, &types);
+
+        let mut imports = vec![];
+        leb128(&mut imports, self.imports.len());
+        for (name, arity) in &self.imports {
+            string(&mut imports, IMPORT_MODULE);
+            string(&mut imports, name);
+            imports.push(0x00);
+            leb128(&mut imports, arities.binary_search(arity).unwrap());
+        }
+        section(&mut out, 2, &imports);
+
+        let mut functions = vec![];
+        leb128(&mut functions, 1);
+        leb128(&mut functions, arities.len());
+        section(&mut out, 3, &functions);
+
+        let mut exports = vec![];
+        leb128(&mut exports, 1);
+        string(&mut exports, EXPORT);
+        exports.push(0x00);
+        leb128(&mut exports, self.imports.len());
+        section(&mut out, 7, &exports);
+
+        let mut body = vec![];
+        let locals = self.locals.len() - self.params.len();
+        if locals > 0 {
+            leb128(&mut body, 1);
+            leb128(&mut body, locals);
+            body.push(F64);
+        } else {
+            leb128(&mut body, 0);
+        }
+        for instruction in &self.body {
+            instruction.encode(&mut body);
+        }
+        body.push(0x0b);
+        let mut code = vec![];
+        leb128(&mut code, 1);
+        leb128(&mut code, body.len());
+        code.extend(body);
+        section(&mut out, 10, &code);
+
+        out
+    }
+}
+
+impl fmt::Display for WasmModule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.to_wat())
+    }
+}
+
+/// Float literal as the text format spells it.
+fn wat_float(c: f64) -> String {
+    if c.is_nan() {
+        "nan".to_string()
+    } else if c.is_infinite() {
+        if c > 0.0 { "inf" } else { "-inf" }.to_string()
+    } else {
+        format!("{:?}", c)
+    }
+}
+
+fn leb128(out: &mut Vec<u8>, mut n: usize) {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn string(out: &mut Vec<u8>, s: &str) {
+    leb128(out, s.len());
+    out.extend(s.as_bytes());
+}
+
+fn function_type(out: &mut Vec<u8>, params: usize, results: usize) {
+    out.push(0x60);
+    leb128(out, params);
+    out.extend(std::iter::repeat_n(F64, params));
+    leb128(out, results);
+    out.extend(std::iter::repeat_n(F64, results));
+}
+
+fn section(out: &mut Vec<u8>, id: u8, contents: &[u8]) {
+    out.push(id);
+    leb128(out, contents.len());
+    out.extend(contents);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, Reported, Run, parse};
+
+    /// The parts of a binary module the backend writes.
+    #[derive(Debug, Default)]
+    struct Decoded {
+        /// Parameter and result counts of each type.
+        types: Vec<(usize, usize)>,
+        /// Module, name and type index of each imported function.
+        imports: Vec<(String, String, usize)>,
+        /// Type index of each defined function.
+        functions: Vec<usize>,
+        /// Name, kind and index of each export.
+        exports: Vec<(String, u8, usize)>,
+        /// Locals declared on top of the parameters.
+        locals: usize,
+        body: Vec<Instruction>,
+    }
+
+    struct Reader<'a> {
+        bytes: &'a [u8],
+        pos: usize,
+    }
+
+    impl Reader<'_> {
+        fn byte(&mut self) -> u8 {
+            self.pos += 1;
+            self.bytes[self.pos - 1]
+        }
+
+        fn leb128(&mut self) -> usize {
+            let mut n = 0;
+            let mut shift = 0;
+            loop {
+                let byte = self.byte();
+                n |= ((byte & 0x7f) as usize) << shift;
+                if byte & 0x80 == 0 {
+                    return n;
+                }
+                shift += 7;
+            }
+        }
+
+        fn name(&mut self) -> String {
+            let len = self.leb128();
+            self.pos += len;
+            String::from_utf8(self.bytes[self.pos - len..self.pos].to_vec()).unwrap()
+        }
+
+        /// A vector of value types, all of which must be `f64`.
+        fn value_types(&mut self) -> usize {
+            let len = self.leb128();
+            for _ in 0..len {
+                assert_eq!(self.byte(), F64);
+            }
+            len
+        }
+
+        fn instruction(&mut self) -> Instruction {
+            match self.byte() {
+                0x20 => Instruction::LocalGet(self.leb128() as u32),
+                0x21 => Instruction::LocalSet(self.leb128() as u32),
+                0x22 => Instruction::LocalTee(self.leb128() as u32),
+                0x44 => {
+                    self.pos += 8;
+                    let bytes = self.bytes[self.pos - 8..self.pos].try_into().unwrap();
+                    Instruction::Const(f64::from_le_bytes(bytes))
+                }
+                0x10 => Instruction::Call(self.leb128() as u32),
+                0x04 => {
+                    assert_eq!(self.byte(), F64);
+                    Instruction::If
+                }
+                0x1a => Instruction::Drop,
+                0x05 => Instruction::Else,
+                0x0b => Instruction::End,
+                0x62 => Instruction::Ne,
+                0x99 => Instruction::Abs,
+                0x9a => Instruction::Neg,
+                0x9b => Instruction::Ceil,
+                0x9c => Instruction::Floor,
+                0x9f => Instruction::Sqrt,
+                0xa0 => Instruction::Add,
+                0xa1 => Instruction::Sub,
+                0xa2 => Instruction::Mul,
+                0xa3 => Instruction::Div,
+                opcode => panic!("unexpected opcode {:#04x}", opcode),
+            }
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> Decoded {
+        assert_eq!(&bytes[..4], b"\0asm");
+        assert_eq!(bytes[4..8], [1, 0, 0, 0]);
+        let mut decoded = Decoded::default();
+        let mut r = Reader { bytes, pos: 8 };
+        let mut last_id = 0;
+        while r.pos < bytes.len() {
+            let id = r.byte();
+            assert!(id > last_id, "section {} out of order", id);
+            last_id = id;
+            let size = r.leb128();
+            let end = r.pos + size;
+            let count = r.leb128();
+            for _ in 0..count {
+                match id {
+                    1 => {
+                        assert_eq!(r.byte(), 0x60);
+                        let params = r.value_types();
+                        let results = r.value_types();
+                        decoded.types.push((params, results));
+                    }
+                    2 => {
+                        let module = r.name();
+                        let name = r.name();
+                        assert_eq!(r.byte(), 0x00);
+                        decoded.imports.push((module, name, r.leb128()));
+                    }
+                    3 => decoded.functions.push(r.leb128()),
+                    7 => {
+                        let name = r.name();
+                        let kind = r.byte();
+                        decoded.exports.push((name, kind, r.leb128()));
+                    }
+                    10 => {
+                        let body_end = r.leb128() + r.pos;
+                        for _ in 0..r.leb128() {
+                            decoded.locals += r.leb128();
+                            assert_eq!(r.byte(), F64);
+                        }
+                        while r.pos < body_end {
+                            decoded.body.push(r.instruction());
+                        }
+                    }
+                    _ => panic!("unexpected section {}", id),
+                }
+            }
+            assert_eq!(r.pos, end, "size of section {}", id);
+        }
+        decoded
+    }
+
+    /// Index just past the `else` or `end` closing the block that starts
+    /// before `i`.
+    fn skip(body: &[Instruction], mut i: usize, to_else: bool) -> usize {
+        let mut depth = 0;
+        loop {
+            i += 1;
+            match body[i - 1] {
+                Instruction::If => depth += 1,
+                Instruction::Else if depth == 0 && to_else => return i,
+                Instruction::End if depth == 0 => return i,
+                Instruction::End => depth -= 1,
+                _ => {}
+            }
+        }
+    }
+
+    /// Calls the exported function with `args`, resolving `math` imports
+    /// to the built-ins and `pow` to `^`.
+    fn call(module: &Decoded, args: &[f64]) -> Vec<f64> {
+        let (_, kind, index) = module
+            .exports
+            .iter()
+            .find(|(name, _, _)| name == EXPORT)
+            .unwrap();
+        assert_eq!(*kind, 0x00);
+        let (params, results) = module.types[module.functions[*index - module.imports.len()]];
+        assert_eq!(params, args.len());
+
+        let mut locals = args.to_vec();
+        locals.resize(params + module.locals, 0.0);
+        let mut stack: Vec<f64> = vec![];
+        let mut i = 0;
+        while i < module.body.len() {
+            let instruction = module.body[i];
+            i += 1;
+            let unary = |stack: &mut Vec<f64>, f: fn(f64) -> f64| {
+                let a = stack.pop().unwrap();
+                stack.push(f(a));
+            };
+            let binary = |stack: &mut Vec<f64>, f: fn(f64, f64) -> f64| {
+                let b = stack.pop().unwrap();
+                let a = stack.pop().unwrap();
+                stack.push(f(a, b));
+            };
+            match instruction {
+                Instruction::LocalGet(n) => stack.push(locals[n as usize]),
+                Instruction::LocalSet(n) => locals[n as usize] = stack.pop().unwrap(),
+                Instruction::LocalTee(n) => locals[n as usize] = *stack.last().unwrap(),
+                Instruction::Const(c) => stack.push(c),
+                Instruction::Call(n) => {
+                    let (module_name, name, ty) = &module.imports[n as usize];
+                    assert_eq!(module_name, IMPORT_MODULE);
+                    let (arity, _) = module.types[*ty];
+                    let args = stack.split_off(stack.len() - arity);
+                    stack.push(match name.as_str() {
+                        "pow" => args[0].powf(args[1]),
+                        name => (builtins::lookup(name).unwrap().eval)(&args),
+                    });
+                }
+                Instruction::Drop => {
+                    stack.pop().unwrap();
+                }
+                // Conditions are `i32`s, held here as 0 or 1
+                Instruction::If => {
+                    if stack.pop().unwrap() == 0.0 {
+                        i = skip(&module.body, i, true);
+                    }
+                }
+                Instruction::Else => i = skip(&module.body, i, false),
+                Instruction::End => {}
+                Instruction::Ne => binary(&mut stack, |a, b| if a != b { 1.0 } else { 0.0 }),
+                Instruction::Add => binary(&mut stack, |a, b| a + b),
+                Instruction::Sub => binary(&mut stack, |a, b| a - b),
+                Instruction::Mul => binary(&mut stack, |a, b| a * b),
+                Instruction::Div => binary(&mut stack, |a, b| a / b),
+                Instruction::Neg => unary(&mut stack, |a| -a),
+                Instruction::Sqrt => unary(&mut stack, f64::sqrt),
+                Instruction::Abs => unary(&mut stack, f64::abs),
+                Instruction::Floor => unary(&mut stack, f64::floor),
+                Instruction::Ceil => unary(&mut stack, f64::ceil),
+            }
+        }
+        assert_eq!(stack.len(), results);
+        stack
+    }
+
+    /// Runs the decoded binary of `input` and reports its results.
+    fn run(input: &str, inputs: &[(&str, f64)]) -> Run {
+        let module = WasmModule::compile(&parse(input).inline_functions());
+        let decoded = decode(&module.to_wasm());
+        let value = |name: &String| inputs.iter().find(|(input, _)| input == name).unwrap().1;
+        let args: Vec<f64> = module.params.iter().map(value).collect();
+        let results = call(&decoded, &args);
+        Run {
+            result: None,
+            vars: module.results.into_iter().zip(results).collect(),
+        }
+    }
+
+    #[test]
+    fn sections_match_the_module() {
+        let module = WasmModule::compile(&parse("y = x ^ 2; z = max(y, x) + sqrt(y)"));
+        let decoded = decode(&module.to_wasm());
+        assert_eq!(decoded.types, [(2, 1), (1, 2)]);
+        let imports: Vec<(&str, &str, usize)> = decoded
+            .imports
+            .iter()
+            .map(|(module, name, ty)| (module.as_str(), name.as_str(), *ty))
+            .collect();
+        assert_eq!(imports, [("math", "pow", 0), ("math", "max", 0)]);
+        assert_eq!(decoded.functions, [1]);
+        assert_eq!(decoded.exports, [("run".to_string(), 0x00, 2)]);
+        assert_eq!(decoded.locals, 2);
+        assert_eq!(decoded.body[..module.body.len()], module.body[..]);
+        assert_eq!(decoded.body[module.body.len()..], [Instruction::End]);
+    }
+
+    #[test]
+    fn matches_the_evaluator() {
+        test_support::check_corpus(Reported::Nothing, run);
+    }
+
+    #[test]
+    fn non_ascii_identifiers_are_mangled() {
+        let wat = WasmModule::compile(&parse("é = x * 2; b = é + 1")).to_wat();
+        assert!(wat.is_ascii(), "{}", wat);
+        assert!(wat.contains("(local $local.1 f64)"), "{}", wat);
+        assert!(wat.contains("(param $x f64)"), "{}", wat);
+        test_support::check(
+            "é = x * 2; b = é + 1",
+            &[("x", 3.0)],
+            Reported::Nothing,
+            run,
+        );
+    }
+}
@@ -0,0 +1,430 @@
+use crate::builtins;
+use crate::ir::{self, Instr, Op, Operand};
+use crate::liveness;
+use crate::regalloc::Allocation;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// SSE registers available to the register allocator, `xmm2` to `xmm15`.
+/// `xmm0` and `xmm1` stay free for operands in flight and call arguments.
+pub const REGISTERS: usize = 14;
+const FIRST_REGISTER: usize = 2;
+
+/// One line of assembly.
+#[derive(Debug, Clone)]
+pub struct AsmLine {
+    pub text: String,
+    /// Index of the three-address instruction the line was generated from;
+    /// `None` for directives, data, and the code around the program.
+    pub source: Option<usize>,
+}
+
+/// x86-64 assembly in Intel syntax for the GNU assembler. Doubles use
+/// SSE2 and the built-ins call the C math library. `main` reads the
+/// program's inputs from its arguments, like the C backend, runs the
+/// program and prints every variable, then the result when the program
+/// ends in an expression.
+#[derive(Debug, Clone, Default)]
+pub struct Assembly {
+    pub lines: Vec<AsmLine>,
+}
+
+impl Assembly {
+    /// Lowers the code of a register allocation to a `main` function; line
+    /// sources index `allocation.code`. Variables that aren't inputs start
+    /// at zero.
+    pub fn generate(allocation: &Allocation, identifier_table: &[(String, usize)]) -> Assembly {
+        let code = &allocation.code;
+        let mut emitter = Emitter {
+            assignment: &allocation.assignment,
+            constants: vec![],
+            lines: vec![],
+            source: None,
+            params: 0,
+        };
+        for (i, instr) in code.iter().enumerate() {
+            emitter.source = Some(i);
+            emitter.instruction(instr);
+        }
+        emitter.source = None;
+
+        let mut vars: Vec<usize> = code
+            .iter()
+            .flat_map(|instr| instr.dst.into_iter().chain(instr.uses()))
+            .filter_map(|operand| match operand {
+                Operand::Var(n) => Some(n),
+                _ => None,
+            })
+            .collect();
+        vars.sort_unstable();
+        vars.dedup();
+        let name_of = |n: usize| {
+            identifier_table
+                .iter()
+                .find(|(_, idx)| *idx == n)
+                .map_or_else(|| format!("id{}", n), |(name, _)| name.clone())
+        };
+        let inputs = liveness::inputs(code);
+        let result = ir::result_temp(code)
+            .map(|n| format!("xmm{}", FIRST_REGISTER + allocation.assignment[&n]));
+
+        let mut lines = vec![];
+        let mut line = |text: String| lines.push(AsmLine { text, source: None });
+        line("    .intel_syntax noprefix".to_string());
+        line("    .section .rodata".to_string());
+        for (k, c) in emitter.constants.iter().enumerate() {
+            line(format!(".LC{}:", k));
+            line(format!("    .quad {:#018x}    # {}", c.to_bits(), c));
+        }
+        for n in &vars {
+            line(format!(".LS{}:", n));
+            line(format!("    .string \"{} = %.17g\\n\"", name_of(*n)));
+        }
+        let usage: String = inputs.iter().map(|n| format!(" {}", name_of(*n))).collect();
+        line(".LSusage:".to_string());
+        line(format!("    .string \"usage: %s{}\\n\"", usage));
+        if !inputs.is_empty() {
+            line(".LSbad:".to_string());
+            line("    .string \"not a number: %s\\n\"".to_string());
+        }
+        if result.is_some() {
+            line(".LSresult:".to_string());
+            line("    .string \"result = %.17g\\n\"".to_string());
+        }
+        line("    .data".to_string());
+        for n in &vars {
+            line(format!("id{}:", n));
+            line(format!("    .double 0    # {}", name_of(*n)));
+        }
+        if !inputs.is_empty() {
+            // argv, the argument being parsed and where `strtod` stopped
+            line(".Largv:".to_string());
+            line("    .quad 0".to_string());
+            line(".Larg:".to_string());
+            line("    .quad 0".to_string());
+            line(".Lparsed:".to_string());
+            line("    .quad 0".to_string());
+        }
+        if result.is_some() {
+            line(".Lresult:".to_string());
+            line("    .double 0".to_string());
+        }
+        line("    .text".to_string());
+        line("    .globl main".to_string());
+        line("main:".to_string());
+        line("    push rbp".to_string());
+        line("    mov rbp, rsp".to_string());
+        // Keeps the stack 16-byte aligned for calls
+        let slots = allocation.spilled.len();
+        if slots > 0 {
+            line(format!("    sub rsp, {}", (8 * slots).next_multiple_of(16)));
+        }
+        line(format!("    cmp edi, {}", inputs.len() + 1));
+        line("    jne .Lusage".to_string());
+        if !inputs.is_empty() {
+            line("    mov qword ptr [rip + .Largv], rsi".to_string());
+        }
+        for (i, n) in inputs.iter().enumerate() {
+            line("    mov rax, qword ptr [rip + .Largv]".to_string());
+            line(format!("    mov rdi, qword ptr [rax + {}]", 8 * (i + 1)));
+            line("    mov qword ptr [rip + .Larg], rdi".to_string());
+            line("    lea rsi, [rip + .Lparsed]".to_string());
+            line("    call strtod@PLT".to_string());
+            line("    mov rax, qword ptr [rip + .Lparsed]".to_string());
+            line("    cmp rax, qword ptr [rip + .Larg]".to_string());
+            line("    je .Lbad".to_string());
+            line("    cmp byte ptr [rax], 0".to_string());
+            line("    jne .Lbad".to_string());
+            line(format!("    movsd qword ptr [rip + id{}], xmm0", n));
+        }
+        lines.extend(emitter.lines);
+
+        let mut line = |text: String| lines.push(AsmLine { text, source: None });
+        line(".Lend:".to_string());
+        if let Some(register) = &result {
+            line(format!(
+                "    movsd qword ptr [rip + .Lresult], {}",
+                register
+            ));
+        }
+        for n in &vars {
+            line(format!("    lea rdi, [rip + .LS{}]", n));
+            line(format!("    movsd xmm0, qword ptr [rip + id{}]", n));
+            line("    mov eax, 1".to_string());
+            line("    call printf@PLT".to_string());
+        }
+        if result.is_some() {
+            line("    lea rdi, [rip + .LSresult]".to_string());
+            line("    movsd xmm0, qword ptr [rip + .Lresult]".to_string());
+            line("    mov eax, 1".to_string());
+            line("    call printf@PLT".to_string());
+        }
+        line("    xor eax, eax".to_string());
+        line("    leave".to_string());
+        line("    ret".to_string());
+        // Errors go to stderr with exit status 2, as in the C backend
+        line(".Lusage:".to_string());
+        line("    mov rdx, qword ptr [rsi]".to_string());
+        line("    lea rsi, [rip + .LSusage]".to_string());
+        line("    jmp .Lfail".to_string());
+        if !inputs.is_empty() {
+            line(".Lbad:".to_string());
+            line("    mov rdx, qword ptr [rip + .Larg]".to_string());
+            line("    lea rsi, [rip + .LSbad]".to_string());
+        }
+        line(".Lfail:".to_string());
+        line("    mov rax, qword ptr [rip + stderr@GOTPCREL]".to_string());
+        line("    mov rdi, qword ptr [rax]".to_string());
+        line("    xor eax, eax".to_string());
+        line("    call fprintf@PLT".to_string());
+        line("    mov eax, 2".to_string());
+        line("    leave".to_string());
+        line("    ret".to_string());
+        line("    .section .note.GNU-stack,\"\",@progbits".to_string());
+
+        Assembly { lines }
+    }
+
+    /// Lines generated from the three-address instruction at `index`.
+    pub fn lines_for(&self, index: usize) -> impl Iterator<Item = &AsmLine> {
+        self.lines
+            .iter()
+            .filter(move |line| line.source == Some(index))
+    }
+}
+
+impl fmt::Display for Assembly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in &self.lines {
+            writeln!(f, "{}", line.text)?;
+        }
+        Ok(())
+    }
+}
+
+/// Memory operand of a spill slot.
+fn stack_slot(slot: usize) -> String {
+    format!("qword ptr [rbp - {}]", 8 * (slot + 1))
+}
+
+/// C math library function for a built-in.
+pub fn libm_name(name: &str) -> String {
+    let name = builtins::lookup(name).map_or(name, |builtin| builtin.name);
+    match name {
+        "ln" => "log",
+        "log" => "log10",
+        "abs" => "fabs",
+        "min" => "fmin",
+        "max" => "fmax",
+        name => name,
+    }
+    .to_string()
+}
+
+struct Emitter<'a> {
+    /// Register of every temporary.
+    assignment: &'a BTreeMap<usize, usize>,
+    /// Constant pool, referenced as `.LCn`.
+    constants: Vec<f64>,
+    lines: Vec<AsmLine>,
+    /// Instruction being lowered.
+    source: Option<usize>,
+    /// Params passed so far to the next call.
+    params: usize,
+}
+
+impl Emitter<'_> {
+    fn emit(&mut self, text: String) {
+        self.lines.push(AsmLine {
+            text,
+            source: self.source,
+        });
+    }
+
+    /// Register or memory operand holding `operand`.
+    fn operand(&mut self, operand: Operand) -> String {
+        match operand {
+            Operand::Temp(n) => format!("xmm{}", FIRST_REGISTER + self.assignment[&n]),
+            Operand::Var(n) => format!("qword ptr [rip + id{}]", n),
+            Operand::Const(c) => {
+                let k = match self
+                    .constants
+                    .iter()
+                    .position(|k| k.to_bits() == c.to_bits())
+                {
+                    Some(k) => k,
+                    None => {
+                        self.constants.push(c);
+                        self.constants.len() - 1
+                    }
+                };
+                format!("qword ptr [rip + .LC{}]", k)
+            }
+        }
+    }
+
+    /// `movsd` between two locations, through `xmm0` when both are in
+    /// memory.
+    fn load(&mut self, dst: &str, src: &str) {
+        if dst == src {
+            return;
+        }
+        if dst.starts_with("xmm") || src.starts_with("xmm") {
+            self.emit(format!("    movsd {}, {}", dst, src));
+        } else {
+            self.emit(format!("    movsd xmm0, {}", src));
+            self.emit(format!("    movsd {}, xmm0", dst));
+        }
+    }
+
+    /// Register to compute `dst` in: its own unless it is in memory or
+    /// holds the second operand, which computing would overwrite.
+    fn target(dst: &str, b: &str) -> String {
+        if dst.starts_with("xmm") && dst != b {
+            dst.to_string()
+        } else {
+            "xmm0".to_string()
+        }
+    }
+
+    fn instruction(&mut self, instr: &Instr) {
+        let a = instr.arg1.map(|a| self.operand(a)).unwrap_or_default();
+        let b = instr.arg2.map(|b| self.operand(b)).unwrap_or_default();
+        let dst = instr.dst.map(|d| self.operand(d)).unwrap_or_default();
+
+        match &instr.op {
+            Op::Copy => self.load(&dst, &a),
+            Op::Add | Op::Sub | Op::Mul | Op::Div => {
+                let mnemonic = match instr.op {
+                    Op::Add => "addsd",
+                    Op::Sub => "subsd",
+                    Op::Mul => "mulsd",
+                    _ => "divsd",
+                };
+                let target = Self::target(&dst, &b);
+                self.load(&target, &a);
+                self.emit(format!("    {} {}, {}", mnemonic, target, b));
+                self.load(&dst, &target);
+            }
+            Op::Neg => {
+                // Flips the sign bit, which `-0.0` has alone
+                let sign = self.operand(Operand::Const(-0.0));
+                let target = Self::target(&dst, "");
+                self.load(&target, &a);
+                self.emit(format!("    movsd xmm1, {}", sign));
+                self.emit(format!("    xorpd {}, xmm1", target));
+                self.load(&dst, &target);
+            }
+            Op::Sqrt => {
+                let target = Self::target(&dst, "");
+                self.emit(format!("    sqrtsd {}, {}", target, a));
+                self.load(&dst, &target);
+            }
+            Op::Pow => {
+                self.load("xmm0", &a);
+                self.load("xmm1", &b);
+                self.emit("    call pow@PLT".to_string());
+                self.load(&dst, "xmm0");
+            }
+            // Params directly precede their call, apart from reloads, which
+            // leave the argument registers alone
+            Op::Param => {
+                self.load(&format!("xmm{}", self.params), &a);
+                self.params += 1;
+            }
+            Op::Call { name, .. } => {
+                self.params = 0;
+                self.emit(format!("    call {}@PLT", libm_name(name)));
+                self.load(&dst, "xmm0");
+            }
+            Op::Return => {
+                self.load("xmm0", &a);
+                self.emit("    jmp .Lend".to_string());
+            }
+            Op::Label(label) => self.emit(format!(".L{}:", label)),
+            Op::Goto(label) => self.emit(format!("    jmp .L{}", label)),
+            Op::IfFalse(label) => {
+                // Unordered compares set ZF too, but NaN is not zero
+                self.load("xmm0", &a);
+                self.emit("    xorpd xmm1, xmm1".to_string());
+                self.emit("    ucomisd xmm0, xmm1".to_string());
+                self.emit("    jp 1f".to_string());
+                self.emit(format!("    je .L{}", label));
+                self.emit("1:".to_string());
+            }
+            Op::Spill(slot) => self.emit(format!("    movsd {}, {}", stack_slot(*slot), a)),
+            Op::Reload(slot) => self.emit(format!("    movsd {}, {}", dst, stack_slot(*slot))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::options::{CompileOptions, FloatMode};
+    use crate::parser::ParseResult;
+    use crate::test_support::{self, Reported, Run};
+
+    fn run_with(options: &CompileOptions, input: &str, inputs: &[(&str, f64)]) -> Run {
+        let result = ParseResult::from_input(input, options);
+        assert!(!result.has_errors(), "{}", input);
+        let args = test_support::native_args(&result, inputs);
+        test_support::run_native("program.s", &result.assembly.to_string(), &args)
+    }
+
+    fn run(input: &str, inputs: &[(&str, f64)]) -> Run {
+        run_with(&CompileOptions::default(), input, inputs)
+    }
+
+    #[test]
+    fn matches_the_evaluator() {
+        if test_support::has_cc() {
+            test_support::check_corpus(Reported::TrailingExpression, run);
+        }
+    }
+
+    #[test]
+    fn results_that_reduce_to_a_name() {
+        if !test_support::has_cc() {
+            return;
+        }
+        for (input, inputs) in test_support::NAME_RESULTS {
+            test_support::check(input, inputs, Reported::TrailingExpression, run);
+        }
+    }
+
+    #[test]
+    fn spilled_temporaries_keep_their_values() {
+        let options = CompileOptions {
+            registers: 2,
+            ..CompileOptions::default()
+        };
+        let input = "a = (x + y) * (x - y) / (x * y + 1) - (x / y) * (y - 1)";
+        let result = ParseResult::from_input(input, &options);
+        assert!(!result.allocation.spilled.is_empty());
+        if !test_support::has_cc() {
+            return;
+        }
+        let run = |input: &str, inputs: &[(&str, f64)]| run_with(&options, input, inputs);
+        test_support::check(
+            input,
+            &[("x", 3.0), ("y", 0.5)],
+            Reported::TrailingExpression,
+            run,
+        );
+        test_support::check_corpus(Reported::TrailingExpression, run);
+    }
+
+    #[test]
+    fn calls_preserve_live_values() {
+        if !test_support::has_cc() {
+            return;
+        }
+        let options = CompileOptions {
+            float_mode: FloatMode::FastMath,
+            ..CompileOptions::default()
+        };
+        let run = |input: &str, inputs: &[(&str, f64)]| run_with(&options, input, inputs);
+        let input = "a = x * 2; b = sin(x) + a * max(a, x ^ 3) - hypot(a, x); a + b";
+        test_support::check(input, &[("x", 0.7)], Reported::TrailingExpression, run);
+    }
+}